@@ -0,0 +1,640 @@
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::io::{IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+use anyhow::{bail, Context};
+use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
+use path_slash::{PathBufExt, PathExt};
+use reqwest::Url;
+use tokio::fs;
+use tokio::fs::File;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::Semaphore;
+use walkdir::WalkDir;
+
+use crate::{
+    format_size, hash_file, LaunchCommand, RemoteManifest, RemoteManifestFileEntry,
+    RemoteManifestSymlink, HASH_ALGORITHM,
+};
+
+const REMOTE_MANIFEST_VERSION: usize = 1;
+
+/// Algorithm used to compress the chunks of the archives
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ArchiveCompression {
+    Zstd,
+    Brotli,
+    Lzma,
+    /// Store the chunks uncompressed
+    None,
+}
+
+impl ArchiveCompression {
+    /// Name stored in the manifest
+    pub fn name(self) -> &'static str {
+        match self {
+            ArchiveCompression::Zstd => "zstd",
+            ArchiveCompression::Brotli => "brotli",
+            ArchiveCompression::Lzma => "lzma",
+            ArchiveCompression::None => "none",
+        }
+    }
+
+    /// Highest compression level supported by the algorithm
+    pub fn max_level(self) -> u32 {
+        match self {
+            ArchiveCompression::Zstd => 22,
+            ArchiveCompression::Brotli => 11,
+            ArchiveCompression::Lzma => 9,
+            ArchiveCompression::None => 0,
+        }
+    }
+
+    /// Compression level used when none is set
+    pub fn default_level(self) -> u32 {
+        match self {
+            ArchiveCompression::Zstd => 4,
+            ArchiveCompression::Brotli => 6,
+            ArchiveCompression::Lzma => 6,
+            ArchiveCompression::None => 0,
+        }
+    }
+
+    /// Compression passed to bitar, `None` to store the chunks uncompressed.
+    /// The default level of the algorithm is used when `level` isn't set.
+    fn bitar_compression(self, level: Option<u32>) -> anyhow::Result<Option<bitar::Compression>> {
+        let level = level.unwrap_or_else(|| self.default_level());
+        if level > self.max_level() {
+            bail!(
+                "The compression level for {} should be between 0 and {}",
+                self.name(),
+                self.max_level()
+            );
+        }
+
+        Ok(match self {
+            ArchiveCompression::Zstd => Some(bitar::Compression::zstd(level)?),
+            ArchiveCompression::Brotli => Some(bitar::Compression::brotli(level)?),
+            ArchiveCompression::Lzma => Some(bitar::Compression::lzma(level)?),
+            ArchiveCompression::None => None,
+        })
+    }
+}
+
+/// How symlinks in the input directory are archived
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SymlinkMode {
+    /// Archive the file or directory the link points to as if it was in place
+    /// of the link
+    Follow,
+    /// Record the link in the manifest so unix clients recreate it. Links are
+    /// skipped when archiving on other platforms.
+    Store,
+    /// Leave links out of the archives
+    Skip,
+}
+
+/// Characters which can't be used in file names on all the platforms the
+/// client runs on. A backslash would also be treated as a path separator on
+/// Windows and map the file to the wrong location.
+const INVALID_PATH_CHARS: &[char] = &['<', '>', ':', '"', '|', '?', '*', '\\'];
+
+/// Settings used to archive a client directory
+#[derive(Clone, Debug)]
+pub struct ArchiveOptions {
+    /// Relative directory to write archive files to within the output directory
+    pub archive_prefix_dir: PathBuf,
+
+    /// File extension to use for archive files
+    pub archive_extension: String,
+
+    /// Algorithm used to compress the chunks
+    pub compression: ArchiveCompression,
+
+    /// Compression level, between 0 and the maximum level of the algorithm.
+    /// The default level of the algorithm is used when not set.
+    pub compression_level: Option<u32>,
+
+    /// Relative path to the updater program in the input directory
+    pub updater: PathBuf,
+
+    /// Base URLs of secondary copies of the archives
+    pub fallback_urls: Vec<Url>,
+
+    /// Command clients launch after updating
+    pub launch: Option<LaunchCommand>,
+
+    /// CPU architecture the client is built for
+    pub arch: Option<String>,
+
+    /// Number of the release, see [`RemoteManifest::release`]
+    pub release: u64,
+
+    /// Content groups as pairs of the group name and the directory, relative
+    /// to the input directory, of its files
+    pub groups: Vec<(String, String)>,
+
+    /// Number of files to archive at once
+    pub jobs: usize,
+
+    /// Manifest of a previous build into the same output directory. Files
+    /// which haven't changed since then keep their existing archive.
+    pub existing_manifest: Option<RemoteManifest>,
+
+    /// Glob patterns, relative to the input directory, of files which aren't
+    /// archived, e.g. `**/Thumbs.db`
+    pub exclude: Vec<String>,
+
+    /// Glob patterns of files which are archived even though they match an
+    /// exclude pattern
+    pub include: Vec<String>,
+
+    /// How symlinks are archived
+    pub symlinks: SymlinkMode,
+
+    /// Print a line for every archived file
+    pub verbose: bool,
+}
+
+impl Default for ArchiveOptions {
+    fn default() -> Self {
+        Self {
+            archive_prefix_dir: PathBuf::from("data"),
+            archive_extension: "cba".to_string(),
+            compression: ArchiveCompression::Zstd,
+            compression_level: None,
+            updater: PathBuf::from("rose-updater.exe"),
+            fallback_urls: Vec::new(),
+            launch: None,
+            arch: None,
+            release: 0,
+            groups: Vec::new(),
+            jobs: default_jobs(),
+            existing_manifest: None,
+            exclude: Vec::new(),
+            include: Vec::new(),
+            symlinks: SymlinkMode::Follow,
+            verbose: false,
+        }
+    }
+}
+
+/// Number of files archived at once when not set, one per CPU
+pub fn default_jobs() -> usize {
+    std::thread::available_parallelism().map_or(1, |jobs| jobs.get())
+}
+
+/// Convert a relative path to the `/` separated UTF-8 form stored in the
+/// manifest, rejecting paths that can't be represented on every client.
+pub(crate) fn manifest_path(path: &Path) -> anyhow::Result<String> {
+    let slash_path = match path.to_slash() {
+        Some(p) => p.to_string(),
+        None => bail!(
+            "The path {} is not valid UTF-8, rename it before archiving",
+            path.display()
+        ),
+    };
+
+    let invalid_char = slash_path
+        .chars()
+        .find(|c| INVALID_PATH_CHARS.contains(c) || c.is_control());
+    if let Some(c) = invalid_char {
+        bail!(
+            "The path {} contains the invalid character {:?}, rename it before archiving",
+            slash_path,
+            c
+        );
+    }
+
+    Ok(slash_path)
+}
+
+/// Build a matcher for glob `patterns` in which `*` doesn't match `/`, so
+/// patterns match nested directories only through `**`
+fn glob_set(patterns: &[String]) -> anyhow::Result<GlobSet> {
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns {
+        let glob = GlobBuilder::new(pattern)
+            .literal_separator(true)
+            .build()
+            .context(format!("Invalid glob pattern {}", pattern))?;
+        builder.add(glob);
+    }
+    Ok(builder.build()?)
+}
+
+/// Path of the archive of the input file at `relative_path`, relative to the
+/// output directory
+pub(crate) fn archive_relative_path(relative_path: &Path, options: &ArchiveOptions) -> PathBuf {
+    let input_extension = relative_path
+        .extension()
+        .unwrap_or_else(|| OsStr::new(""))
+        .to_string_lossy();
+
+    options
+        .archive_prefix_dir
+        .join(relative_path)
+        .with_extension(format!(
+            "{}.{}",
+            &input_extension, &options.archive_extension
+        ))
+}
+
+/// Chunker used for every archive, patches are chunked the same way so their
+/// chunks line up with the archives
+pub(crate) fn chunker_config() -> bitar::chunker::Config {
+    bitar::chunker::Config::RollSum(bitar::chunker::FilterConfig {
+        filter_bits: bitar::chunker::FilterBits::from_size(64 * 1024),
+        min_chunk_size: 16 * 1024,
+        max_chunk_size: 16 * 1024 * 1024,
+        window_size: 64,
+    })
+}
+
+/// Options passed to bitar to create an archive
+pub(crate) fn create_archive_options(
+    options: &ArchiveOptions,
+) -> anyhow::Result<bitar::api::compress::CreateArchiveOptions> {
+    Ok(bitar::api::compress::CreateArchiveOptions {
+        chunker_config: chunker_config(),
+        compression: options
+            .compression
+            .bitar_compression(options.compression_level)?,
+        ..Default::default()
+    })
+}
+
+/// Read the symlink at `path` so it can be recreated by clients, rejecting
+/// links which point outside of the `input` directory
+fn read_symlink(input: &Path, path: &Path) -> anyhow::Result<RemoteManifestSymlink> {
+    let link_path = manifest_path(path.strip_prefix(input)?)?;
+    let target = std::fs::read_link(path)?;
+    if target.is_absolute() {
+        bail!(
+            "The symlink {} points to the absolute path {}, which won't exist on clients",
+            path.display(),
+            target.display()
+        );
+    }
+
+    let symlink = RemoteManifestSymlink {
+        path: link_path,
+        target: manifest_path(&target)?,
+    };
+    if !symlink.is_contained() {
+        bail!(
+            "The symlink {} points to {}, which is outside of the input directory",
+            path.display(),
+            target.display()
+        );
+    }
+    Ok(symlink)
+}
+
+/// Unix permission bits of a file so executables stay executable when cloned
+#[cfg(unix)]
+fn file_mode(metadata: &std::fs::Metadata) -> Option<u32> {
+    use std::os::unix::fs::PermissionsExt;
+    Some(metadata.permissions().mode() & 0o777)
+}
+
+#[cfg(not(unix))]
+fn file_mode(_metadata: &std::fs::Metadata) -> Option<u32> {
+    None
+}
+
+/// Reports how many files have been archived so far.
+///
+/// Verbose output prints a line for every file. Otherwise a terminal shows a
+/// single line which is updated in place, while other outputs such as CI logs
+/// get a plain line for every tenth of the files.
+struct ArchiveProgress {
+    total: usize,
+    completed: AtomicUsize,
+    verbose: bool,
+    terminal: bool,
+}
+
+impl ArchiveProgress {
+    fn new(total: usize, verbose: bool) -> Self {
+        Self {
+            total,
+            completed: AtomicUsize::new(0),
+            verbose,
+            terminal: std::io::stdout().is_terminal(),
+        }
+    }
+
+    fn file_archived(&self, file: &ArchivedFile) {
+        let completed = self.completed.fetch_add(1, Ordering::SeqCst) + 1;
+        let counter = format!("[{}/{}]", completed, self.total);
+        if self.verbose {
+            println!(
+                "{} {} => {}",
+                counter,
+                file.input_path.display(),
+                file.output_path.display()
+            );
+        } else if self.terminal {
+            let mut stdout = std::io::stdout().lock();
+            let _ = write!(stdout, "\r\x1b[K{} {}", counter, file.entry.source_path);
+            let _ = stdout.flush();
+        } else if completed * 10 / self.total != (completed - 1) * 10 / self.total {
+            println!("{} files archived", counter);
+        }
+    }
+
+    fn finish(&self) {
+        if self.terminal && !self.verbose && self.total > 0 {
+            println!();
+        }
+    }
+}
+
+/// An archived input file
+struct ArchivedFile {
+    input_path: PathBuf,
+    output_path: PathBuf,
+    entry: RemoteManifestFileEntry,
+    archive_size: u64,
+    /// Whether the archive of a previous build was kept
+    reused: bool,
+}
+
+/// Archive the file at `input_path` into the output directory, keeping the
+/// archive of `previous` if the file hasn't changed since it was built
+async fn archive_file(
+    input: &Path,
+    output: &Path,
+    input_path: PathBuf,
+    options: &ArchiveOptions,
+    previous: Option<&RemoteManifestFileEntry>,
+) -> anyhow::Result<ArchivedFile> {
+    let input_relative_path = input_path.strip_prefix(input)?;
+    let source_path = manifest_path(input_relative_path)?;
+    let output_relative_path = &archive_relative_path(input_relative_path, options);
+    let output_path = output.join(output_relative_path);
+    let path = manifest_path(output_relative_path)?;
+    let fallback_paths = options
+        .fallback_urls
+        .iter()
+        .map(|url| Ok(url.join(&path)?.to_string()))
+        .collect::<anyhow::Result<_>>()?;
+
+    let group = options
+        .groups
+        .iter()
+        .find(|(_, dir)| {
+            source_path
+                .strip_prefix(dir.as_str())
+                .is_some_and(|rest| rest.starts_with('/'))
+        })
+        .map(|(name, _)| name.clone());
+
+    let mode = file_mode(&fs::metadata(&input_path).await?);
+
+    if let Some(previous) = previous.filter(|previous| previous.path == path) {
+        let previous_output_path = output.join(PathBuf::from_slash(&previous.path));
+        if let Ok(metadata) = fs::metadata(&previous_output_path).await {
+            if hash_file(&input_path).await? == previous.source_hash {
+                return Ok(ArchivedFile {
+                    input_path,
+                    output_path,
+                    entry: RemoteManifestFileEntry {
+                        path,
+                        source_path,
+                        source_hash: previous.source_hash.clone(),
+                        source_size: previous.source_size,
+                        fallback_paths,
+                        mode,
+                        group,
+                    },
+                    archive_size: metadata.len(),
+                    reused: true,
+                });
+            }
+        }
+    }
+
+    if let Some(output_parent) = output_path.parent() {
+        fs::create_dir_all(output_parent).await?;
+    }
+
+    let mut input_file = File::open(&input_path).await?;
+    let mut output_file = File::create(&output_path).await?;
+
+    let archive_options = create_archive_options(options)?;
+    let archive_info =
+        bitar::api::compress::create_archive(&mut input_file, &mut output_file, &archive_options)
+            .await?;
+    output_file.flush().await?;
+    let archive_size = output_file.metadata().await?.len();
+
+    Ok(ArchivedFile {
+        input_path,
+        output_path,
+        entry: RemoteManifestFileEntry {
+            path,
+            source_path,
+            source_hash: archive_info.source_hash,
+            source_size: archive_info.source_length,
+            fallback_paths,
+            mode,
+            group,
+        },
+        archive_size,
+        reused: false,
+    })
+}
+
+/// Archive every file in `input` into `output` and return the manifest
+/// describing them.
+///
+/// Up to `options.jobs` files are archived at once. Files which are unchanged
+/// since `options.existing_manifest` was built aren't archived again. The
+/// files in the manifest are sorted by their source path so the manifest is
+/// the same no matter in which order the files finished.
+pub async fn create_archives(
+    input: &Path,
+    output: &Path,
+    options: &ArchiveOptions,
+) -> anyhow::Result<RemoteManifest> {
+    // Fail before archiving anything if the level doesn't suit the algorithm
+    options
+        .compression
+        .bitar_compression(options.compression_level)?;
+
+    let updater_path = input.join(&options.updater);
+    if !updater_path.exists() {
+        bail!(
+            "The updater {} does not exist in the input directory",
+            &options.updater.display()
+        )
+    }
+
+    let exclude = glob_set(&options.exclude)?;
+    let include = glob_set(&options.include)?;
+
+    let symlink_mode = match options.symlinks {
+        SymlinkMode::Store if cfg!(not(unix)) => {
+            eprintln!("Symlinks can only be stored when archiving on unix, skipping them instead");
+            SymlinkMode::Skip
+        }
+        mode => mode,
+    };
+
+    // Collect the input files up front so we can report overall progress
+    let mut input_paths = Vec::new();
+    let mut symlinks = Vec::new();
+    let walker = WalkDir::new(input).follow_links(symlink_mode == SymlinkMode::Follow);
+    for entry in walker {
+        let entry = match entry {
+            Ok(e) => e,
+            Err(err) => {
+                let path = err.path().unwrap_or_else(|| Path::new(""));
+                eprintln!("Error accessing file {}: {}", path.display(), err);
+                continue;
+            }
+        };
+
+        // Include patterns win over exclude patterns
+        let relative_path = entry.path().strip_prefix(input)?.to_slash_lossy();
+        if exclude.is_match(&*relative_path) && !include.is_match(&*relative_path) {
+            continue;
+        }
+
+        // Followed links are reported as the file or directory they point to
+        if entry.depth() > 0 && entry.path_is_symlink() && symlink_mode != SymlinkMode::Follow {
+            if symlink_mode == SymlinkMode::Store {
+                symlinks.push(read_symlink(input, entry.path())?);
+            }
+            continue;
+        }
+
+        if !entry.file_type().is_file() {
+            continue;
+        }
+
+        input_paths.push(entry.into_path());
+    }
+
+    symlinks.sort_by(|a, b| a.path.cmp(&b.path));
+
+    if !input_paths.contains(&updater_path) {
+        bail!(
+            "The updater {} is excluded from the archives",
+            &options.updater.display()
+        );
+    }
+
+    // Hashes made with another algorithm never match so nothing can be
+    // reused, and archives compressed differently are rebuilt with the new
+    // compression
+    let compression_level = options
+        .compression_level
+        .unwrap_or_else(|| options.compression.default_level());
+    let previous: HashMap<String, RemoteManifestFileEntry> = options
+        .existing_manifest
+        .iter()
+        .filter(|manifest| {
+            manifest.hash_algorithm == HASH_ALGORITHM
+                && manifest.compression.as_deref() == Some(options.compression.name())
+                && manifest.compression_level == Some(compression_level)
+        })
+        .flat_map(|manifest| std::iter::once(&manifest.updater).chain(&manifest.files))
+        .map(|entry| (entry.source_path.clone(), entry.clone()))
+        .collect();
+    let previous = Arc::new(previous);
+    let options = Arc::new(options.clone());
+
+    let total_files = input_paths.len();
+    let jobs = Arc::new(Semaphore::new(options.jobs.max(1)));
+    let progress = Arc::new(ArchiveProgress::new(total_files, options.verbose));
+    let archived = Arc::new(Mutex::new(Vec::with_capacity(total_files)));
+
+    let mut archive_tasks = Vec::new();
+    for input_path in input_paths {
+        let input = input.to_path_buf();
+        let output = output.to_path_buf();
+        let options = options.clone();
+        let previous = previous.clone();
+        let jobs = jobs.clone();
+        let progress = progress.clone();
+        let archived = archived.clone();
+
+        archive_tasks.push(tokio::spawn(async move {
+            let _permit = jobs.acquire_owned().await?;
+            let source_path = manifest_path(input_path.strip_prefix(&input)?)?;
+            let file = archive_file(
+                &input,
+                &output,
+                input_path,
+                &options,
+                previous.get(&source_path),
+            )
+            .await?;
+            progress.file_archived(&file);
+            archived.lock().unwrap().push(file);
+            anyhow::Ok(())
+        }));
+    }
+
+    let results = futures::future::join_all(archive_tasks).await;
+    progress.finish();
+    for result in results {
+        result??;
+    }
+
+    let mut manifest = RemoteManifest {
+        version: REMOTE_MANIFEST_VERSION,
+        launch: options.launch.clone(),
+        arch: options.arch.clone(),
+        release: options.release,
+        compression: Some(options.compression.name().to_string()),
+        compression_level: Some(compression_level),
+        symlinks,
+        hash_algorithm: HASH_ALGORITHM.to_string(),
+        ..Default::default()
+    };
+
+    let mut archived = std::mem::take(&mut *archived.lock().unwrap());
+    archived.sort_by(|a, b| a.entry.source_path.cmp(&b.entry.source_path));
+
+    let reused = archived.iter().filter(|file| file.reused).count();
+    let mut total_archive_size = 0;
+    for file in archived {
+        manifest.total_source_size += file.entry.source_size;
+        total_archive_size += file.archive_size;
+        if file.input_path == updater_path {
+            manifest.updater = file.entry;
+        } else {
+            manifest.files.push(file.entry);
+        }
+    }
+    manifest.files_hash = Some(manifest.compute_files_hash());
+
+    let ratio = match total_archive_size {
+        0 => 1.0,
+        archive_size => manifest.total_source_size as f64 / archive_size as f64,
+    };
+    println!(
+        "Archived {} files ({} reused, {} rebuilt)",
+        total_files,
+        reused,
+        total_files - reused
+    );
+    println!(
+        "Source size: {}",
+        format_size(manifest.total_source_size as u64)
+    );
+    println!("Archive size: {}", format_size(total_archive_size));
+    println!("Compression ratio: {:.2}x", ratio);
+    if !manifest.symlinks.is_empty() {
+        println!("Stored {} symlinks", manifest.symlinks.len());
+    }
+
+    Ok(manifest)
+}
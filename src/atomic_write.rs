@@ -0,0 +1,47 @@
+use std::ffi::OsString;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+
+/// Path of the temporary file written next to `path`, e.g.
+/// `local_manifest.json.tmp`
+fn temp_path(path: &Path) -> PathBuf {
+    let mut file_name = path.file_name().map(OsString::from).unwrap_or_default();
+    file_name.push(".tmp");
+    path.with_file_name(file_name)
+}
+
+/// Write a file so that it either has its previous contents or the complete
+/// new contents, even if the process crashes or the power fails while saving.
+///
+/// The contents are written by `write` to a temporary file next to `path`
+/// which is synced to disk and renamed over `path`. When `write` fails the
+/// temporary file is removed and `path` is left untouched.
+pub fn write_atomic<F>(path: &Path, write: F) -> anyhow::Result<()>
+where
+    F: FnOnce(&mut BufWriter<File>) -> anyhow::Result<()>,
+{
+    let temp_path = temp_path(path);
+
+    let result = File::create(&temp_path)
+        .map_err(anyhow::Error::from)
+        .and_then(|file| {
+            let mut writer = BufWriter::new(file);
+            write(&mut writer)?;
+            writer.flush()?;
+            writer.get_ref().sync_all()?;
+            Ok(())
+        });
+    if let Err(e) = result {
+        let _ = std::fs::remove_file(&temp_path);
+        return Err(e.context(format!("Failed to write {}", temp_path.display())));
+    }
+
+    std::fs::rename(&temp_path, path).context(format!(
+        "Failed to replace {} with {}",
+        path.display(),
+        temp_path.display()
+    ))
+}
@@ -1,40 +1,44 @@
-use std::ffi::OsStr;
 use std::path::{Path, PathBuf};
 
-use anyhow::bail;
-use clap::Parser;
-use path_slash::PathExt;
-use tokio::fs;
-use tokio::fs::File;
-use walkdir::WalkDir;
+use anyhow::{bail, Context};
+use clap::{Parser, Subcommand};
+use reqwest::Url;
 
-use rose_update::{RemoteManifest, RemoteManifestFileEntry};
+use rose_update::{
+    create_archives, create_patches, default_jobs, write_atomic, ArchiveCompression,
+    ArchiveOptions, LaunchCommand, RemoteManifest, RemoteManifestFileEntry, SymlinkMode,
+    PATCH_MANIFEST_NAME,
+};
 
-const REMOTE_MANIFEST_VERSION: usize = 1;
-
-fn parse_compression_level(s: &str) -> Result<u32, String> {
-    let err = "Compression level should be a number between 0 and 22";
-
-    let i = match s.parse::<u32>() {
-        Ok(i) => i,
-        Err(_) => return Err(err.into()),
-    };
-
-    if i > 22 {
-        return Err(err.into());
+/// Parse a `NAME=DIR` content group
+fn parse_group(s: &str) -> Result<(String, String), String> {
+    match s.split_once('=') {
+        Some((name, dir)) if !name.is_empty() && !dir.is_empty() => {
+            Ok((name.to_string(), dir.trim_end_matches('/').to_string()))
+        }
+        _ => Err("Groups should be in the form NAME=DIR".into()),
     }
-
-    Ok(i)
 }
 
 #[derive(Parser, Debug)]
-#[clap(about, version, author)]
+#[clap(
+    about,
+    version,
+    author,
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true
+)]
 struct Args {
+    #[clap(subcommand)]
+    command: Option<Command>,
+
     /// Input directory
-    input: PathBuf,
+    #[clap(required_unless_present = "list-files")]
+    input: Option<PathBuf>,
 
     /// Output directory
-    output: PathBuf,
+    #[clap(required_unless_present = "list-files")]
+    output: Option<PathBuf>,
 
     /// Relative directory to write archive files to within the output directory
     ///
@@ -51,100 +55,245 @@ struct Args {
     #[clap(long, default_value = "manifest.json")]
     manifest_name: String,
 
-    /// Compression level to use (0 to 22)
-    #[clap(long, default_value="4", parse(try_from_str=parse_compression_level))]
-    compression_level: u32,
+    /// Algorithm used to compress the archives
+    #[clap(long, value_enum, default_value = "zstd")]
+    compression: ArchiveCompression,
+
+    /// Compression level to use, 0 to 22 for zstd, 0 to 11 for brotli and 0
+    /// to 9 for lzma. Defaults to 4 for zstd and 6 for brotli and lzma.
+    #[clap(long)]
+    compression_level: Option<u32>,
 
     /// Relative path to the updater program in the input directory
     #[clap(long, default_value = "rose-updater.exe")]
     updater: PathBuf,
+
+    /// Base URL, ending with `/`, of a secondary copy of the archives such as
+    /// an origin server behind a CDN. Clients fall back to it for any archive
+    /// they can't clone from the primary location. Can be repeated, fallbacks
+    /// are tried in order.
+    #[clap(long, value_name = "URL", multiple_occurrences = true)]
+    fallback_url: Vec<Url>,
+
+    /// Executable, relative to the client directory, that clients launch after
+    /// updating. Clients ignore executables they don't allow.
+    #[clap(long)]
+    launch_exe: Option<String>,
+
+    /// Arguments for the launch executable
+    #[clap(
+        long,
+        requires = "launch-exe",
+        allow_hyphen_values = true,
+        value_delimiter = ' '
+    )]
+    launch_args: Vec<String>,
+
+    /// CPU architecture the client is built for, e.g. `x86_64` or `x86`.
+    /// Updaters built for a different architecture refuse to update.
+    #[clap(long)]
+    arch: Option<String>,
+
+    /// Number of the release, which has to be higher than that of the last
+    /// build as clients refuse to update to a lower release. Defaults to one
+    /// more than the release of `--existing-manifest`, or zero.
+    #[clap(long)]
+    release: Option<u64>,
+
+    /// Put every file within a directory, relative to the input directory,
+    /// into an optional content group, e.g. `hd-textures=3DDATA/HD`. Clients
+    /// can choose which groups to install. Can be repeated.
+    #[clap(
+        long,
+        value_name = "NAME=DIR",
+        multiple_occurrences = true,
+        parse(try_from_str = parse_group)
+    )]
+    group: Vec<(String, String)>,
+
+    /// Glob pattern, relative to the input directory, of files which aren't
+    /// archived, e.g. `**/Thumbs.db`. `*` doesn't match `/`, use `**` to match
+    /// files in nested directories. Can be repeated.
+    #[clap(long, value_name = "GLOB", multiple_occurrences = true)]
+    exclude: Vec<String>,
+
+    /// Glob pattern of files which are archived even though they match an
+    /// exclude pattern. Can be repeated.
+    #[clap(long, value_name = "GLOB", multiple_occurrences = true)]
+    include: Vec<String>,
+
+    /// How symlinks in the input directory are archived. `store` records the
+    /// links in the manifest so unix clients recreate them.
+    #[clap(long, value_enum, default_value = "follow")]
+    symlinks: SymlinkMode,
+
+    /// Print a line for every archived file instead of only the progress
+    #[clap(long)]
+    verbose: bool,
+
+    /// Number of files to archive at once. Defaults to the number of CPUs.
+    #[clap(long)]
+    jobs: Option<usize>,
+
+    /// Manifest of a previous build into the output directory. Files which
+    /// haven't changed since then keep their existing archive instead of being
+    /// archived again.
+    #[clap(long, value_name = "MANIFEST")]
+    existing_manifest: Option<PathBuf>,
+
+    /// Print the files listed in an existing manifest instead of creating archives
+    ///
+    /// Each line contains the hex encoded source hash, the source size and the
+    /// source path, sorted by the source path.
+    #[clap(long, value_name = "MANIFEST", conflicts_with_all = &["input", "output"])]
+    list_files: Option<PathBuf>,
 }
 
-#[tokio::main]
-async fn main() -> anyhow::Result<()> {
-    let args = Args::parse();
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Create patches turning the files of one release into the next
+    ///
+    /// Only the data which isn't in the old file is archived, so clients with
+    /// the old release download less than with the full archives.
+    Diff(DiffArgs),
+}
 
-    let updater_path = args.input.join(&args.updater);
-    if !updater_path.exists() {
-        bail!(
-            "The updater {} does not exist in the input directory",
-            &args.updater.display()
-        )
-    }
+#[derive(clap::Args, Debug)]
+struct DiffArgs {
+    /// Directory of the release the patches apply to
+    #[clap(long)]
+    from: PathBuf,
 
-    let mut manifest = RemoteManifest {
-        version: REMOTE_MANIFEST_VERSION,
-        ..Default::default()
-    };
+    /// Directory of the release the patches produce
+    #[clap(long)]
+    to: PathBuf,
 
-    for entry in WalkDir::new(&args.input).into_iter() {
-        let entry = match entry {
-            Ok(e) => e,
-            Err(err) => {
-                let path = err.path().unwrap_or_else(|| Path::new(""));
-                eprintln!("Error accessing file {}: {}", path.display(), err);
-                continue;
-            }
-        };
-
-        if !entry.file_type().is_file() {
-            continue;
-        }
+    /// Output directory
+    output: PathBuf,
 
-        let input_path = entry.path();
-        let input_relative_path = input_path.strip_prefix(&args.input)?;
-        let input_extension = input_relative_path
-            .extension()
-            .unwrap_or_else(|| OsStr::new(""))
-            .to_string_lossy();
+    /// Relative directory to write patch archives to within the output directory
+    #[clap(long, default_value = "patches")]
+    archive_prefix_dir: PathBuf,
 
-        let output_relative_path = &args
-            .archive_prefix_dir
-            .join(input_relative_path)
-            .with_extension(format!("{}.{}", &input_extension, &args.archive_extension));
+    /// File extension to use for patch archives
+    #[clap(long, default_value = "cba")]
+    archive_extension: String,
 
-        let output_path = args.output.join(output_relative_path);
+    /// Algorithm used to compress the patch archives
+    #[clap(long, value_enum, default_value = "zstd")]
+    compression: ArchiveCompression,
 
-        println!("{} => {}", input_path.display(), output_path.display());
+    /// Compression level to use, 0 to 22 for zstd, 0 to 11 for brotli and 0
+    /// to 9 for lzma. Defaults to 4 for zstd and 6 for brotli and lzma.
+    #[clap(long)]
+    compression_level: Option<u32>,
+}
 
-        if let Some(output_parent) = output_path.parent() {
-            fs::create_dir_all(output_parent).await?;
-        }
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn read_manifest(manifest_path: &Path) -> anyhow::Result<RemoteManifest> {
+    let manifest_file = std::fs::File::open(manifest_path).context(format!(
+        "Failed to open the manifest at {}",
+        manifest_path.display()
+    ))?;
+    serde_json::from_reader(manifest_file).context(format!(
+        "Failed to parse the manifest at {}",
+        manifest_path.display()
+    ))
+}
+
+fn list_files(manifest_path: &Path) -> anyhow::Result<()> {
+    let manifest = read_manifest(manifest_path)?;
+
+    let mut entries: Vec<&RemoteManifestFileEntry> = std::iter::once(&manifest.updater)
+        .chain(&manifest.files)
+        .collect();
+    entries.sort_by(|a, b| a.source_path.cmp(&b.source_path));
+
+    for entry in entries {
+        println!(
+            "{}  {:>12}  {}",
+            to_hex(&entry.source_hash),
+            entry.source_size,
+            entry.source_path
+        );
+    }
+
+    Ok(())
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let args = Args::parse();
+
+    if let Some(Command::Diff(diff_args)) = &args.command {
+        return diff(diff_args).await;
+    }
+
+    if let Some(manifest_path) = &args.list_files {
+        return list_files(manifest_path);
+    }
 
-        let mut input_file = File::open(&input_path).await?;
-        let mut output_file = File::create(&output_path).await?;
-
-        let options = bitar::api::compress::CreateArchiveOptions {
-            chunker_config: bitar::chunker::Config::RollSum(bitar::chunker::FilterConfig {
-                filter_bits: bitar::chunker::FilterBits::from_size(64 * 1024),
-                min_chunk_size: 16 * 1024,
-                max_chunk_size: 16 * 1024 * 1024,
-                window_size: 64,
-            }),
-            compression: Some(bitar::Compression::zstd(args.compression_level)?),
-            ..Default::default()
-        };
-
-        let archive_info =
-            bitar::api::compress::create_archive(&mut input_file, &mut output_file, &options)
-                .await?;
-
-        let entry = RemoteManifestFileEntry {
-            path: output_relative_path.to_slash_lossy().to_string(),
-            source_path: input_relative_path.to_slash_lossy().to_string(),
-            source_hash: archive_info.source_hash,
-            source_size: archive_info.source_length,
-        };
-
-        if input_path == updater_path {
-            manifest.updater = entry;
-        } else {
-            manifest.files.push(entry);
+    match (&args.input, &args.output) {
+        (Some(input), Some(output)) => {
+            let manifest = create_archives(input, output, &archive_options(&args)?).await?;
+            write_atomic(&output.join(&args.manifest_name), |writer| {
+                Ok(serde_json::to_writer(writer, &manifest)?)
+            })
         }
+        _ => bail!("Both an input and output directory are required"),
     }
+}
+
+async fn diff(args: &DiffArgs) -> anyhow::Result<()> {
+    let options = ArchiveOptions {
+        archive_prefix_dir: args.archive_prefix_dir.clone(),
+        archive_extension: args.archive_extension.clone(),
+        compression: args.compression,
+        compression_level: args.compression_level,
+        ..Default::default()
+    };
+
+    std::fs::create_dir_all(&args.output)?;
+    let manifest = create_patches(&args.from, &args.to, &args.output, &options).await?;
+    println!("Created {} patches", manifest.files.len());
+    write_atomic(&args.output.join(PATCH_MANIFEST_NAME), |writer| {
+        Ok(serde_json::to_writer(writer, &manifest)?)
+    })
+}
 
-    let manifest_file = std::fs::File::create(args.output.join(&args.manifest_name))?;
-    serde_json::to_writer(manifest_file, &manifest)?;
+fn archive_options(args: &Args) -> anyhow::Result<ArchiveOptions> {
+    let existing_manifest = args
+        .existing_manifest
+        .as_deref()
+        .map(read_manifest)
+        .transpose()?;
 
-    Ok(())
+    Ok(ArchiveOptions {
+        archive_prefix_dir: args.archive_prefix_dir.clone(),
+        archive_extension: args.archive_extension.clone(),
+        compression: args.compression,
+        compression_level: args.compression_level,
+        updater: args.updater.clone(),
+        fallback_urls: args.fallback_url.clone(),
+        launch: args.launch_exe.as_ref().map(|exe| LaunchCommand {
+            exe: exe.clone(),
+            args: args.launch_args.clone(),
+        }),
+        arch: args.arch.clone(),
+        release: args.release.unwrap_or_else(|| {
+            existing_manifest
+                .as_ref()
+                .map_or(0, |manifest| manifest.release + 1)
+        }),
+        groups: args.group.clone(),
+        jobs: args.jobs.unwrap_or_else(default_jobs),
+        existing_manifest,
+        exclude: args.exclude.clone(),
+        include: args.include.clone(),
+        symlinks: args.symlinks,
+        verbose: args.verbose,
+    })
 }
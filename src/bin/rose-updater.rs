@@ -1,36 +1,52 @@
 #![windows_subsystem = "windows"]
-use std::cell::RefCell;
-use std::collections::{HashMap, HashSet};
+use std::cell::{Cell, RefCell};
 use std::env;
 use std::path::{Path, PathBuf};
 use std::process::Command;
 use std::rc::Rc;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
 
 use anyhow::{bail, Context};
 use async_trait::async_trait;
-use clap::Parser;
+use clap::{CommandFactory, FromArgMatches, Parser};
 use fltk::frame::Frame;
 use fltk::image::PngImage;
 use fltk::{enums::*, prelude::*, *};
+use path_slash::PathBufExt;
 use reqwest::Url;
-use serde::{Deserialize, Serialize};
-use tokio::fs;
-use tokio::fs::File;
-use tracing::{debug, error, info, Level};
-use tracing_subscriber::FmtSubscriber;
+use tracing::{error, info, warn, Level};
+use tracing_subscriber::filter::LevelFilter;
+use tracing_subscriber::fmt;
+use tracing_subscriber::prelude::*;
 
 #[cfg(feature = "console")]
 use console_subscriber;
 
+use rose_update::style::Theme;
 use rose_update::{
-    clone_remote, launch_button, progress_bar, LocalManifest, LocalManifestFileEntry,
-    RemoteManifest, RemoteManifestFileEntry, Updater,
+    add_toolbar, config_dir, config_file_paths, default_roots, format_size, is_running,
+    launch_button, load_news, log_dir, progress_bar, prune_log_files, read_log_tail, run_update,
+    CertPins, ChunkBudget, CloneOptions, ConfigFile, DailyCap, DnsResolver, DownloadLimit,
+    FileOwnership, IoPacer, LaunchCommand, LogBuffer, News, PauseFlag, ProgressEventWriter,
+    ProgressObserver, ProgressStage, ProgressState, RangeSupport, RateLimiter, RetryPolicy,
+    Settings, Strings, UpdateCancelled, UpdateConfig, UpdateOutcome, UpdateProgress, Updater,
+    WindowSize, CHANGELOG_CACHE_NAME, LOG_FILE_PREFIX, MAX_LOG_FILES, MMAP_THRESHOLD,
+    NEWS_CACHE_NAME,
 };
 
-const LOCAL_MANIFEST_VERSION: usize = 1;
-const UPDATER_OLD_EXT: &str = "old";
+const DAILY_USAGE_NAME: &str = "daily_download.json";
+const WINDOW_SIZE_NAME: &str = "window.json";
+const LOG_VIEW_LINES: usize = 1000;
+const LOG_VIEWER_LINES: usize = 2000;
+const NEWS_URL: &str = "https://roseonlinegame.com/launcher.html";
 
-const TEXT_FILE_EXTENSIONS: &[&str; 1] = &["xml"];
+const DEFAULT_EXE: &str = "trose.exe";
+const DEFAULT_EXE_ARGS: &[&str] = &["--init", "--server", "connect.roseonlinegame.com"];
+
+/// Executables the remote manifest is allowed to launch. This prevents the
+/// update server from being used to run arbitrary programs.
+const ALLOWED_LAUNCH_EXES: &[&str] = &["trose.exe"];
 
 #[derive(Clone, Parser, Debug)]
 #[clap(about, version, author)]
@@ -47,6 +63,17 @@ struct Args {
     #[clap(long, default_value = "manifest.json")]
     manifest_name: String,
 
+    /// URL to download the manifest from instead of the manifest name within
+    /// the remote archive URL. Archives are still resolved against `--url`.
+    #[clap(long)]
+    manifest_url: Option<String>,
+
+    /// Check that the update and news servers can be reached before updating
+    /// and log the results. The checks always run if the manifest can't be
+    /// downloaded.
+    #[clap(long)]
+    preflight: bool,
+
     /// Skip checking for updater update and only update data files
     #[clap(long)]
     skip_updater: bool,
@@ -59,430 +86,900 @@ struct Args {
     #[clap(long)]
     force_recheck_updater: bool,
 
-    /// Enable/Disable debug logs
+    /// Enable debug logs and show them live in a log window
     #[clap(long)]
     debug: bool,
 
+    /// Update without opening the updater window, printing the progress to
+    /// stdout. The game is not launched and the exit code tells whether the
+    /// update succeeded.
+    #[clap(long)]
+    headless: bool,
+
+    /// Print which files would be downloaded or updated without changing
+    /// anything. Implies `--headless`.
+    #[clap(long)]
+    dry_run: bool,
+
     /// Verify all local files
     #[clap(long)]
     verify: bool,
 
-    /// Executable to run after updating
-    #[clap(long, default_value = "trose.exe")]
-    exe: PathBuf,
+    /// Hash every local file and report whether it's OK, corrupt or missing
+    /// without downloading or changing anything. Exits with a non-zero exit
+    /// code if any file doesn't match. Implies `--headless`.
+    #[clap(long, conflicts_with_all = &["dry-run", "repair"])]
+    verify_only: bool,
+
+    /// Repair the installation. Downloads a fresh manifest bypassing any
+    /// caches, ignores the cached local manifest and interrupted downloads,
+    /// hashes every local file and downloads any file which doesn't match.
+    #[clap(long)]
+    repair: bool,
+
+    /// Update even if the server offers an older release than the one
+    /// installed, e.g. to go back to a previous release on purpose
+    #[clap(long)]
+    allow_downgrade: bool,
+
+    /// Delete files in the output directory which aren't in the manifest after
+    /// a successful update. The updater directory is left alone.
+    #[clap(long)]
+    prune: bool,
+
+    /// Optional content groups to install, e.g. `hd-textures,voices-de`.
+    /// Core files without a group are always installed. Every group is
+    /// installed when not set.
+    #[clap(long, value_name = "GROUPS", value_delimiter = ',')]
+    groups: Option<Vec<String>>,
+
+    /// Preset of performance settings for low end, typical or high end
+    /// machines. Individual settings override the preset.
+    #[clap(long, value_enum, default_value = "balanced")]
+    profile: Profile,
+
+    /// Maximum number of chunk bytes to hold in memory across all downloads.
+    /// Defaults to the value of the profile.
+    #[clap(long)]
+    max_memory: Option<usize>,
+
+    /// Maximum number of files to download at once. Defaults to the value of
+    /// the profile.
+    #[clap(long)]
+    max_concurrent_downloads: Option<usize>,
+
+    /// Number of bytes of free disk space to keep after updating. The update is
+    /// aborted before anything is downloaded if it would leave less than this.
+    #[clap(long, default_value = "0")]
+    min_free_space: u64,
 
-    /// Arguments for the executable
+    /// Rescan reused local data after it has been moved into place to catch
+    /// corruption before downloading the rest of a file
+    #[clap(long)]
+    verify_reorder: bool,
+
+    /// Number of chunks to hash at once when checking local files for data to
+    /// reuse. Defaults to the number of CPU cores.
+    #[clap(long)]
+    verify_jobs: Option<usize>,
+
+    /// Number of downloaded chunks to decompress and hash at once
+    #[clap(long, default_value = "64")]
+    remote_buffer: usize,
+
+    /// Maximum number of bytes per second to write to disk. Useful on slow
+    /// drives where updating otherwise makes the whole system unresponsive.
+    /// Defaults to the value of the profile.
+    #[clap(long, value_name = "BYTES_PER_SEC")]
+    io_pacing: Option<u64>,
+
+    /// Maximum number of bytes to download per day. Once reached the update
+    /// stops and resumes where it left off the next day.
+    #[clap(long, value_name = "BYTES")]
+    daily_cap: Option<u64>,
+
+    /// Maximum number of bytes per second to download across all files
+    #[clap(long, value_name = "BYTES_PER_SEC")]
+    max_bandwidth: Option<u64>,
+
+    /// Resolver used to look up the update servers. Cloudflare or Google can
+    /// be used on networks whose DNS servers are unreliable.
+    #[clap(long, value_enum, default_value = "system")]
+    dns: DnsResolver,
+
+    /// Seconds to reuse the addresses looked up by the Cloudflare or Google
+    /// resolver before looking them up again
+    #[clap(long, value_name = "SECONDS", default_value = "60")]
+    dns_cache_ttl: u64,
+
+    /// User agent sent with every request instead of rose-updater/<version>
+    #[clap(long)]
+    user_agent: Option<String>,
+
+    /// Accept any trusted certificate for the update and news servers instead
+    /// of only those with the pinned keys, e.g. behind a proxy which inspects
+    /// TLS traffic
+    #[clap(long)]
+    no_cert_pinning: bool,
+
+    /// Number of times to retry a failed request
+    #[clap(long, default_value = "4")]
+    retries: u32,
+
+    /// Milliseconds to wait before retrying a failed request. Manifest
+    /// requests double the delay for every further retry.
+    #[clap(long, alias = "retry-backoff-ms", default_value = "1000")]
+    retry_delay_ms: u64,
+
+    /// Maximum number of random milliseconds added to the retry delay so
+    /// clients don't all retry at the same time when a server is overloaded
+    #[clap(long, default_value = "2000")]
+    retry_jitter_ms: u64,
+
+    /// Executable to run after updating. Overrides the executable from the
+    /// remote manifest, defaults to trose.exe.
+    #[clap(long)]
+    exe: Option<PathBuf>,
+
+    /// Arguments for the executable. Overrides the arguments from the remote
+    /// manifest, defaults to `--init --server connect.roseonlinegame.com`.
     /// NOTE: This must be the last option in the command line to properly handle
-    #[clap(
-        multiple_values = true,
-        default_value = "--init --server connect.roseonlinegame.com",
-        value_delimiter = ' '
-    )]
+    #[clap(multiple_values = true, value_delimiter = ' ')]
     exe_args: Vec<String>,
 
     /// Working directory to run the executable
     #[clap(long, default_value = ".")]
     exe_dir: PathBuf,
-}
 
-async fn save_local_manifest(manifest_path: &Path, manfiest: &LocalManifest) -> anyhow::Result<()> {
-    if let Some(manifest_parent_dir) = manifest_path.parent() {
-        std::fs::create_dir_all(manifest_parent_dir)?;
-    }
+    /// Seconds to watch the executable after launching it. If it exits with an
+    /// error within this time an error is shown instead of closing the updater.
+    /// Set to 0 to close the updater immediately after launching.
+    #[clap(long, default_value = "0")]
+    launch_watch_secs: u64,
 
-    let manifest_file = std::fs::File::create(manifest_path)?;
-    serde_json::to_writer(manifest_file, &manfiest)?;
+    /// Don't launch the game if a process with the same executable name is
+    /// already running
+    #[clap(long)]
+    check_running: bool,
 
-    info!("Saved local manifest to {}", manifest_path.display());
+    /// Seconds to wait after updating before launching the game automatically.
+    /// A countdown is shown and clicking play launches the game straight away.
+    #[clap(long)]
+    launch_delay: Option<u64>,
 
-    Ok(())
-}
+    /// Paint a plain background instead of the background image to reduce the
+    /// load on low end hardware
+    #[clap(long)]
+    reduce_effects: bool,
 
-enum DownloadResult {
-    ApplicationUpdated,
-    UpdaterUpdated,
-}
+    /// Width of the updater window, at least 780. Defaults to the width the
+    /// window had when it was last closed.
+    #[clap(long)]
+    window_width: Option<i32>,
 
-async fn get_remote_manifest(
-    remote_url: &Url,
-    manifest_name: &str,
-) -> anyhow::Result<RemoteManifest> {
-    info!("Downloading remote manifest");
-    // Download our remote manifest file
-    let remote_manifest_url = remote_url.join(manifest_name)?;
-    Ok(reqwest::get(remote_manifest_url)
-        .await?
-        .json::<RemoteManifest>()
-        .await?)
-}
+    /// Height of the updater window, at least 630. Defaults to the height the
+    /// window had when it was last closed.
+    #[clap(long)]
+    window_height: Option<i32>,
 
-async fn update_updater(
-    local_updater_path: &Path,
-    updater_output_path: &Path,
-    remote_url: &Url,
-    main_updater: MainProgressUpdater,
-) -> anyhow::Result<()> {
-    // When the updater needs to be updated we change the exe name before
-    // restarting the process. This step ensures that we delete the old,
-    // outdated updater exe.
-    let local_updater_path_old = local_updater_path.with_extension(UPDATER_OLD_EXT);
-    if local_updater_path_old.exists() {
-        fs::remove_file(&local_updater_path_old)
-            .await
-            .context(format!(
-                "Failed to delete the old updater file: {}",
-                local_updater_path_old.display()
-            ))?;
-    }
-
-    info!("Updating updater");
-
-    // We cannot delete or modify a currently executing binary so we rename
-    // the currently executing updater to allow us to download the new one
-    // with the same name.
-    if local_updater_path.exists() {
-        fs::rename(&local_updater_path, &local_updater_path_old)
-            .await
-            .context(format!(
-                "Failed to rename the updater from {} to {}",
-                local_updater_path.display(),
-                local_updater_path_old.display(),
-            ))?;
-    }
-
-    clone_remote(remote_url, updater_output_path, main_updater)
-        .await
-        .context(format!("Failed to clone {}", &remote_url))?;
-
-    info!(
-        "Cloned {} to {}",
-        &remote_url,
-        updater_output_path.display()
-    );
+    /// Language of the updater window, e.g. `de`. Defaults to the language of
+    /// the system, or English if the updater doesn't ship with it.
+    #[clap(long)]
+    lang: Option<String>,
 
-    Ok(())
+    /// Scale of the whole updater window, e.g. `2` on high DPI displays.
+    /// Defaults to the scale of the display.
+    #[clap(long)]
+    ui_scale: Option<f32>,
+
+    /// Format of the progress printed to stdout. `json` prints the progress
+    /// events as newline delimited JSON, also when the window is shown.
+    #[clap(long, value_enum, default_value = "text")]
+    progress_format: ProgressFormat,
+
+    /// File descriptor to write progress events to as newline delimited JSON
+    #[cfg(unix)]
+    #[clap(long)]
+    progress_fd: Option<i32>,
+
+    /// Named pipe to write progress events to as newline delimited JSON
+    #[cfg(windows)]
+    #[clap(long)]
+    progress_pipe: Option<String>,
+
+    /// Octal permission bits to remove from updated files, e.g. `022` so files
+    /// are readable but not writable by other users
+    #[cfg(unix)]
+    #[clap(long, parse(try_from_str = parse_umask))]
+    umask: Option<u32>,
+
+    /// Owner of updated files as `UID:GID`. Either part can be left empty to
+    /// keep it, e.g. `:100` to only change the group.
+    #[cfg(unix)]
+    #[clap(long, value_name = "UID:GID", parse(try_from_str = parse_chown))]
+    chown: Option<(Option<u32>, Option<u32>)>,
+
+    /// Page with the changelog of the client, shown by the "What's new" button
+    /// above the news. The button is only shown when this is given.
+    #[clap(long)]
+    changelog_url: Option<String>,
+
+    /// URL opened when the player asks for support after a failed launch
+    #[clap(long, default_value = "https://roseonlinegame.com")]
+    support_url: String,
+
+    /// Update and launch the beta client instead of the live client. Also
+    /// used when the beta client was selected in the updater window.
+    #[clap(long)]
+    beta: bool,
+
+    /// Remote archive URL of the beta client
+    #[clap(long, default_value = "https://updates.roseonlinegame.com/beta/")]
+    beta_url: String,
+
+    /// Directory within the output directory to install the beta client to
+    #[clap(long, default_value = "beta")]
+    beta_dir: PathBuf,
 }
 
-async fn get_local_manifest(folder: &PathBuf) -> anyhow::Result<LocalManifest> {
-    info!("Getting local manifest");
-
-    // Read the manifest file if we can. Otherwise we default to an empty local
-    // manifest which we save as a new manifest later.
-    let local_manifest = if folder
-        .try_exists()
-        .context("Failed to get the local manifest")?
-    {
-        info!("Using existing manifest file: {}", folder.display());
-
-        let file = File::open(&folder).await?;
-        match serde_json::from_reader(file.into_std().await) {
-            Ok(manifest) => manifest,
-            Err(_) => {
-                info!("Failed to parse local manifest");
-                LocalManifest::default()
-            }
-        }
-    } else {
-        LocalManifest::default()
-    };
+#[cfg(unix)]
+fn parse_umask(s: &str) -> Result<u32, String> {
+    match u32::from_str_radix(s, 8) {
+        Ok(umask) if umask <= 0o777 => Ok(umask),
+        _ => Err("The umask should be an octal number between 000 and 777".into()),
+    }
+}
 
-    Ok(local_manifest)
+#[cfg(unix)]
+fn parse_chown(s: &str) -> Result<(Option<u32>, Option<u32>), String> {
+    let err = "The owner should be in the form UID:GID";
+    let (uid, gid) = s.split_once(':').ok_or(err)?;
+    let parse_id = |id: &str| match id {
+        "" => Ok(None),
+        id => id.parse().map(Some).map_err(|_| err.to_string()),
+    };
+    Ok((parse_id(uid)?, parse_id(gid)?))
 }
 
-struct VerificationResults {
-    files_to_update: Vec<(reqwest::Url, RemoteManifestFileEntry)>,
-    total_size: usize,
-    already_downloaded_size: usize,
+/// How the progress of a headless update is printed to stdout
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum ProgressFormat {
+    /// Progress lines for people, only printed when running headless
+    Text,
+    /// Progress events as newline delimited JSON for other programs
+    Json,
 }
 
-fn verify_local_files(
-    output: &Path,
-    remote_url: &Url,
-    remote_manifest: RemoteManifest,
-    local_filedata: &HashMap<PathBuf, LocalManifestFileEntry>,
-    force_verify: bool,
-) -> anyhow::Result<VerificationResults> {
-    info!("Checking local files");
-
-    let mut files_to_update = Vec::new();
-    let mut total_size = 0;
-    let mut already_downloaded_size = 0;
-    for remote_entry in remote_manifest.files {
-        let output_path = output.join(&remote_entry.source_path);
-        let needs_update = || {
-            if !output_path.exists() {
-                return true;
-            }
+/// Combinations of performance settings suited to different machines
+#[derive(clap::ValueEnum, Clone, Copy, Debug)]
+enum Profile {
+    Low,
+    Balanced,
+    Fast,
+}
 
-            if let Some(local_entry) = local_filedata.get(&PathBuf::from(&remote_entry.source_path))
-            {
-                if local_entry.hash == remote_entry.source_hash {
-                    return false;
-                }
-            }
+impl Profile {
+    fn max_memory(self) -> usize {
+        match self {
+            Profile::Low => 128 * 1024 * 1024,
+            Profile::Balanced => 512 * 1024 * 1024,
+            Profile::Fast => 2048 * 1024 * 1024,
+        }
+    }
 
-            true
-        };
+    fn max_concurrent_downloads(self) -> usize {
+        match self {
+            Profile::Low => 4,
+            Profile::Balanced => 8,
+            Profile::Fast => 16,
+        }
+    }
 
-        total_size += remote_entry.source_size;
+    fn io_pacing(self) -> Option<u64> {
+        match self {
+            Profile::Low => Some(20 * 1024 * 1024),
+            Profile::Balanced | Profile::Fast => None,
+        }
+    }
+}
 
-        if !force_verify && !needs_update() {
-            debug!(
-                "Skipping file {} as it is already present",
-                output_path.display()
-            );
-            already_downloaded_size += remote_entry.source_size;
-            continue;
+impl Args {
+    /// Point the update at the beta channel when it has been selected.
+    ///
+    /// The beta client is installed into its own directory so it never
+    /// overwrites the live client files, and that directory also holds its own
+    /// local manifest cache. The updater itself is only ever updated by the
+    /// live channel.
+    fn with_channel(mut self) -> Self {
+        if self.beta {
+            self.url = self.beta_url.clone();
+            self.output = self.output.join(&self.beta_dir);
+            self.exe_dir = self.exe_dir.join(&self.beta_dir);
+            self.skip_updater = true;
         }
+        self
+    }
 
-        let clone_url = remote_url.join(&remote_entry.path)?;
-        files_to_update.push((clone_url, remote_entry));
+    /// Permissions and owner given to every updated file
+    #[cfg(unix)]
+    fn file_ownership(&self) -> FileOwnership {
+        let (uid, gid) = self.chown.unwrap_or_default();
+        FileOwnership {
+            umask: self.umask,
+            uid,
+            gid,
+        }
     }
 
-    Ok(VerificationResults {
-        files_to_update,
-        total_size,
-        already_downloaded_size,
-    })
-}
+    #[cfg(not(unix))]
+    fn file_ownership(&self) -> FileOwnership {
+        FileOwnership::default()
+    }
 
-fn get_remote_files(
-    output: &Path,
-    files_to_update: Vec<(Url, RemoteManifestFileEntry)>,
-    main_updater: MainProgressUpdater,
-    shutdown_rx: tokio::sync::watch::Receiver<bool>,
-    tx: tokio::sync::mpsc::Sender<LocalManifestFileEntry>,
-) -> anyhow::Result<Vec<tokio::task::JoinHandle<()>>> {
-    let mut clone_tasks = Vec::new();
-
-    for entry in files_to_update {
-        let (clone_url, remote_entry) = entry;
-        let main_updater = main_updater.clone();
-        let output_path = output.join(&remote_entry.source_path);
-        let mut cloned_shutdown = shutdown_rx.clone();
-        let cloned_tx = tx.clone();
-
-        // Bitar doesn't handle text files well so when one of the text files
-        // has changed, we delete it first so bitar will just redownload the
-        // whole file.
-        if let Some(ext) = output_path.extension().and_then(|s| s.to_str()) {
-            if TEXT_FILE_EXTENSIONS.contains(&ext) {
-                if let Err(e) = std::fs::remove_file(&output_path) {
-                    error!(
-                        path =? output_path.display(),
-                        error =? e,
-                        "Failed to delete text file"
-                    )
-                }
+    /// Options used to download every file, pausing whenever `pause` is set
+    fn clone_options(&self, pause: PauseFlag) -> anyhow::Result<CloneOptions> {
+        let mut client_builder = self
+            .dns
+            .client_builder(Duration::from_secs(self.dns_cache_ttl));
+        if let Some(user_agent) = &self.user_agent {
+            client_builder = client_builder.user_agent(user_agent);
+        }
+        if !self.no_cert_pinning {
+            let pins = CertPins::builtin()?;
+            if !pins.is_empty() {
+                client_builder =
+                    client_builder.use_preconfigured_tls(pins.tls_config(default_roots()));
             }
         }
 
-        clone_tasks.push(tokio::spawn(async move {
-            info!("Downloading {}", &clone_url);
-            tokio::select! {
-                res = clone_remote(
-                    &clone_url,
-                    &output_path,
-                    main_updater) => if res.is_ok() {
-                        info!("Cloned {} to {}", &clone_url, output_path.display());
-                        cloned_tx.send(LocalManifestFileEntry {
-                            path: remote_entry.source_path.clone(),
-                            hash: remote_entry.source_hash.clone(),
-                            size: remote_entry.source_size,
-                        }).await.expect("Failed to send clone message");
-                    } else {
-                        error!("Failed to clone {}", &clone_url);
-                    },
-                _ = cloned_shutdown.changed() => {
-                    info!("Stopped cloning {}", &clone_url);
-                }
-            }
-        }));
+        Ok(CloneOptions {
+            client: client_builder.build()?,
+            chunk_budget: ChunkBudget::new(
+                self.max_memory.unwrap_or_else(|| self.profile.max_memory()),
+            ),
+            download_limit: DownloadLimit::new(
+                self.max_concurrent_downloads
+                    .unwrap_or_else(|| self.profile.max_concurrent_downloads()),
+            ),
+            verify_reorder: self.verify_reorder,
+            io_pacer: self
+                .io_pacing
+                .or_else(|| self.profile.io_pacing())
+                .map(IoPacer::new),
+            daily_cap: self.daily_cap.map(|cap| {
+                DailyCap::load(&self.output.join("updater").join(DAILY_USAGE_NAME), cap)
+            }),
+            rate_limiter: self.max_bandwidth.map(RateLimiter::new),
+            retry: RetryPolicy {
+                retries: self.retries,
+                delay: Duration::from_millis(self.retry_delay_ms),
+                jitter: Duration::from_millis(self.retry_jitter_ms),
+            },
+            range_support: RangeSupport::default(),
+            pause,
+            verify_jobs: self.verify_jobs.unwrap_or_else(|| {
+                std::thread::available_parallelism().map_or(1, |jobs| jobs.get())
+            }),
+            remote_buffer: self.remote_buffer,
+            mmap_threshold: MMAP_THRESHOLD,
+        })
     }
 
-    Ok(clone_tasks)
+    /// Strings of the language chosen on the command line or the system's
+    fn strings(&self) -> Strings {
+        match &self.lang {
+            Some(lang) => Strings::for_locale(lang),
+            None => Strings::system(),
+        }
+    }
+
+    /// Settings of the update given on the command line
+    fn update_config(&self, pause: PauseFlag) -> anyhow::Result<UpdateConfig> {
+        let url = Url::parse(&self.url).context(format!("Failed to parse the url {}", self.url))?;
+        let manifest_url = match &self.manifest_url {
+            Some(manifest_url) => Url::parse(manifest_url)
+                .context(format!("Failed to parse the manifest url {}", manifest_url))?,
+            None => url.join(&self.manifest_name)?,
+        };
+
+        Ok(UpdateConfig {
+            news_url: Some(Url::parse(NEWS_URL)?),
+            preflight: self.preflight,
+            skip_updater: self.skip_updater,
+            force_recheck: self.force_recheck,
+            force_recheck_updater: self.force_recheck_updater,
+            verify: self.verify,
+            repair: self.repair,
+            allow_downgrade: self.allow_downgrade,
+            dry_run: self.dry_run,
+            verify_only: self.verify_only,
+            prune: self.prune,
+            prune_protected_dirs: if self.beta {
+                Vec::new()
+            } else {
+                vec![self.beta_dir.clone()]
+            },
+            groups: self.groups.clone(),
+            min_free_space: self.min_free_space,
+            support_url: self.support_url.clone(),
+            ownership: self.file_ownership(),
+            restart_args: env::args_os().skip(1).collect(),
+            strings: self.strings(),
+            ..UpdateConfig::new(
+                url,
+                manifest_url,
+                self.output.clone(),
+                self.clone_options(pause)?,
+            )
+        })
+    }
 }
 
-async fn process(
-    args: &Args,
-    main_updater: MainProgressUpdater,
-    mut shutdown_rx: tokio::sync::watch::Receiver<bool>,
-) -> anyhow::Result<DownloadResult> {
-    let remote_url =
-        Url::parse(&args.url).context(format!("Failed to parse the url {}", args.url))?;
-
-    let remote_manifest = tokio::select! {
-        res = get_remote_manifest(&remote_url, &args.manifest_name) => res?,
-        _ = shutdown_rx.changed() => bail!("Download cancelled")
-    };
+/// Work out the command used to launch the game.
+///
+/// The executable and arguments given on the command line each take
+/// precedence over the remote manifest. The remote launch command is ignored
+/// entirely if its executable isn't in [`ALLOWED_LAUNCH_EXES`].
+fn resolve_launch_command(
+    exe: Option<&Path>,
+    exe_args: &[String],
+    remote: Option<&LaunchCommand>,
+) -> (PathBuf, Vec<String>) {
+    let remote = remote.filter(|launch| {
+        let allowed = ALLOWED_LAUNCH_EXES.contains(&launch.exe.as_str());
+        if !allowed {
+            warn!(
+                exe =? launch.exe,
+                "Ignoring launch command from the remote manifest as the executable is not allowed"
+            );
+        }
+        allowed
+    });
 
-    // The updater can use different "profiles" to use the same updater for different clients
-    let local_manifest_path = args
-        .output
-        .join("updater")
-        .join(remote_url.host_str().unwrap_or("default"))
-        .join("local_manifest.json");
+    let exe = match (exe, remote) {
+        (Some(exe), _) => exe.to_path_buf(),
+        (None, Some(launch)) => PathBuf::from_slash(&launch.exe),
+        (None, None) => PathBuf::from(DEFAULT_EXE),
+    };
 
-    let local_manifest = tokio::select! {
-        res = get_local_manifest(&local_manifest_path) => res?,
-        _ = shutdown_rx.changed() => bail!("Download cancelled")
+    let exe_args = match (exe_args.is_empty(), remote) {
+        (false, _) => exe_args.to_vec(),
+        (true, Some(launch)) => launch.args.clone(),
+        (true, None) => DEFAULT_EXE_ARGS.iter().map(|arg| arg.to_string()).collect(),
     };
 
-    // First, we check if the updater itself needs an update. If it does then we
-    // will only update the updater then start the process again to update the
-    // rest of the files.
-    let updater_output_path = args.output.join(&remote_manifest.updater.source_path);
-    let updater_needs_update = remote_manifest.updater.source_hash != local_manifest.updater.hash;
+    (exe, exe_args)
+}
 
-    if !args.skip_updater && (args.force_recheck_updater || updater_needs_update) {
-        let local_updater_path = args.output.join(&remote_manifest.updater.source_path);
+#[derive(Debug)]
+enum Message {
+    Progress(u64, u64),
+    Launch(Option<LaunchCommand>, String),
+    LaunchCountdown(u64),
+    AutoLaunch,
+    LaunchFailed(Option<i32>),
+    LaunchError(String),
+    LogUpdated,
+    /// HTML of the news page to show
+    News(String),
+    RetryNews,
+    Changelog(News),
+    ShowChangelog,
+    ShowNews,
+    /// The theme was switched in the window
+    Theme(Theme),
+    Status(String),
+    /// Source path of the file being downloaded, empty once no file is
+    CurrentFile(String),
+    Stage(ProgressStage),
+    Cancelled,
+    RestartUpdate,
+    Shutdown,
+    Error(String),
+}
 
-        main_updater
-            .set_max_progress(remote_manifest.updater.source_size)
-            .await;
+/// Shows the progress in the updater window. Every message wakes up the
+/// event loop so the window is redrawn straight away.
+struct WindowProgress(app::Sender<Message>);
 
-        let remote = remote_url.join(&remote_manifest.updater.path)?;
+impl ProgressObserver for WindowProgress {
+    fn on_stage(&self, stage: ProgressStage) {
+        self.0.send(Message::Stage(stage));
+    }
 
-        tokio::select! {
-            res = update_updater(&local_updater_path, &updater_output_path, &remote, main_updater) => res?,
-            _ = shutdown_rx.changed() => bail!("Download cancelled")
-        }
-
-        // We update the local manifest with only the data for the updater, the
-        // rest of the data should be updated the next time we run the updater.
-        let new_local_manifest = LocalManifest {
-            version: LOCAL_MANIFEST_VERSION,
-            updater: LocalManifestFileEntry {
-                path: remote_manifest.updater.source_path.clone(),
-                hash: remote_manifest.updater.source_hash.clone(),
-                size: remote_manifest.updater.source_size,
-            },
-            ..local_manifest
-        };
+    fn on_progress(&self, current: u64, total: u64) {
+        self.0.send(Message::Progress(current, total));
+    }
 
-        save_local_manifest(&local_manifest_path, &new_local_manifest).await?;
+    fn on_status(&self, status: &str) {
+        self.0.send(Message::Status(status.to_string()));
+    }
 
-        info!("Restarting updater");
-        Command::new(env::current_exe()?)
-            .args(
-                env::args()
-                    .skip(1)
-                    // Prevent infinite loop of update rechecks by removing the forced updater check
-                    .filter(|arg| !arg.contains("force-recheck-updater")),
-            )
-            .spawn()?;
-
-        return Ok(DownloadResult::UpdaterUpdated);
-    }
-
-    // Create a lookup table for our local cache data so we can compare to remote manifest
-    let mut current_local_filedata: HashMap<PathBuf, LocalManifestFileEntry> = HashMap::new();
-    for entry in &local_manifest.files {
-        current_local_filedata.insert(PathBuf::from(&entry.path), entry.clone());
-    }
-
-    let VerificationResults {
-        files_to_update,
-        total_size,
-        already_downloaded_size,
-    } = verify_local_files(
-        &args.output,
-        &remote_url,
-        remote_manifest,
-        &current_local_filedata,
-        args.verify,
-    )?;
-
-    main_updater.set_max_progress(total_size).await;
-    main_updater
-        .increment_progress(already_downloaded_size)
-        .await;
-
-    let (tx, mut rx) = tokio::sync::mpsc::channel::<LocalManifestFileEntry>(64);
-
-    let work = tokio::spawn(async move {
-        let mut hash_new_local_manifest = HashSet::new();
-        let mut new_local_manifest = LocalManifest {
-            version: LOCAL_MANIFEST_VERSION,
-            updater: local_manifest.updater,
-            ..Default::default()
+    fn on_file(&self, path: &str) {
+        self.0.send(Message::CurrentFile(path.to_string()));
+    }
+}
+
+/// Prints the progress to stdout when updating headless, one line for every
+/// percent of progress
+#[derive(Default)]
+struct ConsoleProgress {
+    percent: Mutex<Option<u64>>,
+}
+
+impl ProgressObserver for ConsoleProgress {
+    fn on_stage(&self, _stage: ProgressStage) {}
+
+    fn on_progress(&self, current: u64, total: u64) {
+        let percent = match total {
+            0 => 100,
+            total => current.min(total) * 100 / total,
         };
+        let mut last_percent = self.percent.lock().unwrap();
+        if *last_percent != Some(percent) {
+            *last_percent = Some(percent);
+            println!(
+                "Progress: {}% ({} / {})",
+                percent,
+                format_size(current),
+                format_size(total)
+            );
+        }
+    }
+
+    fn on_status(&self, status: &str) {
+        println!("{}", status);
+    }
+}
+
+#[derive(Clone)]
+struct MainProgressUpdater {
+    state: ProgressState,
+    events: Option<ProgressEventWriter>,
+}
 
-        while let Some(manifest) = rx.recv().await {
-            hash_new_local_manifest.insert(PathBuf::from(&manifest.path));
-            new_local_manifest.files.push(manifest);
+impl UpdateProgress for MainProgressUpdater {
+    /// Show a short status line below the progress bar
+    fn set_status(&self, status: String) {
+        if let Some(events) = &self.events {
+            events.set_text(status.clone());
         }
+        self.state.set_status(status);
+    }
 
-        (hash_new_local_manifest, new_local_manifest)
-    });
+    fn set_stage(&self, stage: ProgressStage) {
+        if let Some(events) = &self.events {
+            events.set_stage(stage);
+        }
+        self.state.set_stage(stage);
+    }
 
-    let clone_tasks =
-        get_remote_files(&args.output, files_to_update, main_updater, shutdown_rx, tx)?;
+    fn set_current_file(&self, path: &str) {
+        self.state.set_current_file(path);
+    }
+}
 
-    futures::future::join_all(clone_tasks).await;
-    let (hash_new_local_manifest, mut new_local_manifest) = work.await?;
+#[async_trait]
+impl Updater for MainProgressUpdater {
+    async fn set_max_progress(&self, total: usize) {
+        self.state.set_max_progress(total).await;
+        if let Some(events) = &self.events {
+            events.set_max_progress(total).await;
+        }
+    }
 
-    for (path, local_entry) in current_local_filedata {
-        if !hash_new_local_manifest.contains(&path) {
-            new_local_manifest.files.push(local_entry);
+    async fn increment_progress(&self, amount: usize) {
+        self.state.increment_progress(amount).await;
+        if let Some(events) = &self.events {
+            events.increment_progress(amount).await;
         }
     }
+}
 
-    save_local_manifest(&local_manifest_path, &new_local_manifest).await?;
+/// The news with a button opening the changelog, which is marked when the
+/// changelog changed since the player last opened it
+fn news_with_changelog_button(
+    news_html: &str,
+    changelog_is_new: bool,
+    strings: &Strings,
+) -> String {
+    let badge = if changelog_is_new {
+        format!(
+            " <span style=\"background: #e0413a; color: #fff; border-radius: 8px; \
+             padding: 0 6px\">{}</span>",
+            strings.get("whats_new_badge")
+        )
+    } else {
+        String::new()
+    };
+    add_toolbar(
+        news_html,
+        &format!(
+            "<button onclick=\"show_changelog()\">{}{}</button>",
+            strings.get("whats_new"),
+            badge
+        ),
+    )
+}
 
-    Ok(DownloadResult::ApplicationUpdated)
+/// Show the end of the newest log file in a window of its own. The file is
+/// read again every time so it has everything up to the moment it's opened.
+fn show_log_viewer(strings: &Strings) {
+    let (title, contents) = match read_log_tail(&log_dir(), LOG_VIEWER_LINES) {
+        Ok(Some((path, contents))) => (path.display().to_string(), contents),
+        Err(e) => {
+            warn!(error =? e, "Failed to read the log file");
+            (strings.get("logs").to_string(), e.to_string())
+        }
+        Ok(None) => (
+            strings.get("logs").to_string(),
+            strings.get("no_logs").to_string(),
+        ),
+    };
+
+    let mut viewer_win = window::Window::default()
+        .with_size(780, 400)
+        .with_label(&title);
+    let mut viewer_text = text::TextBuffer::default();
+    viewer_text.set_text(&contents);
+    let mut viewer_display = text::TextDisplay::new(0, 0, 780, 366, "");
+    viewer_display.set_buffer(viewer_text.clone());
+    viewer_display.set_text_size(12);
+    viewer_display.scroll(viewer_display.count_lines(0, viewer_text.length(), true), 0);
+
+    // Copies everything so it can be pasted into a support chat
+    let mut copy_button = button::Button::new(688, 373, 80, 20, strings.get("copy_all"));
+    copy_button.set_label_size(12);
+    copy_button.set_callback(move |_| app::copy(&contents));
+
+    viewer_win.resizable(&viewer_display);
+    viewer_win.end();
+    viewer_win.show();
 }
 
-#[derive(Debug)]
-enum MainProgressUpdaterEvent {
-    SetMaxProgress(usize),
-    IncrementProgress(usize),
+/// Save the settings chosen in the window for the next launch
+fn save_settings(settings_path: Option<&Path>, settings: &Settings) {
+    info!("Saving settings {:?}", settings);
+    match settings_path {
+        Some(settings_path) => {
+            if let Err(e) = settings.save(settings_path) {
+                warn!(error =? e, "Failed to save the settings");
+            }
+        }
+        None => warn!("Not saving the settings as there is no config directory"),
+    }
 }
 
-#[derive(Debug)]
-enum Message {
-    MainProgressUpdate(MainProgressUpdaterEvent),
-    Launch,
-    Shutdown,
-    Error(String),
+/// Create the writer for progress events requested on the command line
+fn progress_event_writer(args: &Args) -> anyhow::Result<Option<ProgressEventWriter>> {
+    #[cfg(unix)]
+    if let Some(fd) = args.progress_fd {
+        // SAFETY: The file descriptor is handed to us by the parent process
+        // and nothing else in the updater uses it.
+        return Ok(Some(unsafe { ProgressEventWriter::from_fd(fd) }));
+    }
+
+    #[cfg(windows)]
+    if let Some(pipe) = &args.progress_pipe {
+        let writer = ProgressEventWriter::from_pipe(pipe)
+            .context(format!("Failed to open the progress pipe {}", pipe))?;
+        return Ok(Some(writer));
+    }
+
+    if args.progress_format == ProgressFormat::Json {
+        return Ok(Some(ProgressEventWriter::new(std::io::stdout())));
+    }
+
+    Ok(None)
 }
 
-#[derive(Clone)]
-struct MainProgressUpdater {
-    sender: app::Sender<Message>,
+/// Attach to the console of the process which started the updater.
+///
+/// The updater is built for the Windows GUI subsystem so it doesn't open a
+/// console window, which also leaves it without a stdout to print to.
+#[cfg(windows)]
+fn attach_parent_console() {
+    use windows::Win32::System::Console::{AttachConsole, ATTACH_PARENT_PROCESS};
+
+    // SAFETY: Attaching fails harmlessly when there's no parent console or
+    // the process already has one.
+    unsafe {
+        let _ = AttachConsole(ATTACH_PARENT_PROCESS);
+    }
 }
 
-#[async_trait]
-impl Updater for MainProgressUpdater {
-    async fn set_max_progress(&self, total: usize) {
-        self.sender.send(Message::MainProgressUpdate(
-            MainProgressUpdaterEvent::SetMaxProgress(total),
-        ));
+/// Run the update on `rt`, reporting the result to the window through `tx`
+fn spawn_update(
+    rt: &tokio::runtime::Runtime,
+    config: UpdateConfig,
+    main_updater: MainProgressUpdater,
+    shutdown_rx: tokio::sync::watch::Receiver<bool>,
+    tx: app::Sender<Message>,
+) -> tokio::task::JoinHandle<()> {
+    rt.spawn(async move {
+        let result = run_update(&config, main_updater, shutdown_rx).await;
+        match result {
+            Ok(download_result) => {
+                info!("Download task completed");
+
+                match download_result {
+                    UpdateOutcome::ApplicationUpdated(launch, summary) => {
+                        info!("Application updated");
+                        tx.send(Message::Launch(
+                            launch,
+                            summary.describe_in(&config.strings),
+                        ));
+                    }
+                    UpdateOutcome::UpdaterUpdated(_) => {
+                        // The updater itself was updated, we should exit because a new
+                        // process was started with the new updater to update the
+                        // application.
+                        info!("Updater updated");
+                        tx.send(Message::Shutdown);
+                    }
+                    // Dry runs and verifying always run headless so there's
+                    // nothing to show
+                    UpdateOutcome::DryRun | UpdateOutcome::Verified(_) => {
+                        tx.send(Message::Shutdown)
+                    }
+                }
+            }
+            Err(e) if e.is::<UpdateCancelled>() => {
+                info!("Download task cancelled");
+                tx.send(Message::Cancelled);
+            }
+            Err(e) => {
+                let error_string = e.to_string();
+                error!("Download task failed, error {}", &error_string);
+                tx.send(Message::Error(error_string));
+            }
+        }
+    })
+}
+
+/// Update without the updater window, e.g. on servers or in CI.
+///
+/// Errors are returned so the updater exits with a non-zero exit code. When
+/// the updater updated itself the exit code of the restarted updater is used.
+fn run_headless(args: Args) -> anyhow::Result<()> {
+    // JSON progress is printed instead of the progress lines so stdout only
+    // has JSON on it
+    let state = match args.progress_format {
+        ProgressFormat::Text => ProgressState::with_observer(ConsoleProgress::default()),
+        ProgressFormat::Json => ProgressState::new(),
+    };
+    let events = progress_event_writer(&args)?;
+    let main_updater = MainProgressUpdater {
+        state,
+        events: events.clone(),
+    };
+    let config = args.update_config(PauseFlag::default())?;
+
+    // Nothing cancels a headless update other than Ctrl-C, the sender is only
+    // kept so the update isn't cancelled by it being dropped
+    let (_shutdown_tx, shutdown_rx) = tokio::sync::watch::channel(false);
+
+    let rt = tokio::runtime::Runtime::new()?;
+    let result = rt.block_on(async {
+        tokio::select! {
+            res = run_update(&config, main_updater, shutdown_rx) => res,
+            _ = tokio::signal::ctrl_c() => Err(UpdateCancelled.into()),
+        }
+    });
+    if let Some(events) = &events {
+        events.flush();
     }
 
-    async fn increment_progress(&self, amount: usize) {
-        self.sender.send(Message::MainProgressUpdate(
-            MainProgressUpdaterEvent::IncrementProgress(amount),
-        ));
+    match result? {
+        UpdateOutcome::ApplicationUpdated(_, summary) => {
+            if !summary.failed_files.is_empty() {
+                bail!("Failed to update {}", summary.failed_files.join(", "));
+            }
+            Ok(())
+        }
+        UpdateOutcome::UpdaterUpdated(mut child) => {
+            info!("Updater updated, continuing with the new updater");
+            let status = child.wait()?;
+            std::process::exit(status.code().unwrap_or(1));
+        }
+        UpdateOutcome::DryRun => Ok(()),
+        UpdateOutcome::Verified(report) => {
+            for (source_path, state) in &report.files {
+                println!("{:<8} {}", state.to_string(), source_path);
+            }
+            println!("{}", report.describe());
+            if !report.is_ok() {
+                bail!("Some files don't match the manifest");
+            }
+            Ok(())
+        }
     }
 }
 
 fn main() -> anyhow::Result<()> {
-    let args = Args::parse();
+    // The channel selected in the window is remembered across launches
+    let settings_path = Settings::path();
+    let settings = settings_path
+        .as_deref()
+        .map(Settings::load)
+        .unwrap_or_default();
+
+    // Options can be given defaults in config files, which the command line
+    // overrides
+    let config_file = ConfigFile::load(&config_file_paths())?;
+    let (command, cli_args) = config_file.apply(Args::command(), env::args_os().collect())?;
+    let mut args =
+        Args::from_arg_matches(&command.get_matches_from(cli_args)).unwrap_or_else(|e| e.exit());
+    args.beta |= settings.beta;
+    let mut args = args.with_channel();
+
+    // A dry run only prints what it would do and verifying only prints the
+    // state of the files, so there's no use for the window
+    args.headless |= args.dry_run || args.verify_only;
+
+    #[cfg(windows)]
+    if args.headless {
+        attach_parent_console();
+    }
 
     // Setup tracing for loggin
+    let log_buffer = LogBuffer::new(LOG_VIEW_LINES);
+
+    // Logs are always written to a file as well, as there's no console when
+    // the window is shown. A new file is started every day and the previous
+    // days are kept so the log of a failed launch is still there afterwards.
+    let log_dir = log_dir();
+    if let Err(e) = prune_log_files(&log_dir, MAX_LOG_FILES) {
+        if e.kind() != std::io::ErrorKind::NotFound {
+            eprintln!("Failed to delete old log files: {}", e);
+        }
+    }
+    let (log_file, _log_file_guard) =
+        tracing_appender::non_blocking(tracing_appender::rolling::daily(log_dir, LOG_FILE_PREFIX));
+    let file_layer = fmt::layer().with_ansi(false).with_writer(log_file);
 
     if cfg!(feature = "console") {
         #[cfg(feature = "console")]
         console_subscriber::init();
+    } else if args.debug && !args.headless {
+        let writer_log_buffer = log_buffer.clone();
+        let subscriber = tracing_subscriber::registry()
+            .with(LevelFilter::from_level(Level::DEBUG))
+            .with(
+                fmt::layer()
+                    .with_ansi(false)
+                    .with_writer(move || writer_log_buffer.writer()),
+            )
+            .with(file_layer);
+        tracing::subscriber::set_global_default(subscriber)
+            .expect("Critical failure: Failed to set default tracing subscriber");
+    } else if args.headless || args.progress_format == ProgressFormat::Json {
+        // Logs go to stderr so stdout only has the progress and the dry run
+        // report for scripts to read
+        let level = if args.debug {
+            Level::DEBUG
+        } else {
+            Level::INFO
+        };
+        let subscriber = tracing_subscriber::registry()
+            .with(LevelFilter::from_level(level))
+            .with(fmt::layer().with_writer(std::io::stderr))
+            .with(file_layer);
+        tracing::subscriber::set_global_default(subscriber)
+            .expect("Critical failure: Failed to set default tracing subscriber");
     } else {
-        let subscriber = FmtSubscriber::builder()
-            .with_max_level(Level::INFO)
-            .finish();
+        let subscriber = tracing_subscriber::registry()
+            .with(LevelFilter::from_level(Level::INFO))
+            .with(fmt::layer())
+            .with(file_layer);
         tracing::subscriber::set_global_default(subscriber)
             .expect("Critical failure: Failed to set default tracing subscriber");
     }
 
+    if args.headless {
+        return run_headless(args);
+    }
+
     // Load application resources
     let icon_bytes = include_bytes!("../../res/client.png");
     let background_bytes = include_bytes!("../../res/Launcher_Alpha_Background.png");
@@ -491,21 +988,86 @@ fn main() -> anyhow::Result<()> {
 
     let app = app::App::default().with_scheme(app::AppScheme::Gtk);
 
+    if let Some(ui_scale) = args.ui_scale {
+        let ui_scale = ui_scale.clamp(0.5, 4.0);
+        for screen in 0..app::screen_count() {
+            app::set_screen_scale(screen, ui_scale);
+        }
+    }
+
+    // The widgets are laid out for the smallest size and the window is
+    // resized to the requested size once they're all in place
+    let window_size_path = args.output.join("updater").join(WINDOW_SIZE_NAME);
+    let saved_size = WindowSize::load(&window_size_path).unwrap_or_default();
+    let window_size = WindowSize {
+        width: args.window_width.unwrap_or(saved_size.width),
+        height: args.window_height.unwrap_or(saved_size.height),
+    }
+    .clamped();
+
     let mut win = window::DoubleWindow::default()
-        .with_size(780, 630)
-        .center_screen()
+        .with_size(WindowSize::MIN.width, WindowSize::MIN.height)
         .with_label("ROSE Online Updater");
 
+    let reduce_effects = args.reduce_effects;
+    let theme = Rc::new(Cell::new(settings.theme));
+    let background_theme = theme.clone();
     let mut background_frame = Frame::new(0, 0, 780, 630, "");
-    background_frame.draw(move |_| {
-        background_image.draw(0, 0, 780, 630);
+    background_frame.draw(move |f| {
+        let theme = background_theme.get();
+        if reduce_effects || !theme.draws_background_image() {
+            draw::draw_rect_fill(f.x(), f.y(), f.w(), f.h(), theme.background_color());
+        } else {
+            background_image.scale(f.w(), f.h(), false, true);
+            background_image.draw(f.x(), f.y(), f.w(), f.h());
+        }
     });
 
+    // Only the news grows with the window. The controls to the right of this
+    // box keep their size and stay in the bottom right corner.
+    let resize_box = Frame::new(0, 0, 560, 530, "");
+    win.resizable(&resize_box);
+    win.size_range(WindowSize::MIN.width, WindowSize::MIN.height, 0, 0);
+
+    let strings = args.strings();
+    info!("Showing the updater in {}", strings.lang());
+
     let mut main_progress_bar = progress_bar::ProgressBar::new(12, 547);
+    main_progress_bar.set_theme(theme.get());
+    main_progress_bar.set_colors(settings.progress_bar.clone());
+    main_progress_bar.set_strings(strings);
 
     let mut launch_button = launch_button::LaunchButton::new(572, 547);
     launch_button.deactivate();
 
+    let mut countdown_frame = Frame::new(572, 603, 196, 24, "");
+    countdown_frame.set_label_color(theme.get().text_color());
+    countdown_frame.set_label_font(Font::Helvetica);
+    countdown_frame.set_label_size(14);
+
+    let mut pause_button = button::Button::new(600, 605, 80, 20, strings.get("pause"));
+    pause_button.set_label_size(12);
+
+    let mut cancel_button = button::Button::new(688, 605, 80, 20, strings.get("cancel"));
+    cancel_button.set_label_size(12);
+
+    let mut summary_frame = Frame::new(12, 603, 300, 24, "");
+    summary_frame.set_label_color(theme.get().text_color());
+    summary_frame.set_label_font(Font::Helvetica);
+    summary_frame.set_label_size(12);
+    summary_frame.set_align(Align::Left | Align::Inside);
+
+    let mut beta_check =
+        button::CheckButton::new(330, 605, 140, 20, strings.get("use_beta_client"));
+    beta_check.set_label_color(theme.get().text_color());
+    beta_check.set_label_size(12);
+    beta_check.set_checked(args.beta);
+
+    let mut theme_check = button::CheckButton::new(470, 605, 100, 20, strings.get("light_theme"));
+    theme_check.set_label_color(theme.get().text_color());
+    theme_check.set_label_size(12);
+    theme_check.set_checked(theme.get() == Theme::Light);
+
     let mut webview_win = window::Window::default().with_size(780, 530).with_pos(0, 0);
     webview_win.set_border(false);
     webview_win.set_frame(FrameType::NoBox);
@@ -515,8 +1077,26 @@ fn main() -> anyhow::Result<()> {
     win.set_icon(Some(icon));
 
     win.end();
+    win.set_size(window_size.width, window_size.height);
+    let mut win = win.center_screen();
     win.show();
 
+    // Show the logs live so support can see what the updater is doing
+    let mut log_view = None;
+    if args.debug {
+        let mut log_win = window::Window::default()
+            .with_size(780, 300)
+            .with_label("ROSE Online Updater Log");
+        let log_text = text::TextBuffer::default();
+        let mut log_display = text::TextDisplay::new(0, 0, 780, 300, "");
+        log_display.set_buffer(log_text.clone());
+        log_display.set_text_size(12);
+        log_win.resizable(&log_display);
+        log_win.end();
+        log_win.show();
+        log_view = Some((log_display, log_text));
+    }
+
     // Script used in the webview to force links to be opened in the native
     // browser rather than in the webview.
     let script = "
@@ -544,24 +1124,147 @@ fn main() -> anyhow::Result<()> {
         }
     });
     webview.init(script);
-    webview.navigate("https://roseonlinegame.com/launcher.html");
 
     // general channel
     let (tx, rx) = app::channel::<Message>();
 
-    // shutdown channel
+    // Bound for the Retry button shown when the news couldn't be loaded
+    let retry_news_tx = tx.clone();
+    webview.bind("retry_news", move |_, _| {
+        retry_news_tx.send(Message::RetryNews);
+    });
+
+    // Bound for the buttons switching between the news and the changelog
+    let show_changelog_tx = tx.clone();
+    webview.bind("show_changelog", move |_, _| {
+        show_changelog_tx.send(Message::ShowChangelog);
+    });
+    let show_news_tx = tx.clone();
+    webview.bind("show_news", move |_, _| {
+        show_news_tx.send(Message::ShowNews);
+    });
+
+    // shutdown channel, replaced every time the update is restarted
     let (shutdown_tx, shutdown_rx) = tokio::sync::watch::channel(false);
+    let shutdown_tx = Rc::new(RefCell::new(shutdown_tx));
+
+    // Pausing keeps every chunk downloaded so far so the downloads carry on
+    // where they stopped once resumed
+    let pause = PauseFlag::default();
+    let config = args.update_config(pause.clone())?;
+    let button_pause = pause.clone();
+    pause_button.set_callback(move |b| {
+        if button_pause.is_paused() {
+            info!("Resuming update");
+            button_pause.resume();
+            b.set_label(strings.get("pause"));
+        } else {
+            info!("Pausing update");
+            button_pause.pause();
+            b.set_label(strings.get("resume"));
+        }
+    });
+
+    // Switching channels takes effect on the next launch, as the running
+    // update already installs to the selected client's directory
+    let settings = Rc::new(RefCell::new(settings));
+    let beta_settings = settings.clone();
+    let beta_settings_path = settings_path.clone();
+    let beta_tx = tx.clone();
+    beta_check.set_callback(move |b| {
+        let mut settings = beta_settings.borrow_mut();
+        settings.beta = b.is_checked();
+        save_settings(beta_settings_path.as_deref(), &settings);
+        beta_tx.send(Message::Status(
+            strings.get("restart_to_switch_clients").to_string(),
+        ));
+    });
+
+    // Unlike the client, the theme is switched straight away
+    let theme_tx = tx.clone();
+    theme_check.set_callback(move |b| {
+        let theme = if b.is_checked() {
+            Theme::Light
+        } else {
+            Theme::Dark
+        };
+        theme_tx.send(Message::Theme(theme));
+    });
+
+    // Cancelling stops the update but keeps the updater open so it can be
+    // restarted with the update button
+    let cancel_shutdown_tx = shutdown_tx.clone();
+    cancel_button.set_callback(move |b| {
+        info!("Cancelling update");
+        if cancel_shutdown_tx.borrow().send(true).is_err() {
+            info!("Failed to send shutdown message");
+        }
+        b.deactivate();
+    });
 
     // Create our updaters
-    let main_updater = MainProgressUpdater { sender: tx.clone() };
+    let main_updater = MainProgressUpdater {
+        state: ProgressState::with_observer(WindowProgress(tx.clone())),
+        events: progress_event_writer(&args)?,
+    };
 
-    // Clone some args before moving args into download task
-    let exe = args.exe.clone();
+    // Clone some args before moving args into download task. The launch
+    // command is only known once the remote manifest has been downloaded.
+    let exe_override = args.exe.clone();
+    let exe_args_override = args.exe_args.clone();
+    let launch_command = Rc::new(RefCell::new(resolve_launch_command(
+        exe_override.as_deref(),
+        &exe_args_override,
+        None,
+    )));
     let exe_dir = args.exe_dir.clone();
-    let exe_args = args.exe_args.clone();
+    let launch_watch = Duration::from_secs(args.launch_watch_secs);
+    let support_url = args.support_url.clone();
+    let launch_delay = args.launch_delay;
+    let check_running = args.check_running;
+    let launched = Rc::new(Cell::new(false));
+    let launch_tx = tx.clone();
+    let countdown_tx = tx.clone();
+
+    // Poll for new log lines rather than waking the UI for every line
+    if log_view.is_some() {
+        let log_tx = tx.clone();
+        std::thread::spawn(move || loop {
+            std::thread::sleep(Duration::from_millis(500));
+            log_tx.send(Message::LogUpdated);
+        });
+    }
+    let mut log_version = 0;
 
     // When the launch button is clicked we start the application
-    launch_button.set_callback(move |_| {
+    let button_launch_command = launch_command.clone();
+    let button_launched = launched.clone();
+    let update_cancelled = Rc::new(Cell::new(false));
+    let button_update_cancelled = update_cancelled.clone();
+    let restart_tx = tx.clone();
+    launch_button.set_callback(move |b| {
+        // The update button restarts a cancelled update
+        if button_update_cancelled.get() {
+            restart_tx.send(Message::RestartUpdate);
+            return;
+        }
+
+        button_launched.set(true);
+        let launch_command = button_launch_command.borrow();
+        let (exe, exe_args) = &*launch_command;
+
+        if check_running {
+            let exe_name = exe.file_name().unwrap_or_default().to_string_lossy();
+            if is_running(&exe_name) {
+                info!("{} is already running", exe_name);
+                dialog::message(
+                    (app::screen_size().0 / 2.0) as i32,
+                    (app::screen_size().0 / 2.0) as i32,
+                    "ROSE is already running.",
+                );
+                return;
+            }
+        }
         info!(
             "Executing Command: {}/{} {}",
             exe_dir.display(),
@@ -569,89 +1272,392 @@ fn main() -> anyhow::Result<()> {
             exe_args.join(" ")
         );
 
-        let exe = exe_dir.join(&exe);
+        let exe = exe_dir.join(exe);
 
-        Command::new(&exe)
+        let mut child = match Command::new(&exe)
             .current_dir(&exe_dir)
-            .args(&exe_args)
+            .args(exe_args)
             .spawn()
-            .unwrap();
+        {
+            Ok(child) => child,
+            Err(e) => {
+                launch_tx.send(Message::LaunchError(format!(
+                    "Failed to launch {}: {}",
+                    exe.display(),
+                    e
+                )));
+                return;
+            }
+        };
 
-        app.quit();
+        if launch_watch.is_zero() {
+            app.quit();
+            return;
+        }
+
+        // Watch the game for a short time so we can tell the player if it
+        // crashed immediately rather than silently closing.
+        b.deactivate();
+        let launch_tx = launch_tx.clone();
+        std::thread::spawn(move || {
+            let launched_at = Instant::now();
+            loop {
+                match child.try_wait() {
+                    Ok(Some(status)) if !status.success() => {
+                        error!("Game exited during launch with {}", status);
+                        launch_tx.send(Message::LaunchFailed(status.code()));
+                        return;
+                    }
+                    Ok(None) if launched_at.elapsed() < launch_watch => {
+                        std::thread::sleep(Duration::from_millis(100));
+                    }
+                    _ => break,
+                }
+            }
+            launch_tx.send(Message::Shutdown);
+        });
     });
 
     let rt = tokio::runtime::Runtime::new().unwrap();
 
-    // Spawn a task to download our updates
-    let process_future = rt.spawn(async move {
-        let result = process(&args, main_updater, shutdown_rx).await;
-        if let Ok(download_result) = result {
-            info!("Download task completed");
-
-            match download_result {
-                DownloadResult::ApplicationUpdated => {
-                    info!("Application updated");
-                    tx.send(Message::Launch);
-                }
-                DownloadResult::UpdaterUpdated => {
-                    // The updater itself was updated, we should exit because a new
-                    // process was started with the new updater to update the
-                    // application.
-                    info!("Updater updated");
-                    tx.send(Message::Shutdown);
-                }
-            }
-        } else {
-            let error_string = result.err().unwrap().to_string();
-            error!("Download task failed or cancelled, error {}", &error_string);
-            tx.send(Message::Error(error_string));
+    // The news is loaded alongside the update. Failing to load it only
+    // affects the news, the update and the Play button carry on regardless.
+    let news_url = Url::parse(NEWS_URL)?;
+    let news_cache_path = config_dir().map(|dir| dir.join(NEWS_CACHE_NAME));
+    let news_client = config.clone_options.client.clone();
+    let news_retry = config.clone_options.retry;
+    let changelog_url = args
+        .changelog_url
+        .as_deref()
+        .map(|url| Url::parse(url).context(format!("Failed to parse the changelog url {}", url)))
+        .transpose()?;
+    let changelog_cache_path = config_dir().map(|dir| dir.join(CHANGELOG_CACHE_NAME));
+    // The tasks are kept so their requests can be aborted when the window is
+    // closed while they're still loading
+    let spawn_news = || {
+        let news_url = news_url.clone();
+        let news_cache_path = news_cache_path.clone();
+        let news_client = news_client.clone();
+        let news_tx = tx.clone();
+        let mut tasks = vec![rt.spawn(async move {
+            let news = load_news(
+                &news_client,
+                &news_url,
+                news_cache_path.as_deref(),
+                &news_retry,
+            )
+            .await;
+            news_tx.send(Message::News(news.to_html(&news_url)));
+        })];
+
+        if let Some(changelog_url) = changelog_url.clone() {
+            let changelog_cache_path = changelog_cache_path.clone();
+            let changelog_client = news_client.clone();
+            let changelog_tx = tx.clone();
+            tasks.push(rt.spawn(async move {
+                let changelog = load_news(
+                    &changelog_client,
+                    &changelog_url,
+                    changelog_cache_path.as_deref(),
+                    &news_retry,
+                )
+                .await;
+                changelog_tx.send(Message::Changelog(changelog));
+            }));
         }
-    });
+        tasks
+    };
+    let mut news_tasks = spawn_news();
+    let mut news_loading = true;
+    let mut news_html = None;
+    let mut changelog = None;
+    let mut showing_changelog = false;
+
+    // Spawn a task to download our updates
+    let mut process_future = spawn_update(
+        &rt,
+        config.clone(),
+        main_updater.clone(),
+        shutdown_rx,
+        tx.clone(),
+    );
 
     while app.wait() {
         if let Some(e) = rx.recv() {
             match e {
-                Message::MainProgressUpdate(e) => match e {
-                    MainProgressUpdaterEvent::SetMaxProgress(amount) => {
+                Message::Progress(current, total) => {
+                    // Every stage starts over with its own total
+                    if main_progress_bar.maximum() != total as usize {
                         main_progress_bar.set_minimum(0);
-                        main_progress_bar.set_maximum(amount);
-                        main_progress_bar.set_value(0);
+                        main_progress_bar.set_maximum(total as usize);
                         background_frame.redraw();
-                        main_progress_bar.redraw();
                         launch_button.redraw();
                     }
-                    MainProgressUpdaterEvent::IncrementProgress(amount) => {
-                        main_progress_bar.set_value(main_progress_bar.value() + amount);
-                        main_progress_bar.redraw();
-                    }
-                },
-                Message::Launch => {
+                    main_progress_bar.set_value(current as usize);
+                    main_progress_bar.redraw();
+                }
+                Message::Launch(launch, summary) => {
                     info!("Ready to launch");
+                    pause_button.hide();
+                    cancel_button.hide();
+                    summary_frame.set_label(&summary);
+                    background_frame.redraw();
+                    main_progress_bar.redraw();
+                    summary_frame.redraw();
+                    *launch_command.borrow_mut() = resolve_launch_command(
+                        exe_override.as_deref(),
+                        &exe_args_override,
+                        launch.as_ref(),
+                    );
                     launch_button.activate();
                     launch_button.change_state(launch_button::LaunchButtonState::Play);
                     launch_button.redraw();
+
+                    // Give the player a chance to read the news before the
+                    // game is launched for them
+                    if let Some(delay) = launch_delay {
+                        let countdown_tx = countdown_tx.clone();
+                        std::thread::spawn(move || {
+                            for remaining in (1..=delay).rev() {
+                                countdown_tx.send(Message::LaunchCountdown(remaining));
+                                std::thread::sleep(Duration::from_secs(1));
+                            }
+                            countdown_tx.send(Message::AutoLaunch);
+                        });
+                    }
+                }
+                Message::LaunchCountdown(remaining) => {
+                    if !launched.get() {
+                        countdown_frame.set_label(&strings.format("starting_in", &[&remaining]));
+                    } else {
+                        countdown_frame.set_label("");
+                    }
+                    background_frame.redraw();
+                    main_progress_bar.redraw();
+                    launch_button.redraw();
+                    countdown_frame.redraw();
+                }
+                Message::AutoLaunch => {
+                    countdown_frame.set_label("");
+                    background_frame.redraw();
+                    main_progress_bar.redraw();
+                    launch_button.redraw();
+                    countdown_frame.redraw();
+                    if !launched.get() {
+                        info!("Launching after delay");
+                        launch_button.do_callback();
+                    }
+                }
+                Message::LaunchFailed(code) => {
+                    let code = code.map_or("unknown".to_string(), |c| c.to_string());
+                    let choice = dialog::choice2(
+                        (app::screen_size().0 / 2.0) as i32,
+                        (app::screen_size().0 / 2.0) as i32,
+                        &strings.format("launch_failed", &[&code]),
+                        strings.get("close"),
+                        strings.get("get_support"),
+                        strings.get("view_logs"),
+                    );
+                    match choice {
+                        Some(1) => {
+                            if let Err(e) = open::that(&support_url) {
+                                error!("Failed to open {}: {}", &support_url, e);
+                            }
+                        }
+                        Some(2) => show_log_viewer(&strings),
+                        _ => {}
+                    }
+                    launch_button.activate();
+                    launch_button.redraw();
+                }
+                Message::LaunchError(e) => {
+                    // The files are up to date so the player can try again
+                    // straight away, e.g. after closing another program
+                    dialog::alert(
+                        (app::screen_size().0 / 2.0) as i32,
+                        (app::screen_size().0 / 2.0) as i32,
+                        &e,
+                    );
+                    launched.set(false);
+                    launch_button.activate();
+                    launch_button.redraw();
+                }
+                Message::News(html) => {
+                    news_loading = false;
+                    news_html = Some(html);
+                    if !showing_changelog {
+                        tx.send(Message::ShowNews);
+                    }
+                }
+                Message::Changelog(news) => {
+                    changelog = Some(news);
+                    // Show the button on the news now that there is a changelog
+                    if !showing_changelog {
+                        tx.send(Message::ShowNews);
+                    }
+                }
+                Message::ShowNews => {
+                    showing_changelog = false;
+                    if let Some(news_html) = &news_html {
+                        match &changelog {
+                            Some(changelog) => {
+                                let is_new =
+                                    changelog.page_hash() != settings.borrow().seen_changelog;
+                                webview.set_html(&news_with_changelog_button(
+                                    news_html, is_new, &strings,
+                                ));
+                            }
+                            None => webview.set_html(news_html),
+                        }
+                    }
+                }
+                Message::ShowChangelog => {
+                    if let (Some(changelog), Some(changelog_url)) = (&changelog, &changelog_url) {
+                        showing_changelog = true;
+                        webview.set_html(&add_toolbar(
+                            &changelog.to_html(changelog_url),
+                            &format!(
+                                "<button onclick=\"show_news()\">{}</button>",
+                                strings.get("back_to_news")
+                            ),
+                        ));
+
+                        // The badge is only shown until the changelog is opened
+                        let page_hash = changelog.page_hash();
+                        let mut settings = settings.borrow_mut();
+                        if page_hash.is_some() && page_hash != settings.seen_changelog {
+                            settings.seen_changelog = page_hash;
+                            save_settings(settings_path.as_deref(), &settings);
+                        }
+                    }
+                }
+                Message::RetryNews => {
+                    // Clicking Retry again while the news is loading doesn't
+                    // start another download
+                    if !news_loading {
+                        info!("Reloading the news");
+                        news_loading = true;
+                        for task in &news_tasks {
+                            task.abort();
+                        }
+                        news_tasks = spawn_news();
+                    }
+                }
+                Message::LogUpdated => {
+                    if let Some((log_display, log_text)) = &mut log_view {
+                        let version = log_buffer.version();
+                        if version != log_version {
+                            log_version = version;
+                            log_text.set_text(&log_buffer.text());
+                            log_display.set_insert_position(log_text.length());
+                            log_display.show_insert_position();
+                        }
+                    }
+                }
+                Message::Theme(new_theme) => {
+                    info!("Switching to the {:?} theme", new_theme);
+                    theme.set(new_theme);
+                    let text_color = new_theme.text_color();
+                    countdown_frame.set_label_color(text_color);
+                    summary_frame.set_label_color(text_color);
+                    beta_check.set_label_color(text_color);
+                    theme_check.set_label_color(text_color);
+                    main_progress_bar.set_theme(new_theme);
+                    win.redraw();
+
+                    let mut settings = settings.borrow_mut();
+                    settings.theme = new_theme;
+                    save_settings(settings_path.as_deref(), &settings);
+                }
+                Message::Stage(stage) => {
+                    main_progress_bar.set_stage(stage);
+                    main_progress_bar.redraw();
+                }
+                Message::CurrentFile(path) => {
+                    main_progress_bar.set_current_file(&path);
+                    main_progress_bar.redraw();
+                }
+                Message::Status(status) => {
+                    summary_frame.set_label(&status);
+                    background_frame.redraw();
+                    summary_frame.redraw();
+                }
+                Message::Cancelled => {
+                    summary_frame.set_label(strings.get("update_cancelled"));
+                    pause.resume();
+                    pause_button.set_label(strings.get("pause"));
+                    pause_button.hide();
+                    cancel_button.hide();
+                    update_cancelled.set(true);
+                    launch_button.activate();
+                    launch_button.change_state(launch_button::LaunchButtonState::Update);
+                    background_frame.redraw();
+                    summary_frame.redraw();
+                    launch_button.redraw();
+                }
+                Message::RestartUpdate => {
+                    info!("Restarting update");
+                    // The previous update has already failed or been
+                    // cancelled, this only makes sure nothing of it is left
+                    // running alongside the new one
+                    process_future.abort();
+                    let (new_shutdown_tx, shutdown_rx) = tokio::sync::watch::channel(false);
+                    *shutdown_tx.borrow_mut() = new_shutdown_tx;
+                    process_future = spawn_update(
+                        &rt,
+                        config.clone(),
+                        main_updater.clone(),
+                        shutdown_rx,
+                        tx.clone(),
+                    );
+
+                    update_cancelled.set(false);
+                    summary_frame.set_label("");
+                    pause_button.show();
+                    cancel_button.activate();
+                    cancel_button.show();
+                    launch_button.deactivate();
+                    launch_button.change_state(launch_button::LaunchButtonState::Updating);
+                    background_frame.redraw();
+                    summary_frame.redraw();
+                    launch_button.redraw();
                 }
                 Message::Shutdown => {
                     info!("Shutting down");
                     break;
                 }
                 Message::Error(e) => {
-                    dialog::alert(
+                    let choice = dialog::choice2(
                         (app::screen_size().0 / 2.0) as i32,
                         (app::screen_size().0 / 2.0) as i32,
-                        &format!(
-                            "An error was detected, please restart the launcher:\nError: {}",
-                            e
-                        ),
+                        &strings.format("update_error", &[&e]),
+                        strings.get("close"),
+                        strings.get("retry"),
+                        strings.get("view_logs"),
                     );
-                    break;
+                    match choice {
+                        Some(1) => tx.send(Message::RestartUpdate),
+                        // The updater stays open as if the update was
+                        // cancelled, so it can be retried after reading the
+                        // logs
+                        Some(2) => {
+                            show_log_viewer(&strings);
+                            tx.send(Message::Cancelled);
+                            tx.send(Message::Status(strings.get("update_failed").to_string()));
+                        }
+                        _ => break,
+                    }
                 }
             }
         }
     }
 
+    for task in &news_tasks {
+        task.abort();
+    }
+
     rt.block_on(async move {
-        let result = shutdown_tx.send(true);
+        let result = shutdown_tx.borrow().send(true);
         if result.is_err() {
             info!("Failed to send shutdown message");
         }
@@ -661,6 +1667,17 @@ fn main() -> anyhow::Result<()> {
     if result.is_err() {
         error!("Error while closing down download process");
     }
+    if let Some(events) = &main_updater.events {
+        events.flush();
+    }
+
+    let window_size = WindowSize {
+        width: win.w(),
+        height: win.h(),
+    };
+    if let Err(e) = window_size.save(&window_size_path) {
+        warn!(error =? e, "Failed to save the window size");
+    }
 
     Ok(())
 }
@@ -1,38 +1,85 @@
-use std::path::Path;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
 
-use anyhow::Context;
-use bitar::archive_reader::HttpReader;
-use bitar::{Archive, ChunkIndex, CloneOutput};
-use futures_util::{StreamExt, TryStreamExt};
-use reqwest::Url;
+use anyhow::{anyhow, Context};
+use bitar::archive_reader::{ArchiveReader, HttpReader, IoReader};
+use bitar::{Archive, ChunkIndex, CloneOutput, HashSum};
+use futures_util::{StreamExt, TryFutureExt, TryStreamExt};
+use reqwest::header::RANGE;
+use reqwest::{StatusCode, Url};
 use tokio::fs;
-use tracing::instrument;
+use tokio::io::{AsyncRead, AsyncWriteExt};
+use tracing::{info, instrument, warn};
 
 use async_trait::async_trait;
 
+use crate::{
+    ChunkBudget, DailyCap, DailyCapReached, DownloadLimit, IoPacer, PauseFlag, RangeSupport,
+    RateLimiter, RetryPolicy,
+};
+
 #[async_trait]
 pub trait Updater {
     async fn set_max_progress(&self, total: usize);
     async fn increment_progress(&self, amount: usize);
 }
 
-#[instrument(skip(updater))]
-pub async fn clone_remote<T: Updater>(
-    url: &Url,
-    output_path: &Path,
-    updater: T
-) -> anyhow::Result<()> {
-    let http_reader = HttpReader::from_url(url.clone()).retries(4);
+/// Options shared by every clone
+#[derive(Clone, Debug)]
+pub struct CloneOptions {
+    /// Client used for every request
+    pub client: reqwest::Client,
 
-    let mut archive = Archive::try_init(http_reader).await.context(format!("Failed to read archive at {}", &url))?;
+    /// Budget of chunk bytes shared between all concurrent clones
+    pub chunk_budget: ChunkBudget,
 
-    // Create parent directory
-    if let Some(output_parent) = output_path.parent() {
-        fs::create_dir_all(output_parent).await?;
-    }
+    /// Limit on the number of files cloned at once
+    pub download_limit: DownloadLimit,
+
+    /// Rescan the output file after the local chunks have been reordered so
+    /// any chunk that was corrupted while being moved is downloaded again
+    pub verify_reorder: bool,
+
+    /// Limit on the rate cloned data is written to disk. Output files are
+    /// also synced after every second's worth of data so the OS doesn't build
+    /// up a large backlog of writes.
+    pub io_pacer: Option<IoPacer>,
+
+    /// Limit on the number of bytes downloaded per day
+    pub daily_cap: Option<DailyCap>,
+
+    /// Limit on the download rate shared by all clones. Chunks are counted by
+    /// their compressed size, which is what's actually received.
+    pub rate_limiter: Option<RateLimiter>,
+
+    /// How failed archive requests are retried
+    pub retry: RetryPolicy,
+
+    /// Which archive servers support range requests
+    pub range_support: RangeSupport,
 
-    // Create a file for clone output
-    let mut output_file = fs::OpenOptions::new()
+    /// Pauses every clone while set
+    pub pause: PauseFlag,
+
+    /// Number of chunks of a local file hashed at once when scanning it for
+    /// data to reuse
+    pub verify_jobs: usize,
+
+    /// Number of chunks fetched from the archive which are decompressed and
+    /// hashed at once. Decompressed chunks count against the chunk budget.
+    pub remote_buffer: usize,
+
+    /// Local files of at least this many bytes are memory-mapped when they're
+    /// scanned for data to reuse, see [`MMAP_THRESHOLD`]
+    pub mmap_threshold: u64,
+}
+
+/// Default size from which local files are memory-mapped instead of read when
+/// scanning them, which saves a read for every chunk of large files
+pub const MMAP_THRESHOLD: u64 = 64 * 1024 * 1024;
+
+async fn open_output_file(output_path: &Path) -> anyhow::Result<fs::File> {
+    let output_file = fs::OpenOptions::new()
         .create(true)
         .write(true)
         .read(true)
@@ -43,34 +90,455 @@ pub async fn clone_remote<T: Updater>(
             output_path.display()
         ))?;
 
-    // Scan the output file for chunks and build a chunk index
+    Ok(output_file)
+}
+
+/// Memory-map `file` for reading
+async fn map_file(file: &fs::File) -> std::io::Result<memmap2::Mmap> {
+    let file = file.try_clone().await?.into_std().await;
+    // SAFETY: The clone doesn't write to the output file until the map has
+    // been dropped. If another program changes the file meanwhile the chunks
+    // read from the map fail to match the archive and are downloaded instead.
+    unsafe { memmap2::Mmap::map(&file) }
+}
+
+/// Scan the output file for chunks and build a chunk index
+///
+/// Files of at least `options.mmap_threshold` bytes are memory-mapped, the
+/// others and those which can't be mapped are read instead.
+async fn scan_output_chunks<T: Updater, R: ArchiveReader>(
+    output_file: &mut fs::File,
+    archive: &Archive<R>,
+    updater: Option<&T>,
+    options: &CloneOptions,
+) -> anyhow::Result<ChunkIndex> {
+    let size = output_file.metadata().await?.len();
+    if size > 0 && size >= options.mmap_threshold {
+        match map_file(output_file).await {
+            Ok(map) => return scan_chunks(&map[..], archive, updater, options).await,
+            Err(e) => warn!(error =? e, "Failed to memory-map the output file, reading it instead"),
+        }
+    }
+    scan_chunks(output_file, archive, updater, options).await
+}
+
+/// Build a chunk index of the data read from `reader`. Up to
+/// `options.verify_jobs` chunks are hashed at once on the blocking thread
+/// pool.
+///
+/// Room for a chunk is taken from the chunk budget before the chunk is read
+/// and given back once it's indexed, so the chunks buffered while hashing
+/// count towards the budget.
+async fn scan_chunks<T: Updater, R: ArchiveReader, C: AsyncRead + Unpin + Send>(
+    reader: C,
+    archive: &Archive<R>,
+    updater: Option<&T>,
+    options: &CloneOptions,
+) -> anyhow::Result<ChunkIndex> {
     let mut output_index = ChunkIndex::new_empty(archive.chunk_hash_length());
-    {
-        let chunker = archive.chunker_config().new_chunker(&mut output_file);
-        let mut chunk_stream = chunker.map_ok(|(offset, chunk)| (offset, chunk.verify()));
-        while let Some(r) = chunk_stream.next().await {
-            let (offset, verified) = r?;
-            let (hash, chunk) = verified.into_parts();
-            output_index.add_chunk(hash, chunk.len(), &[offset]);
+    let chunker = archive.chunker_config().new_chunker(reader);
+    let chunk_budget = &options.chunk_budget;
+    let max_chunk_size = archive.max_chunk_size();
+    let chunks = futures_util::stream::try_unfold(chunker, |mut chunker| async move {
+        let permit = chunk_budget.acquire(max_chunk_size).await;
+        let next = chunker.try_next().await?;
+        Ok::<_, anyhow::Error>(next.map(|(offset, chunk)| ((permit, offset, chunk), chunker)))
+    });
+    // Buffering keeps the chunks in file order while they're hashed
+    let mut chunk_stream = chunks
+        .map_ok(|(permit, offset, chunk)| {
+            tokio::task::spawn_blocking(move || (offset, chunk.verify()))
+                .map_ok(|verified| (permit, verified))
+                .map_err(anyhow::Error::from)
+        })
+        .try_buffered(options.verify_jobs.max(1));
+    while let Some(r) = chunk_stream.next().await {
+        let (_permit, (offset, verified)) = r?;
+        let (hash, chunk) = verified.into_parts();
+        output_index.add_chunk(hash, chunk.len(), &[offset]);
+        if let Some(updater) = updater {
             updater.increment_progress(chunk.len()).await;
         }
     }
 
-    // Create output to contain the clone of the archive's source
-    let mut output = CloneOutput::new(output_file, archive.build_source_index());
+    Ok(output_index)
+}
+
+/// Path of the sidecar file recording the progress of cloning `output_path`
+fn progress_path(output_path: &Path) -> PathBuf {
+    let mut file_name = output_path.file_name().unwrap_or_default().to_os_string();
+    file_name.push(".progress");
+    output_path.with_file_name(file_name)
+}
+
+/// Read the hashes of the chunks recorded as written by an interrupted clone
+/// of `archive`, or `None` if there is no usable progress file.
+///
+/// The first line of the progress file is the source checksum of the archive
+/// being cloned, followed by one chunk hash per line.
+async fn read_progress<R: ArchiveReader>(
+    progress_path: &Path,
+    archive: &Archive<R>,
+) -> Option<HashSet<String>> {
+    let contents = fs::read_to_string(progress_path).await.ok()?;
+    let mut lines = contents.lines();
+    if lines.next()? != archive.source_checksum().to_string() {
+        return None;
+    }
+    Some(lines.map(str::to_string).collect())
+}
+
+/// Start a new progress file for `output`, recording every chunk which is
+/// already in place
+async fn start_progress<R: ArchiveReader>(
+    progress_path: &Path,
+    archive: &Archive<R>,
+    output: &CloneOutput<fs::File>,
+) -> anyhow::Result<fs::File> {
+    let mut contents = format!("{}\n", archive.source_checksum());
+    for descriptor in archive.chunk_descriptors() {
+        if !output.chunks().contains(&descriptor.checksum) {
+            contents += &format!("{}\n", descriptor.checksum);
+        }
+    }
+
+    let mut progress_file = fs::File::create(progress_path).await.context(format!(
+        "Failed to create the progress file at {}",
+        progress_path.display()
+    ))?;
+    progress_file.write_all(contents.as_bytes()).await?;
+    Ok(progress_file)
+}
 
-    // Reorder chunks in the output
-    let _size = output.reorder_in_place(output_index).await?;
+/// Check whether the server hosting `url` honours `Range` requests
+///
+/// Chunks are fetched with ranged requests so a server, or a proxy in
+/// between, which ignores them would return the whole archive for every chunk.
+async fn supports_range_requests(client: &reqwest::Client, url: &Url) -> anyhow::Result<bool> {
+    let response = client
+        .get(url.clone())
+        .header(RANGE, "bytes=0-0")
+        .send()
+        .await?
+        .error_for_status()?;
+    Ok(response.status() == StatusCode::PARTIAL_CONTENT)
+}
 
-    // Fetch the rest of the chunks from the archive
-    let mut chunk_stream = archive.chunk_stream(output.chunks());
-    while let Some(result) = chunk_stream.next().await {
-        let compressed = result?;
-        let unverified = compressed.decompress()?;
-        let verified = unverified.verify()?;
+/// Download the whole archive at `url` to `archive_path` and clone it to
+/// `output_path`
+async fn clone_downloaded_archive<T: Updater>(
+    url: &Url,
+    archive_path: &Path,
+    output_path: &Path,
+    updater: T,
+    options: &CloneOptions,
+) -> anyhow::Result<()> {
+    download_archive(url, archive_path, options)
+        .await
+        .context(format!("Failed to download archive at {}", &url))?;
+    let io_reader = IoReader::new(fs::File::open(archive_path).await?);
+    let archive = Archive::try_init(io_reader)
+        .await
+        .context(format!("Failed to read archive downloaded from {}", &url))?;
+    clone_archive(archive, output_path, updater, options, false).await
+}
+
+/// Download the whole archive at `url` to `archive_path`
+async fn download_archive(
+    url: &Url,
+    archive_path: &Path,
+    options: &CloneOptions,
+) -> anyhow::Result<()> {
+    let mut response = options
+        .client
+        .get(url.clone())
+        .send()
+        .await?
+        .error_for_status()?;
+    let mut archive_file = fs::File::create(archive_path).await?;
+    while let Some(bytes) = response.chunk().await? {
+        options.pause.wait().await;
+        if let Some(daily_cap) = &options.daily_cap {
+            daily_cap.check()?;
+            daily_cap.add(bytes.len()).await;
+        }
+        if let Some(rate_limiter) = &options.rate_limiter {
+            rate_limiter.acquire(bytes.len()).await;
+        }
+        archive_file.write_all(&bytes).await?;
+    }
+    archive_file.flush().await?;
+    Ok(())
+}
+
+#[instrument(skip(updater, options))]
+pub async fn clone_remote<T: Updater + Clone>(
+    url: &Url,
+    output_path: &Path,
+    updater: T,
+    options: &CloneOptions,
+) -> anyhow::Result<()> {
+    let supports_range_requests = options
+        .range_support
+        .check(url, || supports_range_requests(&options.client, url))
+        .await?;
+    if !supports_range_requests {
+        warn!(
+            "{} does not support range requests, downloading the whole archive",
+            url
+        );
+
+        let mut archive_name = output_path.file_name().unwrap_or_default().to_os_string();
+        archive_name.push(".archive");
+        let archive_path = output_path.with_file_name(archive_name);
+        if let Some(output_parent) = output_path.parent() {
+            fs::create_dir_all(output_parent).await?;
+        }
+
+        // The downloaded archive is removed however the clone went, without
+        // hiding why it failed
+        let (archive_path, updater) = (&archive_path, &updater);
+        let result = options
+            .retry
+            .run_if(
+                move || {
+                    clone_downloaded_archive(
+                        url,
+                        archive_path,
+                        output_path,
+                        updater.clone(),
+                        options,
+                    )
+                },
+                |e| !e.is::<DailyCapReached>(),
+            )
+            .await;
+        if let Err(e) = fs::remove_file(&archive_path).await {
+            if e.kind() != std::io::ErrorKind::NotFound {
+                warn!(error =? e, path =? archive_path.display(), "Failed to remove the downloaded archive");
+            }
+        }
+        return result;
+    }
+
+    // Failed archive requests are retried with the backoff of the retry
+    // policy instead of by the reader. A retried clone resumes from the chunks
+    // written by the failed attempt.
+    let updater = &updater;
+    options
+        .retry
+        .run_if(
+            || async move {
+                let http_reader =
+                    HttpReader::from_request(options.client.get(url.clone())).retries(0);
+                let archive = Archive::try_init(http_reader)
+                    .await
+                    .context(format!("Failed to read archive at {}", &url))?;
+                clone_archive(archive, output_path, updater.clone(), options, true).await
+            },
+            |e| !e.is::<DailyCapReached>(),
+        )
+        .await
+}
+
+/// Clone the source of `archive` to `output_path`, reusing any data already
+/// in the output file
+///
+/// When `remote` is set the archive is read over the network, so bytes
+/// fetched from it are counted against the daily cap and rate limited.
+async fn clone_archive<T: Updater, R>(
+    mut archive: Archive<R>,
+    output_path: &Path,
+    updater: T,
+    options: &CloneOptions,
+    remote: bool,
+) -> anyhow::Result<()>
+where
+    R: ArchiveReader + Send,
+    R::Error: std::error::Error + Send + Sync + 'static,
+{
+    let chunk_budget = &options.chunk_budget;
+    let daily_cap = options.daily_cap.as_ref().filter(|_| remote);
+    let rate_limiter = options.rate_limiter.as_ref().filter(|_| remote);
+
+    // Create parent directory
+    if let Some(output_parent) = output_path.parent() {
+        fs::create_dir_all(output_parent).await?;
+    }
+
+    // An interrupted clone recorded which chunks it had already written, so
+    // there's no need to scan the output file for them again
+    let progress_path = progress_path(output_path);
+    let written_chunks = if output_path.exists() {
+        read_progress(&progress_path, &archive).await
+    } else {
+        None
+    };
+
+    let mut output = match written_chunks {
+        Some(written_chunks) => {
+            let mut source_index = archive.build_source_index();
+            let mut written_size = 0;
+            for descriptor in archive.chunk_descriptors() {
+                if written_chunks.contains(&descriptor.checksum.to_string()) {
+                    source_index.remove(&descriptor.checksum);
+                    written_size += descriptor.source_size as usize;
+                }
+            }
+            info!(
+                "Resuming clone of {} with {} chunks already written",
+                output_path.display(),
+                written_chunks.len()
+            );
+            updater.increment_progress(written_size).await;
+            CloneOutput::new(open_output_file(output_path).await?, source_index)
+        }
+        None => {
+            // Create a file for clone output
+            let mut output_file = open_output_file(output_path).await?;
+            let output_index =
+                scan_output_chunks(&mut output_file, &archive, Some(&updater), options).await?;
+
+            // Create output to contain the clone of the archive's source
+            let mut output = CloneOutput::new(output_file, archive.build_source_index());
+
+            // Reorder chunks in the output
+            let _size = output.reorder_in_place(output_index).await?;
+
+            if options.verify_reorder {
+                // Rescan the reordered output. Reordering again with the fresh
+                // index keeps every chunk which is intact and in place, so any
+                // chunk that was corrupted while being moved is left to be
+                // fetched from the archive.
+                drop(output);
+                let mut output_file = open_output_file(output_path).await?;
+                let output_index =
+                    scan_output_chunks(&mut output_file, &archive, None::<&T>, options).await?;
+                output = CloneOutput::new(output_file, archive.build_source_index());
+                let _size = output.reorder_in_place(output_index).await?;
+            }
+
+            output
+        }
+    };
+    let mut progress_file = start_progress(&progress_path, &archive, &output).await?;
+
+    // Fetch the rest of the chunks from the archive, decompressing and hashing
+    // several at once while keeping them in order. We reserve room for the
+    // largest possible chunk before decompressing it since we don't know its
+    // size until it has been decompressed.
+    let max_chunk_size = archive.max_chunk_size();
+    let total_source_size = archive.total_source_size();
+    let mut sync_file = match options.io_pacer {
+        Some(_) => Some(open_output_file(output_path).await?),
+        None => None,
+    };
+    let mut unsynced_size = 0;
+
+    // The bytes received for a chunk are its compressed size in the archive
+    let archive_sizes: HashMap<HashSum, usize> = archive
+        .chunk_descriptors()
+        .iter()
+        .map(|descriptor| {
+            (
+                descriptor.checksum.clone(),
+                descriptor.archive_size as usize,
+            )
+        })
+        .collect();
+    let mut chunk_stream = archive
+        .chunk_stream(output.chunks())
+        .map_err(anyhow::Error::from)
+        .map_ok(|compressed| async move {
+            let permit = chunk_budget.acquire(max_chunk_size).await;
+            let verified = tokio::task::spawn_blocking(move || -> anyhow::Result<_> {
+                Ok(compressed.decompress()?.verify()?)
+            })
+            .await??;
+            Ok::<_, anyhow::Error>((permit, verified))
+        })
+        .try_buffered(options.remote_buffer.max(1));
+    loop {
+        options.pause.wait().await;
+        if let Some(daily_cap) = daily_cap {
+            daily_cap.check()?;
+        }
+
+        let (_permit, verified) = match chunk_stream.next().await {
+            Some(result) => result?,
+            None => break,
+        };
+        let archive_size = archive_sizes.get(verified.hash()).copied();
         let size = output.feed(&verified).await?;
+        progress_file
+            .write_all(format!("{}\n", verified.hash()).as_bytes())
+            .await?;
+
+        if let Some(rate_limiter) = rate_limiter {
+            rate_limiter.acquire(archive_size.unwrap_or(size)).await;
+        }
         updater.increment_progress(size).await;
+
+        if let Some(daily_cap) = daily_cap {
+            daily_cap.add(archive_size.unwrap_or(size)).await;
+        }
+
+        if let (Some(io_pacer), Some(sync_file)) = (&options.io_pacer, &mut sync_file) {
+            io_pacer.wait(size).await;
+            unsynced_size += size as u64;
+            if unsynced_size >= io_pacer.bytes_per_sec() {
+                sync_file.sync_data().await?;
+                unsynced_size = 0;
+            }
+        }
     }
+    drop(chunk_stream);
+    drop(output);
+
+    // The local file may have been larger than the new source, in which case
+    // the old trailing data would still be present after the clone.
+    fs::OpenOptions::new()
+        .write(true)
+        .open(&output_path)
+        .await?
+        .set_len(total_source_size)
+        .await
+        .context(format!(
+            "Failed to truncate the output file at {}",
+            output_path.display()
+        ))?;
+
+    drop(progress_file);
+    fs::remove_file(&progress_path).await.context(format!(
+        "Failed to remove the progress file at {}",
+        progress_path.display()
+    ))?;
 
     Ok(())
 }
+
+/// Clone the archive from the first of `urls` which succeeds
+///
+/// This lets a file whose archive is missing or corrupt at its primary
+/// location be fetched from one of its fallback locations instead.
+pub async fn clone_remote_with_fallbacks<T: Updater + Clone>(
+    urls: &[Url],
+    output_path: &Path,
+    updater: T,
+    options: &CloneOptions,
+) -> anyhow::Result<()> {
+    let _permit = options.download_limit.acquire().await;
+
+    let mut last_error = None;
+    for url in urls {
+        match clone_remote(url, output_path, updater.clone(), options).await {
+            Ok(()) => return Ok(()),
+            Err(e) => {
+                warn!("Failed to clone {}, trying the next location: {:?}", url, e);
+                last_error = Some(e);
+            }
+        }
+    }
+
+    Err(last_error.unwrap_or_else(|| anyhow!("No archive locations to clone from")))
+}
@@ -0,0 +1,145 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use anyhow::{bail, Context};
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use rustls::client::{ServerCertVerified, ServerCertVerifier, WebPkiVerifier};
+use rustls::{Certificate, ClientConfig, OwnedTrustAnchor, RootCertStore, ServerName};
+use sha2::{Digest, Sha256};
+
+/// Pins of the update and news servers, set when building a release as e.g.
+/// `updates2.roseonlinegame.com=<pin>,<pin>;www.roseonlinegame.com=<pin>`
+const BUILTIN_CERT_PINS: Option<&str> = option_env!("ROSE_UPDATER_CERT_PINS");
+
+/// The pin of a DER encoded certificate, which is the base64 encoded SHA-256
+/// hash of its public key info. `None` if the certificate can't be parsed.
+pub fn spki_pin(certificate: &[u8]) -> Option<String> {
+    let (_, certificate) = x509_parser::parse_x509_certificate(certificate).ok()?;
+    Some(BASE64.encode(Sha256::digest(certificate.public_key().raw)))
+}
+
+/// Public keys the certificates of a host have to use. Connections to a host
+/// with pins are rejected unless a certificate of its chain has one of the
+/// pinned keys, which keeps working across certificate renewals as long as
+/// the key stays the same.
+#[derive(Clone, Debug, Default)]
+pub struct CertPins {
+    pins: HashMap<String, Vec<String>>,
+}
+
+impl CertPins {
+    /// Parse pins like `host=<pin>,<pin>;other-host=<pin>`
+    pub fn parse(pins: &str) -> anyhow::Result<Self> {
+        let mut parsed = Self::default();
+        for host_pins in pins.split(';').map(str::trim).filter(|s| !s.is_empty()) {
+            let (host, pins) = host_pins
+                .split_once('=')
+                .context(format!("Missing pins for {}", host_pins))?;
+            for pin in pins.split(',').map(str::trim) {
+                if BASE64.decode(pin).map_or(true, |hash| hash.len() != 32) {
+                    bail!("Invalid pin {} for {}", pin, host);
+                }
+                parsed.add(host.trim(), pin);
+            }
+        }
+        Ok(parsed)
+    }
+
+    /// The pins built into the updater
+    pub fn builtin() -> anyhow::Result<Self> {
+        match BUILTIN_CERT_PINS {
+            Some(pins) => Self::parse(pins).context("Failed to parse the built in pins"),
+            None => Ok(Self::default()),
+        }
+    }
+
+    /// Pin the key with the pin `pin` for `host`
+    pub fn add(&mut self, host: &str, pin: &str) {
+        self.pins
+            .entry(host.to_ascii_lowercase())
+            .or_default()
+            .push(pin.to_string());
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pins.is_empty()
+    }
+
+    /// TLS config which checks server certificates against `roots` and then
+    /// against the pins
+    pub fn tls_config(&self, roots: RootCertStore) -> ClientConfig {
+        ClientConfig::builder()
+            .with_safe_defaults()
+            .with_custom_certificate_verifier(Arc::new(PinnedVerifier {
+                inner: WebPkiVerifier::new(roots, None),
+                pins: self.clone(),
+            }))
+            .with_no_client_auth()
+    }
+
+    /// Whether `host` may be connected to with `chain`
+    fn allows(&self, host: &str, chain: &[&Certificate]) -> bool {
+        let pins = match self.pins.get(&host.to_ascii_lowercase()) {
+            Some(pins) => pins,
+            None => return true,
+        };
+        chain
+            .iter()
+            .filter_map(|certificate| spki_pin(&certificate.0))
+            .any(|pin| pins.contains(&pin))
+    }
+}
+
+/// The root certificates trusted by the updater when no pins are involved
+pub fn default_roots() -> RootCertStore {
+    let mut roots = RootCertStore::empty();
+    roots.add_trust_anchors(webpki_roots::TLS_SERVER_ROOTS.iter().map(|anchor| {
+        OwnedTrustAnchor::from_subject_spki_name_constraints(
+            anchor.subject,
+            anchor.spki,
+            anchor.name_constraints,
+        )
+    }));
+    roots
+}
+
+/// Verifies certificates as usual and then checks their keys against the pins
+struct PinnedVerifier {
+    inner: WebPkiVerifier,
+    pins: CertPins,
+}
+
+impl ServerCertVerifier for PinnedVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &Certificate,
+        intermediates: &[Certificate],
+        server_name: &ServerName,
+        scts: &mut dyn Iterator<Item = &[u8]>,
+        ocsp_response: &[u8],
+        now: SystemTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        let verified = self.inner.verify_server_cert(
+            end_entity,
+            intermediates,
+            server_name,
+            scts,
+            ocsp_response,
+            now,
+        )?;
+
+        if let ServerName::DnsName(host) = server_name {
+            let chain: Vec<&Certificate> =
+                std::iter::once(end_entity).chain(intermediates).collect();
+            if !self.pins.allows(host.as_ref(), &chain) {
+                return Err(rustls::Error::General(format!(
+                    "The certificate of {} doesn't match its pins",
+                    host.as_ref()
+                )));
+            }
+        }
+        Ok(verified)
+    }
+}
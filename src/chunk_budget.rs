@@ -0,0 +1,36 @@
+use std::sync::Arc;
+
+use tokio::sync::{Semaphore, SemaphorePermit};
+
+/// A process-wide budget of chunk bytes that may be held in memory at once.
+///
+/// Every clone task acquires from the same budget before holding a chunk so
+/// the peak memory usage is bounded regardless of how many files are being
+/// cloned concurrently.
+#[derive(Clone, Debug)]
+pub struct ChunkBudget {
+    semaphore: Arc<Semaphore>,
+    max_bytes: u32,
+}
+
+impl ChunkBudget {
+    pub fn new(max_bytes: usize) -> Self {
+        let max_bytes = max_bytes.clamp(1, u32::MAX as usize) as u32;
+        Self {
+            semaphore: Arc::new(Semaphore::new(max_bytes as usize)),
+            max_bytes,
+        }
+    }
+
+    /// Reserve `size` bytes from the budget, waiting until they are available.
+    ///
+    /// Requests larger than the whole budget reserve the entire budget so a
+    /// single large chunk can never deadlock the clone.
+    pub async fn acquire(&self, size: usize) -> SemaphorePermit<'_> {
+        let bytes = size.min(self.max_bytes as usize) as u32;
+        self.semaphore
+            .acquire_many(bytes)
+            .await
+            .expect("Chunk budget semaphore should never be closed")
+    }
+}
@@ -0,0 +1,141 @@
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context};
+use clap::Command;
+use toml::value::{Table, Value};
+
+use crate::config_dir;
+
+/// Name of the file with defaults for the command line options
+pub const CONFIG_FILE_NAME: &str = "rose-updater.toml";
+
+/// The config files read on startup in the order they're applied, the one in
+/// the config directory followed by the one next to the executable
+pub fn config_file_paths() -> Vec<PathBuf> {
+    let exe_dir = std::env::current_exe()
+        .ok()
+        .and_then(|exe| exe.parent().map(Path::to_path_buf));
+    config_dir()
+        .into_iter()
+        .chain(exe_dir)
+        .map(|dir| dir.join(CONFIG_FILE_NAME))
+        .collect()
+}
+
+/// Defaults for the command line options, keyed by the option names without
+/// the leading dashes, e.g. `url = "https://example.com"`. Options given on
+/// the command line override the values of the file.
+#[derive(Clone, Debug, Default)]
+pub struct ConfigFile {
+    values: Table,
+}
+
+impl ConfigFile {
+    pub fn parse(contents: &str) -> anyhow::Result<Self> {
+        let values: Table = toml::from_str(contents)?;
+        Ok(Self {
+            values: values
+                .into_iter()
+                .map(|(key, value)| (key.replace('-', "_"), value))
+                .collect(),
+        })
+    }
+
+    /// Read and merge the config files at `paths`, skipping those which don't
+    /// exist. Values of later files replace those of earlier ones.
+    pub fn load(paths: &[PathBuf]) -> anyhow::Result<Self> {
+        let mut config = Self::default();
+        for path in paths {
+            let contents = match std::fs::read_to_string(path) {
+                Ok(contents) => contents,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(e.into()),
+            };
+            let file = Self::parse(&contents)
+                .context(format!("Failed to read the config file {}", path.display()))?;
+            config.merge(file);
+        }
+        Ok(config)
+    }
+
+    /// Replace the values of this file with those of `other`
+    pub fn merge(&mut self, other: ConfigFile) {
+        self.values.extend(other.values);
+    }
+
+    /// Apply the file to `command` and the command line `args`.
+    ///
+    /// Values become the defaults of their options so they're only used when
+    /// the option isn't on the command line. Flags set to `true` are added to
+    /// `args` unless they're already there.
+    pub fn apply<'help>(
+        &self,
+        mut command: Command<'help>,
+        args: Vec<OsString>,
+    ) -> anyhow::Result<(Command<'help>, Vec<OsString>)> {
+        let mut flags = Vec::new();
+        for (key, value) in &self.values {
+            let arg = match command.get_arguments().find(|arg| arg.get_id() == key) {
+                Some(arg) => arg,
+                None => bail!("Unknown option {} in the config file", key),
+            };
+            let id = arg.get_id();
+
+            if !arg.is_takes_value_set() {
+                match (value, arg.get_long()) {
+                    (Value::Boolean(true), Some(long)) => flags.push(format!("--{}", long)),
+                    (Value::Boolean(_), _) => {}
+                    _ => bail!(
+                        "The option {} in the config file has to be true or false",
+                        key
+                    ),
+                }
+                continue;
+            }
+
+            command = match value {
+                Value::Array(values) => {
+                    let values = values
+                        .iter()
+                        .map(|value| option_value(key, value))
+                        .collect::<anyhow::Result<Vec<_>>>()?;
+                    let values: &'help [&'help str] = Box::leak(values.into_boxed_slice());
+                    command.mut_arg(id, |arg| arg.default_values(values))
+                }
+                value => {
+                    let value = option_value(key, value)?;
+                    command.mut_arg(id, |arg| arg.default_value(value))
+                }
+            };
+        }
+
+        let mut args = args.into_iter();
+        let mut merged: Vec<OsString> = args.next().into_iter().collect();
+        let args: Vec<OsString> = args.collect();
+        merged.extend(
+            flags
+                .into_iter()
+                .map(OsString::from)
+                .filter(|flag| !args.contains(flag)),
+        );
+        merged.extend(args);
+        Ok((command, merged))
+    }
+}
+
+/// `value` as it would be written on the command line. The command is only
+/// built once, so the value is leaked to live as long as the command.
+fn option_value(key: &str, value: &Value) -> anyhow::Result<&'static str> {
+    let value = match value {
+        Value::String(value) => value.clone(),
+        Value::Integer(value) => value.to_string(),
+        Value::Float(value) => value.to_string(),
+        Value::Boolean(value) => value.to_string(),
+        Value::Datetime(value) => value.to_string(),
+        Value::Array(_) | Value::Table(_) => {
+            bail!("Unsupported value for {} in the config file", key)
+        }
+    };
+    Ok(Box::leak(value.into_boxed_str()))
+}
@@ -0,0 +1,145 @@
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use tokio::fs;
+use tracing::warn;
+
+/// How often the usage is written to disk while downloading
+const SAVE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Bytes downloaded on a calendar day
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+struct DailyUsage {
+    date: String,
+    bytes: u64,
+}
+
+/// Error returned once the daily download limit has been reached
+#[derive(Debug)]
+pub struct DailyCapReached {
+    pub cap: u64,
+}
+
+impl fmt::Display for DailyCapReached {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "The daily download limit of {} bytes was reached",
+            self.cap
+        )
+    }
+}
+
+impl std::error::Error for DailyCapReached {}
+
+#[derive(Debug)]
+struct DailyCapState {
+    usage: DailyUsage,
+    last_saved: Instant,
+}
+
+/// A limit on the number of bytes downloaded per calendar day, shared between
+/// all concurrent clones and persisted across runs so a capped update can be
+/// resumed the next day.
+#[derive(Clone, Debug)]
+pub struct DailyCap {
+    cap: u64,
+    path: PathBuf,
+    state: Arc<Mutex<DailyCapState>>,
+    /// Held while writing the state file so writes land in order
+    writing: Arc<tokio::sync::Mutex<()>>,
+}
+
+fn today() -> String {
+    chrono::Local::now().date_naive().to_string()
+}
+
+impl DailyCap {
+    /// Load today's usage from the state file at `path`
+    pub fn load(path: &Path, cap: u64) -> Self {
+        let usage = std::fs::read(path)
+            .ok()
+            .and_then(|contents| serde_json::from_slice::<DailyUsage>(&contents).ok())
+            .unwrap_or_default();
+
+        Self {
+            cap,
+            path: path.to_path_buf(),
+            state: Arc::new(Mutex::new(DailyCapState {
+                usage,
+                last_saved: Instant::now(),
+            })),
+            writing: Arc::new(tokio::sync::Mutex::new(())),
+        }
+    }
+
+    pub fn cap(&self) -> u64 {
+        self.cap
+    }
+
+    fn usage(state: &mut DailyCapState) -> &mut DailyUsage {
+        let today = today();
+        if state.usage.date != today {
+            state.usage = DailyUsage {
+                date: today,
+                bytes: 0,
+            };
+        }
+        &mut state.usage
+    }
+
+    /// Check that more data may be downloaded today
+    pub fn check(&self) -> Result<(), DailyCapReached> {
+        let mut state = self.state.lock().unwrap();
+        if Self::usage(&mut state).bytes >= self.cap {
+            return Err(DailyCapReached { cap: self.cap });
+        }
+        Ok(())
+    }
+
+    pub fn is_reached(&self) -> bool {
+        self.check().is_err()
+    }
+
+    /// Record `size` downloaded bytes
+    pub async fn add(&self, size: usize) {
+        let save = {
+            let mut state = self.state.lock().unwrap();
+            Self::usage(&mut state).bytes += size as u64;
+
+            let save = state.last_saved.elapsed() >= SAVE_INTERVAL;
+            if save {
+                state.last_saved = Instant::now();
+            }
+            save
+        };
+
+        if save {
+            if let Err(e) = self.save().await {
+                warn!(error =? e, "Failed to save the daily download usage");
+            }
+        }
+    }
+
+    /// Write the current usage to the state file
+    pub async fn save(&self) -> anyhow::Result<()> {
+        // The usage is read once the previous write finished so an older
+        // usage never replaces a newer one
+        let _writing = self.writing.lock().await;
+        let usage = self.state.lock().unwrap().usage.clone();
+
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent).await?;
+        }
+        fs::write(&self.path, serde_json::to_vec(&usage)?)
+            .await
+            .context(format!(
+                "Failed to write the daily download usage to {}",
+                self.path.display()
+            ))
+    }
+}
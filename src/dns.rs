@@ -0,0 +1,261 @@
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use anyhow::bail;
+use reqwest::dns::{Addrs, Name, Resolve, Resolving};
+use serde::Deserialize;
+use tracing::warn;
+
+/// How long to wait for a DNS over HTTPS lookup before falling back to the
+/// system resolver
+const DOH_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// User agent sent with every request so updater traffic can be told apart in
+/// the server logs
+pub const USER_AGENT: &str = concat!("rose-updater/", env!("CARGO_PKG_VERSION"));
+
+/// Record type of IPv4 addresses
+const DNS_TYPE_A: u16 = 1;
+
+/// Resolver used for the host names of the update servers
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DnsResolver {
+    /// The resolver configured in the operating system
+    System,
+
+    /// Cloudflare's DNS over HTTPS service at 1.1.1.1
+    Cloudflare,
+
+    /// Google's DNS over HTTPS service at 8.8.8.8
+    Google,
+}
+
+impl DnsResolver {
+    /// Create a client builder which sends the updater's [`USER_AGENT`] and
+    /// resolves host names with this resolver, reusing looked up addresses for
+    /// `cache_ttl`. The system resolver is used if the DNS over HTTPS client
+    /// can't be created.
+    pub fn client_builder(self, cache_ttl: Duration) -> reqwest::ClientBuilder {
+        let builder = reqwest::Client::builder().user_agent(USER_AGENT);
+        let doh = match self {
+            DnsResolver::System => return builder,
+            DnsResolver::Cloudflare => DohResolver::cloudflare(),
+            DnsResolver::Google => DohResolver::google(),
+        };
+        let doh = match doh {
+            Ok(doh) => doh,
+            Err(e) => {
+                warn!(
+                    resolver =? self,
+                    error =? e,
+                    "Failed to create the DNS over HTTPS resolver, using the system resolver"
+                );
+                return builder;
+            }
+        };
+        builder.dns_resolver(Arc::new(CachingResolver::new(
+            FallbackResolver::new(doh, SystemResolver),
+            cache_ttl,
+        )))
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct DohAnswer {
+    #[serde(rename = "type")]
+    record_type: u16,
+    data: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct DohResponse {
+    #[serde(rename = "Status")]
+    status: u32,
+    #[serde(rename = "Answer", default)]
+    answer: Vec<DohAnswer>,
+}
+
+/// Resolves host names with a DNS over HTTPS JSON API, bypassing the DNS
+/// servers of the local network
+#[derive(Clone, Debug)]
+pub struct DohResolver {
+    client: reqwest::Client,
+    endpoint: &'static str,
+}
+
+impl DohResolver {
+    /// Create a resolver for the JSON API at `endpoint` on `host`, which is
+    /// connected to at `addr` so the resolver's own name isn't looked up.
+    fn new(host: &str, addr: SocketAddr, endpoint: &'static str) -> anyhow::Result<Self> {
+        let client = reqwest::Client::builder()
+            .user_agent(USER_AGENT)
+            .resolve(host, addr)
+            .build()?;
+        Ok(Self { client, endpoint })
+    }
+
+    pub fn cloudflare() -> anyhow::Result<Self> {
+        Self::new(
+            "cloudflare-dns.com",
+            SocketAddr::from(([1, 1, 1, 1], 443)),
+            "https://cloudflare-dns.com/dns-query",
+        )
+    }
+
+    pub fn google() -> anyhow::Result<Self> {
+        Self::new(
+            "dns.google",
+            SocketAddr::from(([8, 8, 8, 8], 443)),
+            "https://dns.google/resolve",
+        )
+    }
+
+    /// Look up the IPv4 addresses of `name`
+    pub async fn lookup(&self, name: &str) -> anyhow::Result<Vec<IpAddr>> {
+        let response: DohResponse = self
+            .client
+            .get(self.endpoint)
+            .query(&[("name", name), ("type", "A")])
+            .header(reqwest::header::ACCEPT, "application/dns-json")
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        if response.status != 0 {
+            bail!(
+                "Lookup of {} failed with DNS status {}",
+                name,
+                response.status
+            );
+        }
+
+        let addrs: Vec<IpAddr> = response
+            .answer
+            .iter()
+            .filter(|answer| answer.record_type == DNS_TYPE_A)
+            .filter_map(|answer| answer.data.parse().ok())
+            .collect();
+        if addrs.is_empty() {
+            bail!("No addresses found for {}", name);
+        }
+        Ok(addrs)
+    }
+}
+
+impl Resolve for DohResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        let resolver = self.clone();
+        Box::pin(async move {
+            let addrs = resolver.lookup(name.as_str()).await?;
+            let addrs: Addrs = Box::new(addrs.into_iter().map(|ip| SocketAddr::new(ip, 0)));
+            Ok(addrs)
+        })
+    }
+}
+
+/// Resolves host names with the resolver configured in the operating system
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemResolver;
+
+impl Resolve for SystemResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        Box::pin(async move {
+            let addrs: Vec<SocketAddr> =
+                tokio::net::lookup_host((name.as_str(), 0)).await?.collect();
+            let addrs: Addrs = Box::new(addrs.into_iter());
+            Ok(addrs)
+        })
+    }
+}
+
+/// Resolves host names with `primary`, retrying through `fallback` when the
+/// primary lookup fails or times out, e.g. on networks which block 1.1.1.1
+#[derive(Debug)]
+pub struct FallbackResolver<P, F> {
+    primary: P,
+    fallback: Arc<F>,
+}
+
+impl<P, F> FallbackResolver<P, F> {
+    pub fn new(primary: P, fallback: F) -> Self {
+        Self {
+            primary,
+            fallback: Arc::new(fallback),
+        }
+    }
+}
+
+impl<P: Resolve, F: Resolve + 'static> Resolve for FallbackResolver<P, F> {
+    fn resolve(&self, name: Name) -> Resolving {
+        let primary = self.primary.resolve(name.clone());
+        let fallback = self.fallback.clone();
+        Box::pin(async move {
+            match tokio::time::timeout(DOH_TIMEOUT, primary).await {
+                Ok(Ok(addrs)) => return Ok(addrs),
+                Ok(Err(e)) => warn!(
+                    name = name.as_str(),
+                    error =? e,
+                    "DNS lookup failed, falling back to the system resolver"
+                ),
+                Err(_) => warn!(
+                    name = name.as_str(),
+                    "DNS lookup timed out, falling back to the system resolver"
+                ),
+            }
+            fallback.resolve(name).await
+        })
+    }
+}
+
+/// Resolves host names with `inner`, remembering the addresses of each name
+/// for `ttl` so every request to the same server doesn't look it up again
+#[derive(Debug)]
+pub struct CachingResolver<R> {
+    inner: R,
+    ttl: Duration,
+    cache: Arc<Mutex<HashMap<String, (Instant, Vec<SocketAddr>)>>>,
+}
+
+impl<R> CachingResolver<R> {
+    pub fn new(inner: R, ttl: Duration) -> Self {
+        Self {
+            inner,
+            ttl,
+            cache: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+}
+
+impl<R: Resolve> Resolve for CachingResolver<R> {
+    fn resolve(&self, name: Name) -> Resolving {
+        let host = name.as_str().to_string();
+        {
+            let mut cache = self.cache.lock().unwrap();
+            match cache.get(&host) {
+                Some((resolved_at, addrs)) if resolved_at.elapsed() < self.ttl => {
+                    let addrs: Addrs = Box::new(addrs.clone().into_iter());
+                    return Box::pin(async move { Ok(addrs) });
+                }
+                Some(_) => {
+                    cache.remove(&host);
+                }
+                None => {}
+            }
+        }
+
+        let lookup = self.inner.resolve(name);
+        let cache = self.cache.clone();
+        Box::pin(async move {
+            let addrs: Vec<SocketAddr> = lookup.await?.collect();
+            cache
+                .lock()
+                .unwrap()
+                .insert(host, (Instant::now(), addrs.clone()));
+            let addrs: Addrs = Box::new(addrs.into_iter());
+            Ok(addrs)
+        })
+    }
+}
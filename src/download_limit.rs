@@ -0,0 +1,36 @@
+use std::sync::Arc;
+
+use tokio::sync::{Semaphore, SemaphorePermit};
+
+/// A process-wide limit on the number of files downloaded at once.
+///
+/// Every file clone holds a permit for its whole lifetime so large updates
+/// don't open hundreds of connections at once and get throttled by the CDN.
+#[derive(Clone, Debug)]
+pub struct DownloadLimit {
+    semaphore: Arc<Semaphore>,
+    max_downloads: usize,
+}
+
+impl DownloadLimit {
+    pub fn new(max_downloads: usize) -> Self {
+        let max_downloads = max_downloads.max(1);
+        Self {
+            semaphore: Arc::new(Semaphore::new(max_downloads)),
+            max_downloads,
+        }
+    }
+
+    pub fn max_downloads(&self) -> usize {
+        self.max_downloads
+    }
+
+    /// Wait until another download may start. The download may continue
+    /// until the permit is dropped.
+    pub async fn acquire(&self) -> SemaphorePermit<'_> {
+        self.semaphore
+            .acquire()
+            .await
+            .expect("Download limit semaphore should never be closed")
+    }
+}
@@ -0,0 +1,34 @@
+use std::path::Path;
+
+use blake2::{Blake2b512, Digest};
+use tokio::fs::File;
+use tokio::io::AsyncReadExt;
+
+/// Identifier of the algorithm used by [`hash_file`] and for `source_hash` in
+/// the remote manifest
+pub const HASH_ALGORITHM: &str = "blake2b-512";
+
+/// Hash a whole file with the same algorithm bitar uses for the source hash of
+/// an archive, so the result can be compared to `source_hash` in the manifest.
+pub async fn hash_file(path: &Path) -> anyhow::Result<Vec<u8>> {
+    let mut file = File::open(path).await?;
+    let mut hasher = Blake2b512::new();
+    let mut buffer = vec![0; 64 * 1024];
+    loop {
+        let read = file.read(&mut buffer).await?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+    }
+
+    Ok(hasher.finalize().to_vec())
+}
+
+/// Check that the whole file at `path` hashes to `expected_hash`
+///
+/// Every chunk is verified while cloning but this also catches a file that
+/// was assembled wrongly, e.g. by a disk error while reordering.
+pub async fn verify_output_file(path: &Path, expected_hash: &[u8]) -> anyhow::Result<bool> {
+    Ok(hash_file(path).await? == expected_hash)
+}
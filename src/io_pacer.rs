@@ -0,0 +1,39 @@
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// A process-wide limit on how fast cloned data is written to disk.
+///
+/// Every clone task waits on the same pacer after writing a chunk so slow
+/// drives aren't handed more data than they can write, which otherwise fills
+/// the disk queue and stalls the whole system.
+#[derive(Clone, Debug)]
+pub struct IoPacer {
+    bytes_per_sec: u64,
+    next_write: Arc<Mutex<Instant>>,
+}
+
+impl IoPacer {
+    pub fn new(bytes_per_sec: u64) -> Self {
+        Self {
+            bytes_per_sec: bytes_per_sec.max(1),
+            next_write: Arc::new(Mutex::new(Instant::now())),
+        }
+    }
+
+    pub fn bytes_per_sec(&self) -> u64 {
+        self.bytes_per_sec
+    }
+
+    /// Account for `size` bytes being written, waiting until they fit within
+    /// the write rate.
+    pub async fn wait(&self, size: usize) {
+        let write_at = {
+            let mut next_write = self.next_write.lock().unwrap();
+            let write_at = (*next_write).max(Instant::now());
+            *next_write =
+                write_at + Duration::from_secs_f64(size as f64 / self.bytes_per_sec as f64);
+            write_at
+        };
+        tokio::time::sleep_until(write_at.into()).await;
+    }
+}
@@ -0,0 +1,166 @@
+use std::fmt;
+
+type Table = &'static [(&'static str, &'static str)];
+
+/// Every string of the updater window, the fallback for missing translations
+const ENGLISH: Table = &[
+    ("pause", "Pause"),
+    ("resume", "Resume"),
+    ("cancel", "Cancel"),
+    ("close", "Close"),
+    ("retry", "Retry"),
+    ("get_support", "Get Support"),
+    ("use_beta_client", "Use beta client"),
+    ("light_theme", "Light theme"),
+    ("whats_new", "What's new"),
+    ("whats_new_badge", "New"),
+    ("back_to_news", "Back to news"),
+    ("update_failed", "Update failed"),
+    ("view_logs", "View logs"),
+    ("copy_all", "Copy all"),
+    ("logs", "Logs"),
+    ("no_logs", "No log has been written yet."),
+    (
+        "restart_to_switch_clients",
+        "Restart the updater to switch clients",
+    ),
+    ("update_cancelled", "Update cancelled"),
+    ("starting_in", "Starting in {}s"),
+    ("downloading_patch_metadata", "Downloading patch metadata"),
+    ("downloading_file", "Downloading {}"),
+    ("checking_files", "Checking local files"),
+    ("verifying_files", "Verifying files"),
+    ("cleaning_up", "Cleaning up"),
+    ("update_size", "Update is {}"),
+    ("run_summary", "Checked {} files, updated {} ({})"),
+    ("run_summary_failed", ", {} failed"),
+    (
+        "launch_failed",
+        "The game closed unexpectedly while starting (exit code {}).",
+    ),
+    (
+        "update_error",
+        "An error was detected while updating:\nError: {}",
+    ),
+];
+
+const GERMAN: Table = &[
+    ("pause", "Pausieren"),
+    ("resume", "Fortsetzen"),
+    ("cancel", "Abbrechen"),
+    ("close", "Schließen"),
+    ("retry", "Wiederholen"),
+    ("get_support", "Support"),
+    ("use_beta_client", "Beta-Client verwenden"),
+    ("light_theme", "Helles Design"),
+    ("whats_new", "Was ist neu"),
+    ("whats_new_badge", "Neu"),
+    ("back_to_news", "Zurück zu den News"),
+    ("update_failed", "Update fehlgeschlagen"),
+    ("view_logs", "Logs anzeigen"),
+    ("copy_all", "Alles kopieren"),
+    ("logs", "Logs"),
+    ("no_logs", "Es wurde noch kein Log geschrieben."),
+    (
+        "restart_to_switch_clients",
+        "Starte den Updater neu, um den Client zu wechseln",
+    ),
+    ("update_cancelled", "Update abgebrochen"),
+    ("starting_in", "Start in {}s"),
+    (
+        "downloading_patch_metadata",
+        "Lade Patch-Metadaten herunter",
+    ),
+    ("downloading_file", "Lade {} herunter"),
+    ("checking_files", "Prüfe lokale Dateien"),
+    ("verifying_files", "Überprüfe Dateien"),
+    ("cleaning_up", "Räume auf"),
+    ("update_size", "Das Update ist {} groß"),
+    ("run_summary", "{} Dateien geprüft, {} aktualisiert ({})"),
+    ("run_summary_failed", ", {} fehlgeschlagen"),
+    (
+        "launch_failed",
+        "Das Spiel wurde beim Starten unerwartet beendet (Exit-Code {}).",
+    ),
+    (
+        "update_error",
+        "Beim Update ist ein Fehler aufgetreten:\nFehler: {}",
+    ),
+];
+
+/// Language code and strings of every language the updater ships with
+const LANGUAGES: &[(&str, Table)] = &[("en", ENGLISH), ("de", GERMAN)];
+
+/// Strings shown in the updater window in the language of the player
+#[derive(Clone, Copy)]
+pub struct Strings {
+    lang: &'static str,
+    table: Table,
+}
+
+impl Strings {
+    /// Strings of the language of `locale`, e.g. `de`, `de-DE` or
+    /// `de_DE.UTF-8`. Languages the updater doesn't ship with use English.
+    pub fn for_locale(locale: &str) -> Self {
+        let lang = locale
+            .split(|c: char| c == '-' || c == '_' || c == '.')
+            .next()
+            .unwrap_or_default()
+            .to_ascii_lowercase();
+        LANGUAGES
+            .iter()
+            .find(|(code, _)| *code == lang)
+            .map(|&(lang, table)| Self { lang, table })
+            .unwrap_or_default()
+    }
+
+    /// Strings of the language the system is set to
+    pub fn system() -> Self {
+        sys_locale::get_locale()
+            .map(|locale| Self::for_locale(&locale))
+            .unwrap_or_default()
+    }
+
+    /// Code of the language, e.g. `en`
+    pub fn lang(&self) -> &'static str {
+        self.lang
+    }
+
+    /// The string for `key`, the English one if it isn't translated and the
+    /// key itself if there's no such string at all
+    pub fn get(&self, key: &'static str) -> &'static str {
+        let lookup = |table: Table| table.iter().find(|(k, _)| *k == key).map(|&(_, text)| text);
+        lookup(self.table)
+            .or_else(|| lookup(ENGLISH))
+            .unwrap_or(key)
+    }
+
+    /// The string for `key` with every `{}` replaced by the next of `args`
+    pub fn format(&self, key: &'static str, args: &[&dyn fmt::Display]) -> String {
+        let mut parts = self.get(key).split("{}");
+        let mut text = parts.next().unwrap_or_default().to_string();
+        let mut args = args.iter();
+        for part in parts {
+            if let Some(arg) = args.next() {
+                text += &arg.to_string();
+            }
+            text += part;
+        }
+        text
+    }
+}
+
+impl Default for Strings {
+    fn default() -> Self {
+        Self {
+            lang: "en",
+            table: ENGLISH,
+        }
+    }
+}
+
+impl fmt::Debug for Strings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("Strings").field(&self.lang).finish()
+    }
+}
@@ -1,7 +1,70 @@
+pub mod archive;
+pub mod atomic_write;
 pub mod bitar_ext;
+pub mod cert_pins;
+pub mod chunk_budget;
+pub mod config_file;
+pub mod daily_cap;
+pub mod dns;
+pub mod download_limit;
+pub mod hash;
+pub mod io_pacer;
+pub mod lang;
 pub mod launch_button;
+pub mod log_buffer;
+pub mod log_files;
 pub mod manifest;
+pub mod news;
+pub mod ownership;
+pub mod patch;
+pub mod pause;
+pub mod preflight;
 pub mod progress_bar;
+pub mod progress_events;
+pub mod progress_state;
+pub mod prune;
+pub mod range_support;
+pub mod rate_limiter;
+pub mod retry;
+pub mod running;
+pub mod self_update;
+pub mod settings;
+pub mod speed;
+pub mod style;
+pub mod symlinks;
+pub mod update;
+pub mod window_size;
 
+pub use archive::*;
+pub use atomic_write::*;
 pub use bitar_ext::*;
+pub use cert_pins::*;
+pub use chunk_budget::*;
+pub use config_file::*;
+pub use daily_cap::*;
+pub use dns::*;
+pub use download_limit::*;
+pub use hash::*;
+pub use io_pacer::*;
+pub use lang::*;
+pub use log_buffer::*;
+pub use log_files::*;
 pub use manifest::*;
+pub use news::*;
+pub use ownership::*;
+pub use patch::*;
+pub use pause::*;
+pub use preflight::*;
+pub use progress_events::*;
+pub use progress_state::*;
+pub use prune::*;
+pub use range_support::*;
+pub use rate_limiter::*;
+pub use retry::*;
+pub use running::*;
+pub use self_update::*;
+pub use settings::*;
+pub use speed::*;
+pub use symlinks::*;
+pub use update::*;
+pub use window_size::*;
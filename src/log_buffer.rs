@@ -0,0 +1,84 @@
+use std::collections::VecDeque;
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+
+#[derive(Default)]
+struct LogBufferState {
+    lines: VecDeque<String>,
+    partial: String,
+    version: u64,
+}
+
+/// Keeps the most recent log lines in memory so they can be shown in the UI
+#[derive(Clone)]
+pub struct LogBuffer {
+    capacity: usize,
+    state: Arc<Mutex<LogBufferState>>,
+}
+
+impl LogBuffer {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            state: Arc::new(Mutex::new(LogBufferState::default())),
+        }
+    }
+
+    /// Number which changes every time a line is added
+    pub fn version(&self) -> u64 {
+        self.state.lock().unwrap().version
+    }
+
+    /// The buffered lines joined by newlines
+    pub fn text(&self) -> String {
+        let state = self.state.lock().unwrap();
+        let mut text = String::new();
+        for line in &state.lines {
+            text.push_str(line);
+            text.push('\n');
+        }
+        text
+    }
+
+    /// Create a writer which writes to stdout as well as the buffer
+    pub fn writer(&self) -> LogWriter {
+        LogWriter {
+            buffer: self.clone(),
+        }
+    }
+
+    fn push(&self, buf: &[u8]) {
+        let mut state = self.state.lock().unwrap();
+        state.partial.push_str(&String::from_utf8_lossy(buf));
+
+        while let Some(end) = state.partial.find('\n') {
+            let line = state.partial[..end].trim_end().to_string();
+            state.partial.drain(..=end);
+            state.lines.push_back(line);
+            if state.lines.len() > self.capacity {
+                state.lines.pop_front();
+            }
+            state.version += 1;
+        }
+    }
+}
+
+/// Writes log output to stdout and a [`LogBuffer`]
+pub struct LogWriter {
+    buffer: LogBuffer,
+}
+
+impl Write for LogWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        // There's no console to write to when running as a windows app so
+        // failures to write to stdout are ignored
+        let _ = std::io::stdout().write_all(buf);
+        self.buffer.push(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        let _ = std::io::stdout().flush();
+        Ok(())
+    }
+}
@@ -0,0 +1,69 @@
+use std::path::{Path, PathBuf};
+
+use crate::project_dirs;
+
+/// Prefix of the log files, each of which is suffixed with its day, e.g.
+/// `rose-updater.log.2024-05-01`
+pub const LOG_FILE_PREFIX: &str = "rose-updater.log";
+
+/// Number of daily log files kept, older ones are deleted on startup
+pub const MAX_LOG_FILES: usize = 7;
+
+/// The directory the updater writes its logs to. Falls back to a directory
+/// within the system's temporary directory if there is no data directory so
+/// the updater still logs on locked down systems.
+pub fn log_dir() -> PathBuf {
+    project_dirs()
+        .map(|dirs| dirs.data_local_dir().join("logs"))
+        .unwrap_or_else(|| std::env::temp_dir().join("rose-updater").join("logs"))
+}
+
+/// Every log file in `dir`, oldest first
+pub fn log_files(dir: &Path) -> std::io::Result<Vec<PathBuf>> {
+    let prefix = format!("{}.", LOG_FILE_PREFIX);
+    let mut files = Vec::new();
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        let is_log = entry
+            .file_name()
+            .to_str()
+            .is_some_and(|name| name.starts_with(&prefix));
+        if is_log && entry.file_type()?.is_file() {
+            files.push(entry.path());
+        }
+    }
+
+    // The day in the name sorts the same as the date
+    files.sort();
+    Ok(files)
+}
+
+/// Delete all but the newest `keep` log files in `dir`, returning how many
+/// were deleted
+pub fn prune_log_files(dir: &Path, keep: usize) -> std::io::Result<usize> {
+    let files = log_files(dir)?;
+    let excess = files.len().saturating_sub(keep);
+    for file in &files[..excess] {
+        std::fs::remove_file(file)?;
+    }
+    Ok(excess)
+}
+
+/// The newest log file in `dir` and its last `max_lines` lines, `None` if no
+/// log has been written yet
+pub fn read_log_tail(dir: &Path, max_lines: usize) -> std::io::Result<Option<(PathBuf, String)>> {
+    let path = match log_files(dir) {
+        Ok(mut files) => match files.pop() {
+            Some(path) => path,
+            None => return Ok(None),
+        },
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e),
+    };
+
+    let contents = std::fs::read(&path)?;
+    let contents = String::from_utf8_lossy(&contents);
+    let lines: Vec<&str> = contents.lines().collect();
+    let start = lines.len().saturating_sub(max_lines);
+    Ok(Some((path, lines[start..].join("\n"))))
+}
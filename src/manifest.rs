@@ -1,10 +1,195 @@
+use std::collections::BTreeSet;
+use std::fmt;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context};
+use blake2::{Blake2b512, Digest};
+use path_slash::PathBufExt;
+use reqwest::Url;
 use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tracing::{info, warn};
+
+use crate::{write_atomic, HASH_ALGORITHM};
+
+/// Version of the local manifest written by this updater
+pub const LOCAL_MANIFEST_VERSION: usize = 1;
 
 #[derive(Serialize, Deserialize, Clone, Debug, Default)]
 pub struct RemoteManifest {
     pub version: usize,
     pub updater: RemoteManifestFileEntry,
     pub files: Vec<RemoteManifestFileEntry>,
+
+    /// Symlinks recreated on unix clients, recorded when archiving with
+    /// `--symlinks store`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub symlinks: Vec<RemoteManifestSymlink>,
+
+    /// Command used to launch the game after updating. Clients only use it if
+    /// the executable is one they allow.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub launch: Option<LaunchCommand>,
+
+    /// CPU architecture the client is built for, in the form of
+    /// `std::env::consts::ARCH` (e.g. `x86_64`). Updaters built for another
+    /// architecture refuse to update the client.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub arch: Option<String>,
+
+    /// Algorithm the archives were compressed with, e.g. `zstd`. Not recorded
+    /// in manifests written before the algorithm could be chosen.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub compression: Option<String>,
+
+    /// Level the archives were compressed with. Not recorded in manifests
+    /// written before the level was.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub compression_level: Option<u32>,
+
+    /// Hash covering every other field, see [`RemoteManifest::compute_files_hash`]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub files_hash: Option<Vec<u8>>,
+
+    /// Algorithm used for the `source_hash` of every entry. Manifests written
+    /// before this was recorded always used [`HASH_ALGORITHM`].
+    #[serde(default = "default_hash_algorithm")]
+    pub hash_algorithm: String,
+
+    /// Sum of the `source_size` of every entry including the updater, so the
+    /// size of the client is known before any archive is read. Zero in
+    /// manifests written before this was recorded.
+    #[serde(default)]
+    pub total_source_size: usize,
+
+    /// Number of the release, increased with every build of the client.
+    /// Clients refuse to update to a lower release than the one installed.
+    /// Zero in manifests written before this was recorded.
+    #[serde(default)]
+    pub release: u64,
+}
+
+fn default_hash_algorithm() -> String {
+    HASH_ALGORITHM.to_string()
+}
+
+impl RemoteManifest {
+    /// Hash every field of the manifest except `files_hash` itself, with the
+    /// updater and file entries sorted by their source path
+    ///
+    /// This lets a manifest with an added, removed or altered entry be
+    /// detected even if every individual entry looks valid.
+    pub fn compute_files_hash(&self) -> Vec<u8> {
+        // Destructured so a new field can't be left out of the hash
+        let RemoteManifest {
+            version,
+            updater,
+            files,
+            symlinks,
+            launch,
+            arch,
+            compression,
+            compression_level,
+            files_hash: _,
+            hash_algorithm,
+            total_source_size,
+            release,
+        } = self;
+
+        let mut entries: Vec<&RemoteManifestFileEntry> =
+            std::iter::once(updater).chain(files).collect();
+        entries.sort_by(|a, b| a.source_path.cmp(&b.source_path));
+
+        // Every field is prefixed with its length and every list with its
+        // number of items so the boundaries between fields can't be shifted
+        // without changing the hash. Optional fields are prefixed with
+        // whether they're set.
+        let mut hasher = Blake2b512::new();
+        let mut update = |bytes: &[u8]| {
+            hasher.update((bytes.len() as u64).to_le_bytes());
+            hasher.update(bytes);
+        };
+        update(&(*version as u64).to_le_bytes());
+        for entry in entries {
+            let RemoteManifestFileEntry {
+                path,
+                source_path,
+                source_hash,
+                source_size,
+                fallback_paths,
+                mode,
+                group,
+            } = entry;
+            update(path.as_bytes());
+            update(source_path.as_bytes());
+            update(source_hash);
+            update(&(*source_size as u64).to_le_bytes());
+            update(&(fallback_paths.len() as u64).to_le_bytes());
+            for fallback_path in fallback_paths {
+                update(fallback_path.as_bytes());
+            }
+            update(&[mode.is_some() as u8]);
+            update(&mode.unwrap_or_default().to_le_bytes());
+            update(&[group.is_some() as u8]);
+            update(group.as_deref().unwrap_or_default().as_bytes());
+        }
+
+        let mut symlinks: Vec<&RemoteManifestSymlink> = symlinks.iter().collect();
+        symlinks.sort_by(|a, b| a.path.cmp(&b.path));
+        update(&(symlinks.len() as u64).to_le_bytes());
+        for symlink in symlinks {
+            let RemoteManifestSymlink { path, target } = symlink;
+            update(path.as_bytes());
+            update(target.as_bytes());
+        }
+
+        update(&[launch.is_some() as u8]);
+        if let Some(LaunchCommand { exe, args }) = launch {
+            update(exe.as_bytes());
+            update(&(args.len() as u64).to_le_bytes());
+            for arg in args {
+                update(arg.as_bytes());
+            }
+        }
+        update(&[arch.is_some() as u8]);
+        update(arch.as_deref().unwrap_or_default().as_bytes());
+        update(&[compression.is_some() as u8]);
+        update(compression.as_deref().unwrap_or_default().as_bytes());
+        update(&[compression_level.is_some() as u8]);
+        update(&compression_level.unwrap_or_default().to_le_bytes());
+        update(hash_algorithm.as_bytes());
+        update(&(*total_source_size as u64).to_le_bytes());
+        update(&release.to_le_bytes());
+
+        hasher.finalize().to_vec()
+    }
+
+    /// Names of the optional content groups in the manifest
+    pub fn groups(&self) -> BTreeSet<&str> {
+        self.files
+            .iter()
+            .filter_map(|entry| entry.group.as_deref())
+            .collect()
+    }
+
+    /// Check the entries against `files_hash` if the manifest has one
+    pub fn verify_files_hash(&self) -> anyhow::Result<()> {
+        if let Some(files_hash) = &self.files_hash {
+            if *files_hash != self.compute_files_hash() {
+                bail!("The manifest is corrupt, its entries don't match its hash");
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Executable and arguments used to start the game
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct LaunchCommand {
+    /// Path of the executable relative to the executable directory
+    pub exe: String,
+    pub args: Vec<String>,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, Default)]
@@ -13,6 +198,208 @@ pub struct RemoteManifestFileEntry {
     pub source_path: String,
     pub source_hash: Vec<u8>,
     pub source_size: usize,
+
+    /// Alternative locations of the archive which are tried in order when the
+    /// archive at `path` can't be cloned. Relative paths are resolved against
+    /// the remote URL in the same way as `path`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub fallback_paths: Vec<String>,
+
+    /// Unix permission bits of the source file, `None` when archived on
+    /// Windows. Only applied on unix.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mode: Option<u32>,
+
+    /// Optional content group the file belongs to, e.g. `hd-textures`. Files
+    /// without a group are core files which are always installed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub group: Option<String>,
+}
+
+impl RemoteManifestFileEntry {
+    /// Whether the file should be installed when only `groups` are selected
+    pub fn in_groups(&self, groups: &[String]) -> bool {
+        self.group
+            .as_ref()
+            .is_none_or(|group| groups.contains(group))
+    }
+
+    /// URLs of the archive starting with the primary location followed by
+    /// any fallback locations
+    pub fn archive_urls(&self, remote_url: &Url) -> anyhow::Result<Vec<Url>> {
+        std::iter::once(&self.path)
+            .chain(&self.fallback_paths)
+            .map(|path| Ok(remote_url.join(path)?))
+            .collect()
+    }
+
+    /// Local path of the source file within the `output` directory
+    ///
+    /// Source paths are always stored as UTF-8 with `/` separators so they are
+    /// converted to the platform's separators here.
+    pub fn local_path(&self, output: &Path) -> PathBuf {
+        output.join(PathBuf::from_slash(&self.source_path))
+    }
+}
+
+/// Symlink within the client, both paths are stored with `/` separators
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct RemoteManifestSymlink {
+    /// Path of the link relative to the client directory
+    pub path: String,
+    /// Target of the link relative to the directory containing the link
+    pub target: String,
+}
+
+impl RemoteManifestSymlink {
+    /// Local path of the link within the `output` directory
+    pub fn local_path(&self, output: &Path) -> PathBuf {
+        output.join(PathBuf::from_slash(&self.path))
+    }
+
+    /// Whether both the link and its target are within the client directory
+    ///
+    /// Only the path components are checked, so a target pointing through
+    /// another link is only contained if that link is contained as well.
+    pub fn is_contained(&self) -> bool {
+        if self.path.starts_with('/') || self.target.starts_with('/') {
+            return false;
+        }
+        if self
+            .path
+            .split('/')
+            .any(|component| component.is_empty() || component == "." || component == "..")
+        {
+            return false;
+        }
+
+        // Start in the directory containing the link
+        let mut depth = self.path.split('/').count() - 1;
+        for component in self.target.split('/') {
+            match component {
+                "" | "." => {}
+                ".." => match depth.checked_sub(1) {
+                    Some(parent_depth) => depth = parent_depth,
+                    None => return false,
+                },
+                _ => depth += 1,
+            }
+        }
+        true
+    }
+}
+
+/// Name of the copy of the last remote manifest which was downloaded, kept in
+/// the updater directory of the manifest's host
+pub const REMOTE_MANIFEST_CACHE_NAME: &str = "remote_manifest.json";
+
+/// Copy of the last remote manifest downloaded from `url`, reused while the
+/// server reports that its `ETag` is unchanged
+#[derive(Serialize, Deserialize)]
+struct CachedManifest {
+    url: String,
+    etag: String,
+    manifest: RemoteManifest,
+}
+
+fn read_cached_manifest(cache_path: &Path, url: &Url) -> Option<CachedManifest> {
+    let contents = std::fs::read(cache_path).ok()?;
+    let cached: CachedManifest = serde_json::from_slice(&contents).ok()?;
+    (cached.url == url.as_str()).then_some(cached)
+}
+
+fn save_cached_manifest(cache_path: &Path, cached: &CachedManifest) -> anyhow::Result<()> {
+    if let Some(parent) = cache_path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    write_atomic(cache_path, |writer| {
+        Ok(serde_json::to_writer(writer, cached)?)
+    })
+}
+
+/// Download and parse the remote manifest at `url` using `client`
+///
+/// The manifest is requested with gzip or brotli compression and decompressed
+/// according to the `Content-Encoding` of the response, so servers which
+/// don't compress it still work. When `bypass_cache` is set any caches
+/// between us and the server are asked for a fresh copy.
+///
+/// Manifests with an `ETag` are kept at `cache_path`. The next download asks
+/// the server for the manifest only if it changed and uses the kept copy when
+/// it didn't, unless `bypass_cache` is set.
+pub async fn download_remote_manifest(
+    client: &reqwest::Client,
+    url: Url,
+    bypass_cache: bool,
+    cache_path: Option<&Path>,
+) -> anyhow::Result<RemoteManifest> {
+    let cached = match cache_path {
+        Some(cache_path) if !bypass_cache => read_cached_manifest(cache_path, &url),
+        _ => None,
+    };
+
+    let mut request = client
+        .get(url.clone())
+        .header(reqwest::header::ACCEPT_ENCODING, "gzip, br");
+    if bypass_cache {
+        request = request.header(reqwest::header::CACHE_CONTROL, "no-cache");
+    }
+    if let Some(cached) = &cached {
+        request = request.header(reqwest::header::IF_NONE_MATCH, &cached.etag);
+    }
+
+    let response = request.send().await?;
+    if response.status() == reqwest::StatusCode::NOT_MODIFIED {
+        match cached {
+            Some(cached) => {
+                info!("The remote manifest is unchanged, using the cached copy");
+                return Ok(cached.manifest);
+            }
+            None => bail!("The server reported the manifest as unchanged without a cached copy"),
+        }
+    }
+
+    let response = response.error_for_status()?;
+    let etag = response
+        .headers()
+        .get(reqwest::header::ETAG)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_string);
+    let encoding = response
+        .headers()
+        .get(reqwest::header::CONTENT_ENCODING)
+        .and_then(|value| value.to_str().ok())
+        .map(|value| value.trim().to_ascii_lowercase());
+    let body = response.bytes().await?;
+
+    let mut contents = Vec::new();
+    match encoding.as_deref() {
+        Some("gzip") => {
+            flate2::read::GzDecoder::new(&body[..]).read_to_end(&mut contents)?;
+        }
+        Some("br") => {
+            brotli_decompressor::Decompressor::new(&body[..], 4096).read_to_end(&mut contents)?;
+        }
+        None | Some("identity") => contents.extend_from_slice(&body),
+        Some(encoding) => bail!("Unsupported manifest encoding {}", encoding),
+    }
+
+    let manifest: RemoteManifest =
+        serde_json::from_slice(&contents).context("Failed to parse the remote manifest")?;
+
+    if let (Some(cache_path), Some(etag)) = (cache_path, etag) {
+        let cached = CachedManifest {
+            url: url.to_string(),
+            etag,
+            manifest,
+        };
+        if let Err(e) = save_cached_manifest(cache_path, &cached) {
+            warn!(error =? e, "Failed to cache the remote manifest");
+        }
+        return Ok(cached.manifest);
+    }
+
+    Ok(manifest)
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, Default)]
@@ -20,6 +407,10 @@ pub struct LocalManifest {
     pub version: usize,
     pub updater: LocalManifestFileEntry,
     pub files: Vec<LocalManifestFileEntry>,
+
+    /// Release of the last remote manifest the files were updated to
+    #[serde(default)]
+    pub release: u64,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, Default)]
@@ -27,4 +418,107 @@ pub struct LocalManifestFileEntry {
     pub path: String,
     pub hash: Vec<u8>,
     pub size: usize,
+
+    /// Unix permission bits applied to the file
+    #[serde(default)]
+    pub mode: Option<u32>,
+
+    /// Modification time of the file in unix seconds when it was last
+    /// updated or verified
+    #[serde(default)]
+    pub modified_at: Option<i64>,
+}
+
+impl LocalManifestFileEntry {
+    /// Whether the file at `path` was modified since this entry was recorded,
+    /// e.g. by an antivirus restoring it or a manual edit. Entries without a
+    /// modification time are never considered modified.
+    pub fn is_modified(&self, path: &Path) -> bool {
+        match self.modified_at {
+            Some(modified_at) => file_modified_at(path) != Some(modified_at),
+            None => false,
+        }
+    }
+}
+
+/// Modification time of the file at `path` in unix seconds
+pub fn file_modified_at(path: &Path) -> Option<i64> {
+    let modified = std::fs::metadata(path).ok()?.modified().ok()?;
+    let secs = match modified.duration_since(std::time::UNIX_EPOCH) {
+        Ok(duration) => duration.as_secs() as i64,
+        Err(e) => -(e.duration().as_secs() as i64),
+    };
+    Some(secs)
+}
+
+/// Error returned when a local manifest was written by a newer updater
+#[derive(Debug)]
+pub struct UnsupportedManifestVersion {
+    pub version: usize,
+}
+
+impl fmt::Display for UnsupportedManifestVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "The local manifest has version {} but this updater only supports up to version {}. Please update the updater.",
+            self.version, LOCAL_MANIFEST_VERSION
+        )
+    }
+}
+
+impl std::error::Error for UnsupportedManifestVersion {}
+
+/// Parse a local manifest, upgrading manifests written by older updaters to
+/// [`LOCAL_MANIFEST_VERSION`] so their file list is kept.
+///
+/// Returns [`UnsupportedManifestVersion`] for manifests newer than this
+/// updater understands.
+pub fn migrate_local_manifest(mut value: Value) -> anyhow::Result<LocalManifest> {
+    let version = match value.get("version") {
+        Some(version) => version
+            .as_u64()
+            .context("The local manifest version is not a number")?
+            as usize,
+        None => 0,
+    };
+    if version > LOCAL_MANIFEST_VERSION {
+        return Err(UnsupportedManifestVersion { version }.into());
+    }
+
+    if version < 1 {
+        migrate_local_manifest_v0(&mut value)?;
+    }
+
+    serde_json::from_value(value).context("Failed to parse the local manifest")
+}
+
+/// Version 0 manifests had no `version` field, no file sizes and could omit
+/// the updater entry
+fn migrate_local_manifest_v0(value: &mut Value) -> anyhow::Result<()> {
+    let manifest = value
+        .as_object_mut()
+        .context("The local manifest is not an object")?;
+
+    let add_size = |entry: &mut Value| {
+        if let Some(entry) = entry.as_object_mut() {
+            entry.entry("size").or_insert(json!(0));
+        }
+    };
+
+    add_size(
+        manifest
+            .entry("updater")
+            .or_insert_with(|| json!({ "path": "", "hash": [] })),
+    );
+    manifest
+        .entry("files")
+        .or_insert_with(|| json!([]))
+        .as_array_mut()
+        .context("The local manifest files are not a list")?
+        .iter_mut()
+        .for_each(add_size);
+
+    manifest.insert("version".to_string(), json!(1));
+    Ok(())
 }
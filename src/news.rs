@@ -0,0 +1,141 @@
+use std::io::Write;
+use std::path::Path;
+
+use blake2::{Blake2b512, Digest};
+use reqwest::Url;
+use tracing::{info, warn};
+
+use crate::{write_atomic, RetryPolicy};
+
+/// Name of the copy of the last news page which was loaded
+pub const NEWS_CACHE_NAME: &str = "news.html";
+
+/// Name of the copy of the last changelog which was loaded, which is loaded
+/// and cached just like the news
+pub const CHANGELOG_CACHE_NAME: &str = "changelog.html";
+
+/// News page shown in the updater window
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum News {
+    /// The page was just downloaded
+    Live(String),
+    /// The page couldn't be downloaded, this is the last copy which was
+    Cached(String),
+    /// The page couldn't be downloaded and there is no copy of it
+    Unavailable,
+}
+
+/// Download the news page at `url`
+pub async fn fetch_news(client: &reqwest::Client, url: &Url) -> anyhow::Result<String> {
+    Ok(client
+        .get(url.clone())
+        .send()
+        .await?
+        .error_for_status()?
+        .text()
+        .await?)
+}
+
+fn save_cache(cache_path: &Path, html: &str) -> anyhow::Result<()> {
+    if let Some(parent) = cache_path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    write_atomic(cache_path, |writer| Ok(writer.write_all(html.as_bytes())?))
+}
+
+/// Download the news page at `url`, keeping a copy at `cache_path` which is
+/// shown instead when the page can't be downloaded, e.g. when offline. Failed
+/// downloads are retried according to `retry` first.
+pub async fn load_news(
+    client: &reqwest::Client,
+    url: &Url,
+    cache_path: Option<&Path>,
+    retry: &RetryPolicy,
+) -> News {
+    match retry.run(|| fetch_news(client, url)).await {
+        Ok(html) => {
+            if let Some(cache_path) = cache_path {
+                if let Err(e) = save_cache(cache_path, &html) {
+                    warn!(error =? e, "Failed to cache the news");
+                }
+            }
+            News::Live(html)
+        }
+        Err(e) => {
+            warn!(error =? e, "Failed to download the news from {}", url);
+            match cache_path.map(std::fs::read_to_string) {
+                Some(Ok(html)) => {
+                    info!("Showing cached news");
+                    News::Cached(html)
+                }
+                _ => News::Unavailable,
+            }
+        }
+    }
+}
+
+/// Insert `insert` right after the opening `tag` of `html`, or at the start
+/// if there is no such tag
+fn insert_after_tag(html: &str, tag: &str, insert: &str) -> String {
+    // Lowercasing ASCII keeps every byte offset the same
+    let lowercase = html.to_ascii_lowercase();
+    let position = lowercase
+        .find(tag)
+        .and_then(|start| lowercase[start..].find('>').map(|end| start + end + 1));
+    match position {
+        Some(position) => format!("{}{}{}", &html[..position], insert, &html[position..]),
+        None => format!("{}{}", insert, html),
+    }
+}
+
+/// Add a bar with `buttons` to the top of the page `html`
+pub fn add_toolbar(html: &str, buttons: &str) -> String {
+    insert_after_tag(
+        html,
+        "<body",
+        &format!(
+            "<div style=\"background: #211a27; font: 12px sans-serif; padding: 4px 8px; \
+             text-align: right\">{}</div>",
+            buttons
+        ),
+    )
+}
+
+impl News {
+    /// Hash of the page, `None` if there is no page. Used to tell whether the
+    /// page changed since the player last saw it.
+    pub fn page_hash(&self) -> Option<Vec<u8>> {
+        match self {
+            News::Live(html) | News::Cached(html) => {
+                Some(Blake2b512::digest(html.as_bytes()).to_vec())
+            }
+            News::Unavailable => None,
+        }
+    }
+
+    /// HTML to show for the news page at `url`. Relative links and images are
+    /// resolved against `url` as the page isn't navigated to.
+    ///
+    /// When the news couldn't be loaded a Retry button calls the
+    /// `retry_news()` function, which the page showing the news has to bind.
+    pub fn to_html(&self, url: &Url) -> String {
+        let base = format!("<base href=\"{}\">", url);
+        match self {
+            News::Live(html) => insert_after_tag(html, "<head", &base),
+            News::Cached(html) => {
+                let html = insert_after_tag(html, "<head", &base);
+                insert_after_tag(
+                    &html,
+                    "<body",
+                    "<div style=\"background: #211a27; color: #fff; font: 12px sans-serif; \
+                     padding: 4px 8px; opacity: 0.8\">Showing cached news, the latest news \
+                     couldn't be loaded <button onclick=\"retry_news()\">Retry</button></div>",
+                )
+            }
+            News::Unavailable => "<html><body style=\"background: #211a27; color: #fff; \
+                font: 14px sans-serif; padding: 16px\">The news couldn't be loaded. \
+                <button onclick=\"retry_news()\">Retry</button></body></html>"
+                .to_string(),
+        }
+    }
+}
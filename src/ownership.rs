@@ -0,0 +1,38 @@
+use std::path::Path;
+
+/// Permissions and owner given to every updated file
+#[derive(Clone, Copy, Debug, Default)]
+pub struct FileOwnership {
+    /// Permission bits removed from every file, e.g. `0o022`
+    pub umask: Option<u32>,
+    pub uid: Option<u32>,
+    pub gid: Option<u32>,
+}
+
+impl FileOwnership {
+    /// Apply the permission bits `mode` from the manifest, less the umask,
+    /// and the owner to `path`. Only the read, write and execute bits are
+    /// applied, never setuid, setgid or sticky bits.
+    #[cfg(unix)]
+    pub fn apply(&self, path: &Path, mode: Option<u32>) -> anyhow::Result<()> {
+        use std::os::unix::fs::PermissionsExt;
+
+        let mode = match (mode, self.umask) {
+            (Some(mode), umask) => Some(mode & 0o777 & !umask.unwrap_or(0)),
+            (None, Some(umask)) => Some(0o666 & !umask),
+            (None, None) => None,
+        };
+        if let Some(mode) = mode {
+            std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode))?;
+        }
+        if self.uid.is_some() || self.gid.is_some() {
+            std::os::unix::fs::chown(path, self.uid, self.gid)?;
+        }
+        Ok(())
+    }
+
+    #[cfg(not(unix))]
+    pub fn apply(&self, _path: &Path, _mode: Option<u32>) -> anyhow::Result<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,311 @@
+use std::collections::HashMap;
+use std::io::SeekFrom;
+use std::path::Path;
+
+use anyhow::{bail, Context};
+use blake2::{Blake2b512, Digest};
+use futures_util::{StreamExt, TryStreamExt};
+use serde::{Deserialize, Serialize};
+use tokio::fs;
+use tokio::fs::File;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncSeekExt, AsyncWrite, AsyncWriteExt};
+use walkdir::WalkDir;
+
+use crate::{
+    archive_relative_path, chunker_config, create_archive_options, hash_file, manifest_path,
+    verify_output_file, ArchiveOptions, HASH_ALGORITHM,
+};
+
+/// Version of the patch manifest written by the archive tool
+pub const PATCH_MANIFEST_VERSION: usize = 1;
+
+/// Name of the manifest describing the patches in the output directory
+pub const PATCH_MANIFEST_NAME: &str = "patch-manifest.json";
+
+/// Patches turning one release of the client into the next
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct PatchManifest {
+    pub version: usize,
+
+    /// Hash of every file of the release the patches apply to, see
+    /// [`base_hash`]
+    pub base_hash: Vec<u8>,
+
+    /// Algorithm used for every hash in the manifest
+    pub hash_algorithm: String,
+
+    /// Patches of the files which changed between the releases. Files which
+    /// are new in the target release have no patch.
+    pub files: Vec<PatchFileEntry>,
+}
+
+/// Patch of a single file
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct PatchFileEntry {
+    /// Path of the patch archive relative to the patch manifest. Its source is
+    /// the data inserted by the patch.
+    pub path: String,
+    pub source_path: String,
+
+    /// Hash of the file the patch applies to
+    pub base_hash: Vec<u8>,
+
+    /// Hash and size of the file once patched
+    pub source_hash: Vec<u8>,
+    pub source_size: usize,
+
+    /// Size of the patch archive
+    pub patch_size: u64,
+
+    /// Steps rebuilding the target file in order
+    pub ops: Vec<PatchOp>,
+}
+
+/// Step of a patch, each appending to the target file
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum PatchOp {
+    /// Copy `size` bytes at `offset` in the base file
+    Copy { offset: u64, size: u64 },
+    /// Insert the next `size` bytes of the patch archive's source
+    Insert { size: u64 },
+}
+
+/// Hash identifying a release by the source path and hash of its files
+///
+/// The client can compute this from its local manifest to find out whether a
+/// patch manifest applies to its current files.
+pub fn base_hash<'a>(files: impl IntoIterator<Item = (&'a str, &'a [u8])>) -> Vec<u8> {
+    let mut files: Vec<(&str, &[u8])> = files.into_iter().collect();
+    files.sort();
+
+    // Every field is prefixed with its length, like the files hash of the
+    // remote manifest
+    let mut hasher = Blake2b512::new();
+    for (source_path, source_hash) in files {
+        for bytes in [source_path.as_bytes(), source_hash] {
+            hasher.update((bytes.len() as u64).to_le_bytes());
+            hasher.update(bytes);
+        }
+    }
+    hasher.finalize().to_vec()
+}
+
+/// Append `op` to `ops`, merging it with the previous step where possible
+fn push_op(ops: &mut Vec<PatchOp>, op: PatchOp) {
+    if let Some(last) = ops.last_mut() {
+        match (last, op) {
+            (
+                PatchOp::Copy { offset, size },
+                PatchOp::Copy {
+                    offset: next_offset,
+                    size: next_size,
+                },
+            ) if *offset + *size == next_offset => {
+                *size += next_size;
+                return;
+            }
+            (PatchOp::Insert { size }, PatchOp::Insert { size: next_size }) => {
+                *size += next_size;
+                return;
+            }
+            _ => {}
+        }
+    }
+    ops.push(op);
+}
+
+/// Offset, hash and size of every chunk of the file at `path`
+async fn file_chunks(path: &Path) -> anyhow::Result<Vec<(u64, bitar::HashSum, u64)>> {
+    let mut file = File::open(path).await?;
+    let config = chunker_config();
+    let chunker = config.new_chunker(&mut file);
+    let mut chunk_stream = chunker.map_ok(|(offset, chunk)| (offset, chunk.verify()));
+
+    let mut chunks = Vec::new();
+    while let Some(result) = chunk_stream.next().await {
+        let (offset, verified) = result?;
+        let (hash, chunk) = verified.into_parts();
+        chunks.push((offset, hash, chunk.len() as u64));
+    }
+    Ok(chunks)
+}
+
+/// Copy exactly `size` bytes from `reader` to `writer`
+async fn copy_exact<R, W>(reader: &mut R, writer: &mut W, size: u64) -> anyhow::Result<()>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let copied = tokio::io::copy(&mut reader.take(size), writer).await?;
+    if copied != size {
+        bail!("Expected {} bytes but only {} were left", size, copied);
+    }
+    Ok(())
+}
+
+/// Find the steps rebuilding `new_path` from `old_path`, writing the data
+/// which isn't in the old file to `inserts_path`
+async fn diff_file(
+    old_path: &Path,
+    new_path: &Path,
+    inserts_path: &Path,
+) -> anyhow::Result<Vec<PatchOp>> {
+    let mut old_offsets = HashMap::new();
+    for (offset, hash, _) in file_chunks(old_path).await? {
+        old_offsets.entry(hash).or_insert(offset);
+    }
+
+    let mut new_file = File::open(new_path).await?;
+    let mut inserts = File::create(inserts_path).await?;
+    let mut ops = Vec::new();
+    for (offset, hash, size) in file_chunks(new_path).await? {
+        match old_offsets.get(&hash) {
+            Some(&old_offset) => push_op(
+                &mut ops,
+                PatchOp::Copy {
+                    offset: old_offset,
+                    size,
+                },
+            ),
+            None => {
+                new_file.seek(SeekFrom::Start(offset)).await?;
+                copy_exact(&mut new_file, &mut inserts, size).await?;
+                push_op(&mut ops, PatchOp::Insert { size });
+            }
+        }
+    }
+    inserts.flush().await?;
+
+    Ok(ops)
+}
+
+/// Create a patch for every file in `to` which differs from the file at the
+/// same path in `from`, and return the manifest describing them.
+///
+/// The data which isn't in the old file is archived to the archive path of
+/// the file within `output`, using the prefix directory, extension and
+/// compression of `options`.
+pub async fn create_patches(
+    from: &Path,
+    to: &Path,
+    output: &Path,
+    options: &ArchiveOptions,
+) -> anyhow::Result<PatchManifest> {
+    let archive_options = create_archive_options(options)?;
+
+    let mut base_files = Vec::new();
+    for entry in WalkDir::new(from) {
+        let entry = entry?;
+        if entry.file_type().is_file() {
+            let source_path = manifest_path(entry.path().strip_prefix(from)?)?;
+            base_files.push((source_path, hash_file(entry.path()).await?));
+        }
+    }
+    let base_hashes: HashMap<&str, &[u8]> = base_files
+        .iter()
+        .map(|(source_path, hash)| (source_path.as_str(), hash.as_slice()))
+        .collect();
+
+    let mut manifest = PatchManifest {
+        version: PATCH_MANIFEST_VERSION,
+        base_hash: base_hash(base_hashes.iter().map(|(path, hash)| (*path, *hash))),
+        hash_algorithm: HASH_ALGORITHM.to_string(),
+        files: Vec::new(),
+    };
+
+    for entry in WalkDir::new(to).sort_by_file_name() {
+        let entry = entry?;
+        if !entry.file_type().is_file() {
+            continue;
+        }
+
+        let relative_path = entry.path().strip_prefix(to)?;
+        let source_path = manifest_path(relative_path)?;
+        let source_hash = hash_file(entry.path()).await?;
+        let old_hash = match base_hashes.get(source_path.as_str()) {
+            Some(&old_hash) if old_hash != source_hash => old_hash,
+            _ => continue,
+        };
+
+        let patch_relative_path = archive_relative_path(relative_path, options);
+        let patch_path = output.join(&patch_relative_path);
+        if let Some(patch_parent) = patch_path.parent() {
+            fs::create_dir_all(patch_parent).await?;
+        }
+
+        let mut inserts_path = patch_path.clone().into_os_string();
+        inserts_path.push(".inserts");
+        let inserts_path = Path::new(&inserts_path);
+        let ops = diff_file(&from.join(relative_path), entry.path(), inserts_path)
+            .await
+            .context(format!("Failed to diff {}", source_path))?;
+
+        let mut inserts = File::open(inserts_path).await?;
+        let mut patch_file = File::create(&patch_path).await?;
+        bitar::api::compress::create_archive(&mut inserts, &mut patch_file, &archive_options)
+            .await?;
+        patch_file.flush().await?;
+        drop(inserts);
+        fs::remove_file(inserts_path).await?;
+
+        let patch_size = patch_file.metadata().await?.len();
+        let source_size = entry.metadata()?.len() as usize;
+        println!(
+            "{} => {} ({} of {} bytes)",
+            source_path,
+            patch_path.display(),
+            patch_size,
+            source_size
+        );
+
+        manifest.files.push(PatchFileEntry {
+            path: manifest_path(&patch_relative_path)?,
+            source_path,
+            base_hash: old_hash.to_vec(),
+            source_hash,
+            source_size,
+            patch_size,
+            ops,
+        });
+    }
+
+    Ok(manifest)
+}
+
+/// Rebuild the target of `entry` at `output_path` from the file it applies to
+/// at `base_path` and the source of its patch archive at `inserts_path`
+pub async fn apply_patch(
+    base_path: &Path,
+    inserts_path: &Path,
+    entry: &PatchFileEntry,
+    output_path: &Path,
+) -> anyhow::Result<()> {
+    if hash_file(base_path).await? != entry.base_hash {
+        bail!(
+            "{} is not the file the patch of {} applies to",
+            base_path.display(),
+            entry.source_path
+        );
+    }
+
+    let mut base = File::open(base_path).await?;
+    let mut inserts = File::open(inserts_path).await?;
+    let mut output = File::create(output_path).await?;
+    for op in &entry.ops {
+        match *op {
+            PatchOp::Copy { offset, size } => {
+                base.seek(SeekFrom::Start(offset)).await?;
+                copy_exact(&mut base, &mut output, size).await?;
+            }
+            PatchOp::Insert { size } => copy_exact(&mut inserts, &mut output, size).await?,
+        }
+    }
+    output.flush().await?;
+    drop(output);
+
+    if !verify_output_file(output_path, &entry.source_hash).await? {
+        bail!("The patched {} doesn't match its hash", entry.source_path);
+    }
+    Ok(())
+}
@@ -0,0 +1,44 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use tokio::sync::Notify;
+
+/// Flag shared by every clone which pauses downloading while it's set.
+///
+/// Clones wait before fetching each chunk, so the chunks written before
+/// pausing are kept and the clone carries on with the next chunk once
+/// resumed.
+#[derive(Clone, Debug, Default)]
+pub struct PauseFlag {
+    paused: Arc<AtomicBool>,
+    resumed: Arc<Notify>,
+}
+
+impl PauseFlag {
+    pub fn pause(&self) {
+        self.paused.store(true, Ordering::SeqCst);
+    }
+
+    pub fn resume(&self) {
+        self.paused.store(false, Ordering::SeqCst);
+        self.resumed.notify_waiters();
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::SeqCst)
+    }
+
+    /// Wait until the flag is no longer set, returning immediately when it
+    /// isn't set
+    pub async fn wait(&self) {
+        loop {
+            // Register for the notification before checking the flag so a
+            // resume in between isn't missed
+            let resumed = self.resumed.notified();
+            if !self.is_paused() {
+                return;
+            }
+            resumed.await;
+        }
+    }
+}
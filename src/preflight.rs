@@ -0,0 +1,108 @@
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use reqwest::{StatusCode, Url};
+
+const PREFLIGHT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The step of a connectivity check which failed
+#[derive(Debug)]
+pub enum PreflightFailure {
+    /// The host name could not be resolved, e.g. DNS is blocked or offline
+    Dns(String),
+    /// A connection could not be established, e.g. TLS or a proxy is failing
+    Connect(String),
+    /// The server responded with an error status
+    Http(StatusCode),
+    Other(String),
+}
+
+impl fmt::Display for PreflightFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PreflightFailure::Dns(e) => write!(f, "DNS lookup failed: {}", e),
+            PreflightFailure::Connect(e) => write!(f, "Connection (TCP/TLS) failed: {}", e),
+            PreflightFailure::Http(status) => write!(f, "HTTP request failed: {}", status),
+            PreflightFailure::Other(e) => write!(f, "Request failed: {}", e),
+        }
+    }
+}
+
+/// Result of checking that a single URL can be reached
+#[derive(Debug)]
+pub struct PreflightCheck {
+    pub name: String,
+    pub url: Url,
+    pub elapsed: Duration,
+    pub failure: Option<PreflightFailure>,
+}
+
+impl fmt::Display for PreflightCheck {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.failure {
+            None => write!(
+                f,
+                "{} ({}): pass in {:?}",
+                self.name, self.url, self.elapsed
+            ),
+            Some(failure) => write!(
+                f,
+                "{} ({}): fail in {:?}, {}",
+                self.name, self.url, self.elapsed, failure
+            ),
+        }
+    }
+}
+
+async fn check(client: &reqwest::Client, url: &Url) -> Result<(), PreflightFailure> {
+    let host = url
+        .host_str()
+        .ok_or_else(|| PreflightFailure::Other("URL has no host".into()))?;
+    let port = url.port_or_known_default().unwrap_or(443);
+
+    // Resolve the host ourselves first so DNS failures can be told apart from
+    // connection failures
+    let addrs = tokio::time::timeout(PREFLIGHT_TIMEOUT, tokio::net::lookup_host((host, port)))
+        .await
+        .map_err(|_| PreflightFailure::Dns("timed out".into()))?
+        .map_err(|e| PreflightFailure::Dns(e.to_string()))?;
+    if addrs.count() == 0 {
+        return Err(PreflightFailure::Dns("no addresses found".into()));
+    }
+
+    let response = client
+        .head(url.clone())
+        .timeout(PREFLIGHT_TIMEOUT)
+        .send()
+        .await
+        .map_err(|e| {
+            if e.is_connect() || e.is_timeout() {
+                PreflightFailure::Connect(e.to_string())
+            } else {
+                PreflightFailure::Other(e.to_string())
+            }
+        })?;
+
+    if !response.status().is_success() {
+        return Err(PreflightFailure::Http(response.status()));
+    }
+
+    Ok(())
+}
+
+/// Check that each of the named `urls` can be reached, one after the other
+pub async fn run_preflight(client: &reqwest::Client, urls: &[(&str, Url)]) -> Vec<PreflightCheck> {
+    let mut checks = Vec::new();
+    for (name, url) in urls {
+        let start = Instant::now();
+        let failure = check(client, url).await.err();
+        checks.push(PreflightCheck {
+            name: name.to_string(),
+            url: url.clone(),
+            elapsed: start.elapsed(),
+            failure,
+        });
+    }
+
+    checks
+}
@@ -1,13 +1,29 @@
 use std::ops::{Deref, DerefMut};
 use std::sync::atomic::{AtomicBool, AtomicI32, AtomicUsize, Ordering};
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
-use fltk::enums::{Align, Color, Font, FrameType};
+use fltk::enums::{Align, Font, FrameType};
 use fltk::frame::*;
 use fltk::image::*;
 use fltk::{draw, prelude::*};
 use humansize::{file_size_opts, FileSize};
 
+use crate::style::{ProgressBarColors, Theme};
+use crate::{ProgressStage, SpeedEstimate, Strings};
+
+/// Format a remaining time as e.g. `35s`, `4m 10s` or `1h 5m`
+fn format_eta(eta: Duration) -> String {
+    let secs = eta.as_secs();
+    if secs >= 3600 {
+        format!("{}h {}m", secs / 3600, secs % 3600 / 60)
+    } else if secs >= 60 {
+        format!("{}m {}s", secs / 60, secs % 60)
+    } else {
+        format!("{}s", secs)
+    }
+}
+
 pub struct ProgressBar {
     bar: Frame,
     min: Arc<AtomicUsize>,
@@ -15,6 +31,12 @@ pub struct ProgressBar {
     value: Arc<AtomicUsize>,
     _max_size: Arc<AtomicI32>,
     is_zero: Arc<AtomicBool>,
+    speed: Arc<Mutex<SpeedEstimate>>,
+    theme: Arc<Mutex<Theme>>,
+    colors: Arc<Mutex<ProgressBarColors>>,
+    strings: Arc<Mutex<Strings>>,
+    current_file: Arc<Mutex<String>>,
+    stage: Arc<Mutex<ProgressStage>>,
 }
 
 impl ProgressBar {
@@ -33,12 +55,24 @@ impl ProgressBar {
         let value = Arc::new(AtomicUsize::new(0));
         let max_size = Arc::new(AtomicI32::new(0));
         let is_zero = Arc::new(AtomicBool::new(false));
+        let speed = Arc::new(Mutex::new(SpeedEstimate::new()));
+        let theme = Arc::new(Mutex::new(Theme::default()));
+        let colors = Arc::new(Mutex::new(ProgressBarColors::default()));
+        let strings = Arc::new(Mutex::new(Strings::default()));
+        let current_file = Arc::new(Mutex::new(String::new()));
+        let stage = Arc::new(Mutex::new(ProgressStage::default()));
         bar.draw({
             let min = min.clone();
             let max = max.clone();
             let value = value.clone();
             let max_size = max_size.clone();
             let is_zero = is_zero.clone();
+            let speed = speed.clone();
+            let theme = theme.clone();
+            let colors = colors.clone();
+            let strings = strings.clone();
+            let current_file = current_file.clone();
+            let stage = stage.clone();
             move |b| {
                 let mut png = PngImage::from_data(progress_bar_bytes).unwrap();
 
@@ -59,8 +93,9 @@ impl ProgressBar {
 
                 png.draw(b.x(), b.y(), width as i32, png.height());
 
+                let colors = colors.lock().unwrap();
                 draw::set_font(Font::Courier, 18);
-                draw::set_draw_color(Color::White);
+                draw::set_draw_color(colors.percentage());
                 // right side %
                 let percentage = if max - min == 0 {
                     0
@@ -81,7 +116,7 @@ impl ProgressBar {
                     );
                 } else if max == 0 {
                     draw::draw_text2(
-                        "Downloading patch metadata",
+                        strings.lock().unwrap().get("downloading_patch_metadata"),
                         b.x(),
                         b.y(),
                         b.width(),
@@ -102,6 +137,25 @@ impl ProgressBar {
                     data_size
                 };
 
+                // Show the speed while downloading so a stalled download can
+                // be told apart from a slow one
+                let speed = speed.lock().unwrap();
+                let bytes_per_sec = speed.speed_bytes_per_sec();
+                let data_size = match speed.eta(max - value) {
+                    Some(eta) if !is_zero && value < max => format!(
+                        "{} ({}/s, ~{} left)",
+                        data_size,
+                        (bytes_per_sec as u64)
+                            .file_size(file_size_opts::CONVENTIONAL)
+                            .unwrap(),
+                        format_eta(eta)
+                    ),
+                    _ => data_size,
+                };
+                drop(speed);
+
+                // The size is drawn on the background rather than the bar
+                let theme = *theme.lock().unwrap();
                 draw::set_font(Font::Helvetica, 12);
                 let mut size = draw::width(&data_size) as i32;
                 if size > max_size.load(Ordering::Relaxed) {
@@ -115,10 +169,10 @@ impl ProgressBar {
                     b.y() + b.height() - 25,
                     size,
                     30,
-                    Color::from_rgb(33, 26, 39),
+                    colors.background(theme),
                 );
                 draw::set_font(Font::Helvetica, 12);
-                draw::set_draw_color(Color::White);
+                draw::set_draw_color(colors.text(theme));
                 draw::draw_text2(
                     &data_size,
                     b.x(),
@@ -127,6 +181,45 @@ impl ProgressBar {
                     30,
                     Align::Right,
                 );
+
+                // The file being downloaded goes to the left of the size so
+                // a stalled download can be pinned to a file. The stages
+                // without downloads say what they're doing instead.
+                let strings = strings.lock().unwrap();
+                let current_file = current_file.lock().unwrap();
+                let label = if !current_file.is_empty() {
+                    Some(strings.format("downloading_file", &[&*current_file]))
+                } else {
+                    match *stage.lock().unwrap() {
+                        ProgressStage::Rebuilding => {
+                            Some(strings.get("checking_files").to_string())
+                        }
+                        ProgressStage::Verifying => {
+                            Some(strings.get("verifying_files").to_string())
+                        }
+                        ProgressStage::Cleanup => Some(strings.get("cleaning_up").to_string()),
+                        _ => None,
+                    }
+                };
+                if let Some(label) = label {
+                    draw::draw_box(
+                        FrameType::FlatBox,
+                        b.x(),
+                        b.y() + b.height() - 25,
+                        b.width() - size,
+                        30,
+                        colors.background(theme),
+                    );
+                    draw::set_draw_color(colors.text(theme));
+                    draw::draw_text2(
+                        &label,
+                        b.x(),
+                        b.y() + b.height() - 30,
+                        b.width() - size - 10,
+                        30,
+                        Align::Left | Align::Clip,
+                    );
+                }
             }
         });
         Self {
@@ -136,14 +229,47 @@ impl ProgressBar {
             value,
             _max_size: max_size,
             is_zero,
+            speed,
+            theme,
+            colors,
+            strings,
+            current_file,
+            stage,
         }
     }
 
+    /// Step of the update the progress belongs to, call `redraw` afterwards
+    pub fn set_stage(&mut self, stage: ProgressStage) {
+        *self.stage.lock().unwrap() = stage;
+    }
+
+    /// Source path of the file being downloaded, empty to hide it. Call
+    /// `redraw` afterwards.
+    pub fn set_current_file(&mut self, path: &str) {
+        *self.current_file.lock().unwrap() = path.to_string();
+    }
+
+    /// Colors replacing those of the theme, call `redraw` afterwards
+    pub fn set_colors(&mut self, colors: ProgressBarColors) {
+        *self.colors.lock().unwrap() = colors;
+    }
+
+    /// Language of the text drawn on the bar
+    pub fn set_strings(&mut self, strings: Strings) {
+        *self.strings.lock().unwrap() = strings;
+    }
+
+    /// Colors of the text drawn next to the bar, call `redraw` afterwards
+    pub fn set_theme(&mut self, theme: Theme) {
+        *self.theme.lock().unwrap() = theme;
+    }
+
     pub fn set_minimum(&mut self, value: usize) {
         self.min.store(value, Ordering::Relaxed);
     }
 
     pub fn set_maximum(&mut self, value: usize) {
+        self.speed.lock().unwrap().reset();
         self.max.store(value, Ordering::Relaxed);
         if value == 0 {
             self.is_zero.store(true, Ordering::Relaxed);
@@ -151,7 +277,10 @@ impl ProgressBar {
     }
 
     pub fn set_value(&mut self, value: usize) {
-        self.value.store(value, Ordering::Relaxed);
+        let previous = self.value.swap(value, Ordering::Relaxed);
+        if value > previous {
+            self.speed.lock().unwrap().record(value - previous);
+        }
     }
 
     pub fn minimum(&self) -> usize {
@@ -0,0 +1,226 @@
+use std::io::Write;
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use crate::Updater;
+
+/// Shortest time between two events which only differ in their progress
+const PROGRESS_EVENT_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Step of the update which the progress belongs to
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ProgressStage {
+    /// Downloading the remote manifest
+    #[default]
+    Manifest,
+    /// Hashing the local files to rebuild a missing local manifest
+    Rebuilding,
+    /// Updating the updater itself
+    Updater,
+    /// Downloading the game files
+    Downloading,
+    /// The update has finished
+    Done,
+    /// Checking which local files are out of date, or hashing every local
+    /// file when only verifying
+    Verifying,
+    /// Creating symlinks and deleting files which aren't in the manifest
+    /// after downloading
+    Cleanup,
+}
+
+/// Progress reported to external launchers as a line of JSON.
+///
+/// The fields are a stable interface for launchers, new fields may be added
+/// but existing ones aren't changed or removed.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct ProgressEvent {
+    pub stage: ProgressStage,
+    pub current: usize,
+    pub total: usize,
+    /// Status text shown to the player, e.g. the size of the update
+    #[serde(default)]
+    pub text: String,
+}
+
+enum WriterMessage {
+    Event(ProgressEvent),
+    /// Write the event held back by the throttle and acknowledge once
+    /// everything before it has been written
+    Flush(mpsc::Sender<()>),
+}
+
+/// The writer thread's end, which throttles events that only move the
+/// progress forward
+struct EventSink {
+    writer: Box<dyn Write + Send>,
+    written: Option<(ProgressEvent, Instant)>,
+    pending: Option<ProgressEvent>,
+}
+
+impl EventSink {
+    fn offer(&mut self, event: ProgressEvent) {
+        let throttled = self.written.as_ref().map_or(false, |(written, at)| {
+            written.stage == event.stage
+                && written.total == event.total
+                && written.text == event.text
+                && at.elapsed() < PROGRESS_EVENT_INTERVAL
+        });
+        if throttled {
+            self.pending = Some(event);
+            return;
+        }
+
+        // The last progress of a stage is written before moving on
+        self.write_pending();
+        self.write(event);
+    }
+
+    /// Time until the held back event is due
+    fn pending_due_in(&self) -> Duration {
+        self.written.as_ref().map_or(Duration::ZERO, |(_, at)| {
+            PROGRESS_EVENT_INTERVAL.saturating_sub(at.elapsed())
+        })
+    }
+
+    fn write_pending(&mut self) {
+        if let Some(event) = self.pending.take() {
+            self.write(event);
+        }
+    }
+
+    fn write(&mut self, event: ProgressEvent) {
+        let writer = &mut self.writer;
+        let result = serde_json::to_writer(&mut *writer, &event)
+            .map_err(std::io::Error::from)
+            .and_then(|_| writer.write_all(b"\n"))
+            .and_then(|_| writer.flush());
+        if let Err(e) = result {
+            warn!(error =? e, "Failed to write progress event");
+        }
+        self.written = Some((event, Instant::now()));
+    }
+
+    fn run(mut self, rx: mpsc::Receiver<WriterMessage>) {
+        loop {
+            let message = match self.pending {
+                Some(_) => match rx.recv_timeout(self.pending_due_in()) {
+                    Ok(message) => message,
+                    Err(RecvTimeoutError::Timeout) => {
+                        self.write_pending();
+                        continue;
+                    }
+                    Err(RecvTimeoutError::Disconnected) => break,
+                },
+                None => match rx.recv() {
+                    Ok(message) => message,
+                    Err(_) => break,
+                },
+            };
+            match message {
+                WriterMessage::Event(event) => self.offer(event),
+                WriterMessage::Flush(done) => {
+                    self.write_pending();
+                    let _ = done.send(());
+                }
+            }
+        }
+        self.write_pending();
+    }
+}
+
+/// Writes a [`ProgressEvent`] as newline delimited JSON every time the
+/// progress changes so another program can follow the update.
+///
+/// The events are written by a thread of their own so a slow reader never
+/// holds up the update. Events which only move the progress forward are
+/// written at most ten times a second.
+#[derive(Clone)]
+pub struct ProgressEventWriter {
+    event: Arc<Mutex<ProgressEvent>>,
+    tx: mpsc::Sender<WriterMessage>,
+}
+
+impl ProgressEventWriter {
+    pub fn new(writer: impl Write + Send + 'static) -> Self {
+        let (tx, rx) = mpsc::channel();
+        let sink = EventSink {
+            writer: Box::new(writer),
+            written: None,
+            pending: None,
+        };
+        std::thread::spawn(move || sink.run(rx));
+        Self {
+            event: Arc::new(Mutex::new(ProgressEvent::default())),
+            tx,
+        }
+    }
+
+    /// Write events to a file descriptor inherited from the parent process
+    ///
+    /// # Safety
+    ///
+    /// `fd` must be an open file descriptor which isn't owned by anything
+    /// else in this process.
+    #[cfg(unix)]
+    pub unsafe fn from_fd(fd: std::os::unix::io::RawFd) -> Self {
+        use std::os::unix::io::FromRawFd;
+        Self::new(std::fs::File::from_raw_fd(fd))
+    }
+
+    /// Write events to a named pipe created by the parent process, e.g.
+    /// `\\.\pipe\rose-updater-progress`
+    #[cfg(windows)]
+    pub fn from_pipe(name: &str) -> std::io::Result<Self> {
+        let pipe = std::fs::OpenOptions::new().write(true).open(name)?;
+        Ok(Self::new(pipe))
+    }
+
+    /// Move on to the next step of the update
+    pub fn set_stage(&self, stage: ProgressStage) {
+        self.update(|event| event.stage = stage);
+    }
+
+    pub fn set_text(&self, text: String) {
+        self.update(|event| event.text = text);
+    }
+
+    /// Wait until every event so far has been written, including the last
+    /// one held back by the throttle. This blocks so it's meant for when the
+    /// update is over, e.g. before exiting.
+    pub fn flush(&self) {
+        let (done_tx, done_rx) = mpsc::channel();
+        if self.tx.send(WriterMessage::Flush(done_tx)).is_ok() {
+            let _ = done_rx.recv();
+        }
+    }
+
+    fn update(&self, f: impl FnOnce(&mut ProgressEvent)) {
+        // The lock is held while sending so the events are queued in order
+        let mut event = self.event.lock().unwrap();
+        f(&mut event);
+        if self.tx.send(WriterMessage::Event(event.clone())).is_err() {
+            warn!("Failed to write progress event, the writer has stopped");
+        }
+    }
+}
+
+#[async_trait]
+impl Updater for ProgressEventWriter {
+    async fn set_max_progress(&self, total: usize) {
+        self.update(|event| {
+            event.current = 0;
+            event.total = total;
+        });
+    }
+
+    async fn increment_progress(&self, amount: usize) {
+        self.update(|event| event.current += amount);
+    }
+}
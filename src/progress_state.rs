@@ -0,0 +1,115 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use async_trait::async_trait;
+
+use crate::{ProgressStage, UpdateProgress, Updater};
+
+/// Receives every change of a [`ProgressState`], e.g. to print the progress
+/// or to wake up a UI
+pub trait ProgressObserver: Send + Sync {
+    /// The update moved on to `stage`
+    fn on_stage(&self, stage: ProgressStage);
+
+    /// `current` of `total` bytes of the current stage are done
+    fn on_progress(&self, current: u64, total: u64);
+
+    /// The status line changed, e.g. to the size of the update
+    fn on_status(&self, _status: &str) {}
+
+    /// The file being downloaded changed, empty once no file is
+    fn on_file(&self, _path: &str) {}
+}
+
+/// Progress of an update shared by every download task.
+///
+/// The progress can be polled at any time and is also reported to the
+/// observer, if any, as soon as it changes.
+#[derive(Clone, Default)]
+pub struct ProgressState {
+    stage: Arc<Mutex<ProgressStage>>,
+    current: Arc<AtomicU64>,
+    total: Arc<AtomicU64>,
+    current_file: Arc<Mutex<String>>,
+    observer: Option<Arc<dyn ProgressObserver>>,
+}
+
+impl ProgressState {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create a state which reports every change to `observer`
+    pub fn with_observer(observer: impl ProgressObserver + 'static) -> Self {
+        Self {
+            observer: Some(Arc::new(observer)),
+            ..Default::default()
+        }
+    }
+
+    pub fn stage(&self) -> ProgressStage {
+        *self.stage.lock().unwrap()
+    }
+
+    pub fn current(&self) -> u64 {
+        self.current.load(Ordering::SeqCst)
+    }
+
+    pub fn total(&self) -> u64 {
+        self.total.load(Ordering::SeqCst)
+    }
+
+    /// Source path of the file which last made progress, empty if none
+    pub fn current_file(&self) -> String {
+        self.current_file.lock().unwrap().clone()
+    }
+}
+
+#[async_trait]
+impl Updater for ProgressState {
+    async fn set_max_progress(&self, total: usize) {
+        self.current.store(0, Ordering::SeqCst);
+        self.total.store(total as u64, Ordering::SeqCst);
+        if let Some(observer) = &self.observer {
+            observer.on_progress(0, total as u64);
+        }
+    }
+
+    async fn increment_progress(&self, amount: usize) {
+        let current = self.current.fetch_add(amount as u64, Ordering::SeqCst) + amount as u64;
+        if let Some(observer) = &self.observer {
+            observer.on_progress(current, self.total());
+        }
+    }
+}
+
+impl UpdateProgress for ProgressState {
+    fn set_status(&self, status: String) {
+        if let Some(observer) = &self.observer {
+            observer.on_status(&status);
+        }
+    }
+
+    fn set_stage(&self, stage: ProgressStage) {
+        // The file only belongs to the stage it was downloaded in
+        self.set_current_file("");
+
+        *self.stage.lock().unwrap() = stage;
+        if let Some(observer) = &self.observer {
+            observer.on_stage(stage);
+        }
+    }
+
+    fn set_current_file(&self, path: &str) {
+        let mut current_file = self.current_file.lock().unwrap();
+        if *current_file == path {
+            return;
+        }
+        *current_file = path.to_string();
+        drop(current_file);
+
+        if let Some(observer) = &self.observer {
+            observer.on_file(path);
+        }
+    }
+}
@@ -0,0 +1,104 @@
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+
+use path_slash::PathExt;
+use tracing::{info, warn};
+
+use crate::{old_updater_path, RemoteManifest};
+
+/// Name of the directory within the output directory holding the updater's
+/// own state, which is never pruned
+const UPDATER_DIR_NAME: &str = "updater";
+
+/// Extensions appended to the name of a file while it's being updated: the
+/// progress of an interrupted clone, the temporary copy of a text file and a
+/// whole downloaded archive. These are kept so an interrupted update can
+/// resume.
+const SIDECAR_EXTS: &[&str] = &["progress", "tmp", "archive"];
+
+/// Key used to compare a path relative to the output directory with the
+/// source paths in the manifest. Windows paths are case insensitive.
+fn path_key(relative_path: &Path) -> String {
+    let path = relative_path.to_slash_lossy().to_string();
+    if cfg!(windows) {
+        path.to_lowercase()
+    } else {
+        path
+    }
+}
+
+/// Whether `relative_path` is the in-progress copy of a file in `keep`
+fn is_sidecar(relative_path: &Path, keep: &HashSet<String>) -> bool {
+    let is_sidecar_ext = relative_path
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .map_or(false, |ext| SIDECAR_EXTS.contains(&ext));
+    is_sidecar_ext && keep.contains(&path_key(&relative_path.with_extension("")))
+}
+
+/// Delete every file within `output` which isn't listed in `remote_manifest`,
+/// returning the deleted paths.
+///
+/// The updater directory and `protected_dirs`, relative to `output`, are
+/// skipped, as are the previous updater kept to roll back to and the files of
+/// interrupted downloads. Symlinks aren't followed so nothing outside of
+/// `output` is touched. Files which can't be deleted are logged and skipped.
+pub fn prune_local_files(
+    output: &Path,
+    remote_manifest: &RemoteManifest,
+    protected_dirs: &[PathBuf],
+) -> anyhow::Result<Vec<PathBuf>> {
+    let updater_path = Path::new(&remote_manifest.updater.source_path);
+    let keep: HashSet<String> = std::iter::once(&remote_manifest.updater)
+        .chain(&remote_manifest.files)
+        .map(|entry| path_key(Path::new(&entry.source_path)))
+        .chain(
+            remote_manifest
+                .symlinks
+                .iter()
+                .map(|symlink| path_key(Path::new(&symlink.path))),
+        )
+        .chain(std::iter::once(path_key(&old_updater_path(updater_path))))
+        .collect();
+    let protected: HashSet<String> = std::iter::once(Path::new(UPDATER_DIR_NAME))
+        .chain(protected_dirs.iter().map(PathBuf::as_path))
+        .map(path_key)
+        .collect();
+
+    let mut pruned = Vec::new();
+    let walker = walkdir::WalkDir::new(output)
+        .min_depth(1)
+        .into_iter()
+        .filter_entry(|entry| {
+            !entry.file_type().is_dir()
+                || entry
+                    .path()
+                    .strip_prefix(output)
+                    .map_or(true, |path| !protected.contains(&path_key(path)))
+        });
+    for entry in walker {
+        let entry = entry?;
+        if entry.file_type().is_dir() {
+            continue;
+        }
+
+        let relative_path = entry.path().strip_prefix(output)?;
+        if keep.contains(&path_key(relative_path)) || is_sidecar(relative_path, &keep) {
+            continue;
+        }
+
+        match std::fs::remove_file(entry.path()) {
+            Ok(()) => {
+                info!(path =? entry.path().display(), "Deleted file not in the manifest");
+                pruned.push(entry.path().to_path_buf());
+            }
+            Err(e) => warn!(
+                error =? e,
+                path =? entry.path().display(),
+                "Failed to delete file not in the manifest"
+            ),
+        }
+    }
+
+    Ok(pruned)
+}
@@ -0,0 +1,35 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+
+use reqwest::Url;
+use tokio::sync::OnceCell;
+
+/// Whether the archive servers honour range requests, shared between all
+/// concurrent clones.
+///
+/// Every host is only probed once, clones of other files on the same host
+/// wait for the first probe and reuse its result.
+#[derive(Clone, Debug, Default)]
+pub struct RangeSupport {
+    hosts: Arc<Mutex<HashMap<String, Arc<OnceCell<bool>>>>>,
+}
+
+impl RangeSupport {
+    /// Whether the host of `url` supports range requests, calling `probe` if
+    /// the host hasn't been probed yet. Failed probes aren't remembered so
+    /// the next clone probes again.
+    pub async fn check<F, Fut>(&self, url: &Url, probe: F) -> anyhow::Result<bool>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = anyhow::Result<bool>>,
+    {
+        let key = format!(
+            "{}:{}",
+            url.host_str().unwrap_or_default(),
+            url.port_or_known_default().unwrap_or_default()
+        );
+        let cell = self.hosts.lock().unwrap().entry(key).or_default().clone();
+        cell.get_or_try_init(probe).await.copied()
+    }
+}
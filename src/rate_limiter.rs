@@ -0,0 +1,61 @@
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    last_refill: Instant,
+}
+
+/// A process-wide token bucket limiting how fast data is downloaded.
+///
+/// Every clone takes tokens from the same bucket so the limit applies to the
+/// total download rate rather than to each file. The bucket holds up to one
+/// second's worth of data so short bursts aren't delayed.
+#[derive(Clone, Debug)]
+pub struct RateLimiter {
+    bytes_per_sec: u64,
+    bucket: Arc<Mutex<Bucket>>,
+}
+
+impl RateLimiter {
+    pub fn new(bytes_per_sec: u64) -> Self {
+        let bytes_per_sec = bytes_per_sec.max(1);
+        Self {
+            bytes_per_sec,
+            bucket: Arc::new(Mutex::new(Bucket {
+                tokens: bytes_per_sec as f64,
+                last_refill: Instant::now(),
+            })),
+        }
+    }
+
+    pub fn bytes_per_sec(&self) -> u64 {
+        self.bytes_per_sec
+    }
+
+    /// Take `size` bytes from the bucket, waiting until the bucket has
+    /// refilled enough to cover them.
+    pub async fn acquire(&self, size: usize) {
+        let rate = self.bytes_per_sec as f64;
+        let wait = {
+            let mut bucket = self.bucket.lock().unwrap();
+            let now = Instant::now();
+            let refill = now.duration_since(bucket.last_refill).as_secs_f64() * rate;
+            bucket.tokens = (bucket.tokens + refill).min(rate) - size as f64;
+            bucket.last_refill = now;
+
+            // Going into debt lets a chunk larger than the bucket through and
+            // makes everyone after it wait until the debt is paid off
+            if bucket.tokens < 0.0 {
+                Duration::from_secs_f64(-bucket.tokens / rate)
+            } else {
+                Duration::ZERO
+            }
+        };
+
+        if !wait.is_zero() {
+            tokio::time::sleep(wait).await;
+        }
+    }
+}
@@ -0,0 +1,68 @@
+use std::future::Future;
+use std::time::Duration;
+
+use tracing::warn;
+
+/// Longest time to wait between two attempts regardless of the backoff
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(30);
+
+/// How failed requests are retried
+#[derive(Clone, Copy, Debug)]
+pub struct RetryPolicy {
+    /// Number of retries after the first attempt
+    pub retries: u32,
+
+    /// Delay before the first retry, doubled for every further retry
+    pub delay: Duration,
+
+    /// Maximum random delay added to every retry so clients which failed at
+    /// the same time don't all retry at the same time
+    pub jitter: Duration,
+}
+
+impl RetryPolicy {
+    /// Delay before retry number `attempt`, counting from zero
+    pub fn retry_delay(&self, attempt: u32) -> Duration {
+        let backoff = self
+            .delay
+            .saturating_mul(2u32.saturating_pow(attempt))
+            .min(MAX_RETRY_DELAY);
+        backoff + self.jitter.mul_f64(rand::random::<f64>())
+    }
+
+    /// Run `f` until it succeeds or every retry has been used up, returning
+    /// the last error.
+    pub async fn run<T, F, Fut>(&self, f: F) -> anyhow::Result<T>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = anyhow::Result<T>>,
+    {
+        self.run_if(f, |_| true).await
+    }
+
+    /// Like [`RetryPolicy::run`], but errors for which `is_retryable` returns
+    /// false are returned straight away
+    pub async fn run_if<T, F, Fut>(
+        &self,
+        mut f: F,
+        is_retryable: impl Fn(&anyhow::Error) -> bool,
+    ) -> anyhow::Result<T>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = anyhow::Result<T>>,
+    {
+        let mut attempt = 0;
+        loop {
+            match f().await {
+                Ok(value) => return Ok(value),
+                Err(e) if attempt < self.retries && is_retryable(&e) => {
+                    let delay = self.retry_delay(attempt);
+                    warn!(error =? e, "Request failed, retrying in {:?}", delay);
+                    tokio::time::sleep(delay).await;
+                    attempt += 1;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
@@ -0,0 +1,71 @@
+/// Check whether a process with the executable file name `exe_name` is running
+///
+/// Processes are matched by name only so an unrelated program with the same
+/// name is also detected.
+#[cfg(windows)]
+pub fn is_running(exe_name: &str) -> bool {
+    use windows::Win32::Foundation::CloseHandle;
+    use windows::Win32::System::Diagnostics::ToolHelp::{
+        CreateToolhelp32Snapshot, Process32FirstW, Process32NextW, PROCESSENTRY32W,
+        TH32CS_SNAPPROCESS,
+    };
+
+    // SAFETY: The entry is initialised with its size as the API requires and
+    // the snapshot handle is closed before returning.
+    unsafe {
+        let snapshot = match CreateToolhelp32Snapshot(TH32CS_SNAPPROCESS, 0) {
+            Ok(snapshot) => snapshot,
+            Err(_) => return false,
+        };
+
+        let mut entry = PROCESSENTRY32W {
+            dwSize: std::mem::size_of::<PROCESSENTRY32W>() as u32,
+            ..Default::default()
+        };
+
+        let mut found = false;
+        let mut has_entry = Process32FirstW(snapshot, &mut entry).as_bool();
+        while has_entry {
+            let len = entry
+                .szExeFile
+                .iter()
+                .position(|&c| c == 0)
+                .unwrap_or(entry.szExeFile.len());
+            let name = String::from_utf16_lossy(&entry.szExeFile[..len]);
+            if name.eq_ignore_ascii_case(exe_name) {
+                found = true;
+                break;
+            }
+            has_entry = Process32NextW(snapshot, &mut entry).as_bool();
+        }
+
+        let _ = CloseHandle(snapshot);
+        found
+    }
+}
+
+/// Check whether a process with the executable file name `exe_name` is running
+///
+/// Processes are matched by name only so an unrelated program with the same
+/// name is also detected. Windows style paths are handled so games running
+/// through Wine are detected too.
+#[cfg(not(windows))]
+pub fn is_running(exe_name: &str) -> bool {
+    let entries = match std::fs::read_dir("/proc") {
+        Ok(entries) => entries,
+        Err(_) => return false,
+    };
+
+    entries.filter_map(Result::ok).any(|entry| {
+        let cmdline = match std::fs::read(entry.path().join("cmdline")) {
+            Ok(cmdline) => cmdline,
+            Err(_) => return false,
+        };
+        let program = cmdline.split(|&b| b == 0).next().unwrap_or_default();
+        let program = String::from_utf8_lossy(program);
+        program
+            .rsplit(['/', '\\'])
+            .next()
+            .is_some_and(|name| name.eq_ignore_ascii_case(exe_name))
+    })
+}
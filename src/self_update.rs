@@ -0,0 +1,67 @@
+use std::path::{Path, PathBuf};
+use std::process::Child;
+
+use anyhow::Context;
+use tracing::{error, warn};
+
+/// Extension of the previous updater, which is moved out of the way while the
+/// new updater is downloaded since a running executable can't be replaced
+pub const UPDATER_OLD_EXT: &str = "old";
+
+/// Path the running updater at `updater_path` is moved to while updating it
+pub fn old_updater_path(updater_path: &Path) -> PathBuf {
+    updater_path.with_extension(UPDATER_OLD_EXT)
+}
+
+/// Restore the previous updater when updating the updater was interrupted
+/// after it was moved out of the way but before the new updater was written,
+/// returning whether it was restored.
+pub fn recover_interrupted_updater(updater_path: &Path) -> anyhow::Result<bool> {
+    let old_path = old_updater_path(updater_path);
+    if updater_path.exists() || !old_path.exists() {
+        return Ok(false);
+    }
+
+    warn!(
+        path =? updater_path.display(),
+        "The updater is missing after an interrupted update, restoring the previous updater"
+    );
+    std::fs::rename(&old_path, updater_path).context(format!(
+        "Failed to restore the previous updater from {}",
+        old_path.display()
+    ))?;
+    Ok(true)
+}
+
+/// Put the previous updater back in place of the new updater at
+/// `updater_path`, e.g. when the new updater is corrupt
+pub fn rollback_updater(updater_path: &Path) -> anyhow::Result<()> {
+    let old_path = old_updater_path(updater_path);
+    std::fs::rename(&old_path, updater_path).context(format!(
+        "Failed to restore the previous updater from {}",
+        old_path.display()
+    ))
+}
+
+/// Start the new updater at `updater_path` with `spawn`, rolling back to the
+/// previous updater when it can't be started
+pub fn start_new_updater<F>(updater_path: &Path, spawn: F) -> anyhow::Result<Child>
+where
+    F: FnOnce(&Path) -> std::io::Result<Child>,
+{
+    match spawn(updater_path) {
+        Ok(child) => Ok(child),
+        Err(e) => {
+            error!(
+                error =? e,
+                path =? updater_path.display(),
+                "Failed to start the new updater, restoring the previous updater"
+            );
+            rollback_updater(updater_path)?;
+            Err(anyhow::Error::from(e).context(format!(
+                "Failed to start the new updater {}, the previous updater was restored",
+                updater_path.display()
+            )))
+        }
+    }
+}
@@ -0,0 +1,66 @@
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::style::{ProgressBarColors, Theme};
+use crate::write_atomic;
+
+/// The user's directories for the updater, `None` if the system has none
+pub(crate) fn project_dirs() -> Option<directories::ProjectDirs> {
+    directories::ProjectDirs::from("com", "Rednim Games", "ROSE Updater")
+}
+
+/// The user's config directory for the updater, `None` if the system has none
+pub fn config_dir() -> Option<PathBuf> {
+    project_dirs().map(|dirs| dirs.config_dir().to_path_buf())
+}
+
+/// Choices made in the updater window which are remembered across launches
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct Settings {
+    /// Update and launch the beta client instead of the live client
+    #[serde(default)]
+    pub beta: bool,
+
+    /// Look of the updater window
+    #[serde(default)]
+    pub theme: Theme,
+
+    /// Hash of the changelog the player last opened, see
+    /// [`crate::News::page_hash`]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seen_changelog: Option<Vec<u8>>,
+
+    /// Colors of the progress bar replacing those of the theme. These are
+    /// only set by editing the settings file.
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub progress_bar: ProgressBarColors,
+}
+
+fn is_default<T: Default + PartialEq>(value: &T) -> bool {
+    *value == T::default()
+}
+
+impl Settings {
+    /// Path of the settings file in the user's config directory, `None` if
+    /// the system has no config directory
+    pub fn path() -> Option<PathBuf> {
+        config_dir().map(|dir| dir.join("settings.json"))
+    }
+
+    /// Read the settings saved at `path`, the defaults if there are none
+    pub fn load(path: &Path) -> Self {
+        std::fs::read(path)
+            .ok()
+            .and_then(|contents| serde_json::from_slice(&contents).ok())
+            .unwrap_or_default()
+    }
+
+    /// Save the settings to `path` so they're used by the next launch
+    pub fn save(&self, path: &Path) -> anyhow::Result<()> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        write_atomic(path, |writer| Ok(serde_json::to_writer(writer, self)?))
+    }
+}
@@ -0,0 +1,84 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// How far back progress is taken into account, short enough for the estimate
+/// to react quickly when the download is throttled or stalls
+const SPEED_WINDOW: Duration = Duration::from_secs(5);
+
+/// Estimates the download speed from the progress made over the last few
+/// seconds
+#[derive(Clone, Debug, Default)]
+pub struct SpeedEstimate {
+    started: Option<Instant>,
+    samples: VecDeque<(Instant, usize)>,
+}
+
+impl SpeedEstimate {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Forget all progress, e.g. when a new stage starts
+    pub fn reset(&mut self) {
+        self.started = None;
+        self.samples.clear();
+    }
+
+    /// Record `amount` bytes of progress made just now
+    pub fn record(&mut self, amount: usize) {
+        self.record_at(Instant::now(), amount);
+    }
+
+    /// Record `amount` bytes of progress made at `at`
+    pub fn record_at(&mut self, at: Instant, amount: usize) {
+        self.started.get_or_insert(at);
+        self.samples.push_back((at, amount));
+        while let Some((sample_at, _)) = self.samples.front() {
+            if at.saturating_duration_since(*sample_at) <= SPEED_WINDOW {
+                break;
+            }
+            self.samples.pop_front();
+        }
+    }
+
+    pub fn speed_bytes_per_sec(&self) -> f64 {
+        self.speed_at(Instant::now())
+    }
+
+    /// Average speed over the window ending at `now`
+    pub fn speed_at(&self, now: Instant) -> f64 {
+        let started = match self.started {
+            Some(started) => started,
+            None => return 0.0,
+        };
+
+        let window_start = now
+            .checked_sub(SPEED_WINDOW)
+            .unwrap_or(started)
+            .max(started);
+        let bytes: usize = self
+            .samples
+            .iter()
+            .filter(|(at, _)| *at >= window_start)
+            .map(|(_, amount)| amount)
+            .sum();
+        let elapsed = now
+            .saturating_duration_since(window_start)
+            .max(Duration::from_millis(100));
+        bytes as f64 / elapsed.as_secs_f64()
+    }
+
+    /// Estimated time to make `remaining` bytes of progress at the current
+    /// speed, `None` while nothing is being downloaded
+    pub fn eta(&self, remaining: usize) -> Option<Duration> {
+        self.eta_at(Instant::now(), remaining)
+    }
+
+    pub fn eta_at(&self, now: Instant, remaining: usize) -> Option<Duration> {
+        let speed = self.speed_at(now);
+        if speed < 1.0 {
+            return None;
+        }
+        Some(Duration::from_secs_f64(remaining as f64 / speed))
+    }
+}
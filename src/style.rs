@@ -0,0 +1,96 @@
+use fltk::enums::Color;
+use serde::{Deserialize, Serialize};
+
+/// Background and text of the dark theme
+pub const DARK_BACKGROUND_COLOR: Color = Color::from_rgb(33, 26, 39);
+pub const DARK_TEXT_COLOR: Color = Color::White;
+
+/// Background and text of the light theme
+pub const LIGHT_BACKGROUND_COLOR: Color = Color::from_rgb(240, 237, 243);
+pub const LIGHT_TEXT_COLOR: Color = DARK_BACKGROUND_COLOR;
+
+/// Percentage drawn on the progress bar, which is the same in every theme as
+/// it's drawn on the bar image
+pub const PROGRESS_PERCENTAGE_COLOR: Color = Color::White;
+
+/// Look of the updater window, chosen in the window and remembered across
+/// launches
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Theme {
+    /// The background art with light text
+    #[default]
+    Dark,
+    /// A plain light background with dark text
+    Light,
+}
+
+impl Theme {
+    /// Flat background, used instead of the background art by the light
+    /// theme and when effects are reduced
+    pub fn background_color(self) -> Color {
+        match self {
+            Theme::Dark => DARK_BACKGROUND_COLOR,
+            Theme::Light => LIGHT_BACKGROUND_COLOR,
+        }
+    }
+
+    /// Color of labels and text drawn on the background
+    pub fn text_color(self) -> Color {
+        match self {
+            Theme::Dark => DARK_TEXT_COLOR,
+            Theme::Light => LIGHT_TEXT_COLOR,
+        }
+    }
+
+    /// Whether the background art is drawn behind the window
+    pub fn draws_background_image(self) -> bool {
+        self == Theme::Dark
+    }
+}
+
+/// Colors of the progress bar which replace those of the theme, e.g. to
+/// re-skin the updater for an event. Every color is a hex color like
+/// `#ff8000`, colors which are missing or invalid use the theme's.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct ProgressBarColors {
+    /// Percentage drawn on the bar
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub percentage: Option<String>,
+
+    /// Size and speed drawn below the bar
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub text: Option<String>,
+
+    /// Box behind the size and speed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub background: Option<String>,
+}
+
+/// Parse a hex color like `#ff8000`
+fn parse_color(hex: Option<&str>) -> Option<Color> {
+    let hex = hex?.strip_prefix('#')?;
+    if hex.len() != 6 {
+        return None;
+    }
+    let rgb = u32::from_str_radix(hex, 16).ok()?;
+    Some(Color::from_rgb(
+        (rgb >> 16) as u8,
+        (rgb >> 8) as u8,
+        rgb as u8,
+    ))
+}
+
+impl ProgressBarColors {
+    pub fn percentage(&self) -> Color {
+        parse_color(self.percentage.as_deref()).unwrap_or(PROGRESS_PERCENTAGE_COLOR)
+    }
+
+    pub fn text(&self, theme: Theme) -> Color {
+        parse_color(self.text.as_deref()).unwrap_or_else(|| theme.text_color())
+    }
+
+    pub fn background(&self, theme: Theme) -> Color {
+        parse_color(self.background.as_deref()).unwrap_or_else(|| theme.background_color())
+    }
+}
@@ -0,0 +1,74 @@
+use std::path::Path;
+
+use tracing::warn;
+
+use crate::RemoteManifestSymlink;
+
+/// Create the `symlinks` from the manifest within `output`, returning how many
+/// were created or changed.
+///
+/// Links which already point to their target are left alone, while files or
+/// links of another target in their place are replaced. Links which would
+/// point outside of `output` are skipped.
+#[cfg(unix)]
+pub fn create_symlinks(output: &Path, symlinks: &[RemoteManifestSymlink]) -> anyhow::Result<usize> {
+    use anyhow::{bail, Context};
+    use path_slash::PathBufExt;
+    use std::path::PathBuf;
+
+    let mut created = 0;
+    for symlink in symlinks {
+        if !symlink.is_contained() {
+            warn!(
+                path = %symlink.path,
+                target = %symlink.target,
+                "Skipping symlink which points outside of the client"
+            );
+            continue;
+        }
+
+        let path = symlink.local_path(output);
+        let target = PathBuf::from_slash(&symlink.target);
+        match std::fs::symlink_metadata(&path) {
+            Ok(metadata) if metadata.file_type().is_symlink() => {
+                if std::fs::read_link(&path)? == target {
+                    continue;
+                }
+                std::fs::remove_file(&path)?;
+            }
+            Ok(metadata) if metadata.is_dir() => bail!(
+                "Can't create the symlink {} as a directory is in its place",
+                path.display()
+            ),
+            Ok(_) => std::fs::remove_file(&path)?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e.into()),
+        }
+
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::os::unix::fs::symlink(&target, &path).context(format!(
+            "Failed to create the symlink {} to {}",
+            path.display(),
+            target.display()
+        ))?;
+        created += 1;
+    }
+
+    Ok(created)
+}
+
+#[cfg(not(unix))]
+pub fn create_symlinks(
+    _output: &Path,
+    symlinks: &[RemoteManifestSymlink],
+) -> anyhow::Result<usize> {
+    if !symlinks.is_empty() {
+        warn!(
+            "Skipping {} symlinks which are only created on unix",
+            symlinks.len()
+        );
+    }
+    Ok(0)
+}
@@ -0,0 +1,1616 @@
+use std::collections::{HashMap, HashSet};
+use std::ffi::OsString;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command};
+
+use anyhow::{bail, Context};
+use async_trait::async_trait;
+use humansize::{file_size_opts, FileSize};
+use path_slash::PathBufExt;
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+use tokio::fs;
+use tokio::fs::File;
+use tokio::io::AsyncWriteExt;
+use tracing::{debug, error, info, warn};
+
+use crate::{
+    clone_remote_with_fallbacks, create_symlinks, download_remote_manifest, file_modified_at,
+    hash_file, migrate_local_manifest, old_updater_path, prune_local_files,
+    recover_interrupted_updater, rollback_updater, run_preflight, start_new_updater,
+    verify_output_file, write_atomic, CloneOptions, FileOwnership, LaunchCommand, LocalManifest,
+    LocalManifestFileEntry, ProgressStage, RemoteManifest, RemoteManifestFileEntry, Strings,
+    UnsupportedManifestVersion, Updater, HASH_ALGORITHM, LOCAL_MANIFEST_VERSION,
+    REMOTE_MANIFEST_CACHE_NAME,
+};
+
+const LOCAL_MANIFEST_BACKUP_EXT: &str = "json.bak";
+const CHECKPOINT_NAME: &str = "checkpoint.jsonl";
+const TEMP_FILE_EXT: &str = "tmp";
+const VERIFY_CACHE_NAME: &str = "verify_cache.json";
+const RUN_SUMMARY_NAME: &str = "last_run.json";
+
+const TEXT_FILE_EXTENSIONS: &[&str; 1] = &["xml"];
+
+async fn save_local_manifest(manifest_path: &Path, manfiest: &LocalManifest) -> anyhow::Result<()> {
+    if let Some(manifest_parent_dir) = manifest_path.parent() {
+        std::fs::create_dir_all(manifest_parent_dir)?;
+    }
+
+    write_atomic(manifest_path, |writer| {
+        Ok(serde_json::to_writer(writer, &manfiest)?)
+    })?;
+
+    info!("Saved local manifest to {}", manifest_path.display());
+
+    Ok(())
+}
+
+/// Summary of what a run did, saved so it can be inspected after the run
+#[derive(Serialize, Clone, Debug, Default)]
+pub struct RunSummary {
+    pub verify: bool,
+    pub repair: bool,
+    pub files_checked: usize,
+    pub files_to_update: usize,
+    pub files_updated: usize,
+    pub bytes_updated: usize,
+    pub files_changed: usize,
+    pub failed_files: Vec<String>,
+}
+
+impl RunSummary {
+    /// One line description of the run shown to the player
+    pub fn describe(&self) -> String {
+        self.describe_in(&Strings::default())
+    }
+
+    /// [`RunSummary::describe`] in the language of `strings`
+    pub fn describe_in(&self, strings: &Strings) -> String {
+        let mut description = strings.format(
+            "run_summary",
+            &[
+                &self.files_checked,
+                &self.files_updated,
+                &format_size(self.bytes_updated as u64),
+            ],
+        );
+        if !self.failed_files.is_empty() {
+            description += &strings.format("run_summary_failed", &[&self.failed_files.len()]);
+        }
+        description
+    }
+}
+
+/// Error returned when the update was cancelled, e.g. with the cancel button
+#[derive(Debug)]
+pub struct UpdateCancelled;
+
+impl fmt::Display for UpdateCancelled {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Download cancelled")
+    }
+}
+
+impl std::error::Error for UpdateCancelled {}
+
+/// Settings of an update run by [`run_update`]
+#[derive(Clone, Debug)]
+pub struct UpdateConfig {
+    /// Remote archive URL the archives in the manifest are resolved against
+    pub url: Url,
+
+    /// URL of the remote manifest
+    pub manifest_url: Url,
+
+    /// Directory the client is installed to
+    pub output: PathBuf,
+
+    /// News page included in the connectivity checks
+    pub news_url: Option<Url>,
+
+    /// Check that the update and news servers can be reached before updating
+    pub preflight: bool,
+
+    /// Skip checking for updater update and only update data files
+    pub skip_updater: bool,
+
+    /// Ignore the local manifest and check all files
+    pub force_recheck: bool,
+
+    /// Ignore the local manifest and check the updater for updates
+    pub force_recheck_updater: bool,
+
+    /// Verify all local files
+    pub verify: bool,
+
+    /// Redownload the manifest bypassing caches and hash every local file
+    pub repair: bool,
+
+    /// Update to a remote manifest whose release is lower than the installed
+    /// one instead of refusing to
+    pub allow_downgrade: bool,
+
+    /// Print what would be updated without changing anything
+    pub dry_run: bool,
+
+    /// Hash every local file and report whether it matches the remote
+    /// manifest without changing anything
+    pub verify_only: bool,
+
+    /// Delete files which aren't in the manifest after a successful update
+    pub prune: bool,
+
+    /// Directories within the output directory which are never pruned, e.g.
+    /// the install of another channel
+    pub prune_protected_dirs: Vec<PathBuf>,
+
+    /// Optional content groups to install, every group when `None`
+    pub groups: Option<Vec<String>>,
+
+    /// Number of bytes of free disk space to keep after updating
+    pub min_free_space: u64,
+
+    /// URL shown when the updater doesn't match the game build
+    pub support_url: String,
+
+    /// Permissions and owner given to every updated file
+    pub ownership: FileOwnership,
+
+    /// Options used to download every file
+    pub clone_options: CloneOptions,
+
+    /// Arguments the new updater is started with after updating itself
+    pub restart_args: Vec<OsString>,
+
+    /// Language of the status lines shown to the player
+    pub strings: Strings,
+}
+
+impl UpdateConfig {
+    /// Create a config updating `output` from the archives at `url` with the
+    /// manifest at `manifest_url`, with every other setting left at its
+    /// default
+    pub fn new(url: Url, manifest_url: Url, output: PathBuf, clone_options: CloneOptions) -> Self {
+        Self {
+            url,
+            manifest_url,
+            output,
+            news_url: None,
+            preflight: false,
+            skip_updater: false,
+            force_recheck: false,
+            force_recheck_updater: false,
+            verify: false,
+            repair: false,
+            allow_downgrade: false,
+            dry_run: false,
+            verify_only: false,
+            prune: false,
+            prune_protected_dirs: Vec::new(),
+            groups: None,
+            min_free_space: 0,
+            support_url: String::new(),
+            ownership: FileOwnership::default(),
+            clone_options,
+            restart_args: Vec::new(),
+            strings: Strings::default(),
+        }
+    }
+}
+
+/// Result of a successful [`run_update`]
+#[derive(Debug)]
+pub enum UpdateOutcome {
+    ApplicationUpdated(Option<LaunchCommand>, RunSummary),
+    /// The updater was updated and restarted as the given process to update
+    /// the application
+    UpdaterUpdated(Child),
+    /// The changes of the update were printed without updating anything
+    DryRun,
+    /// The local files were checked without updating anything
+    Verified(VerifyReport),
+}
+
+/// State of a local file compared to the remote manifest
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum FileState {
+    Ok,
+    /// The file exists but its contents don't match the manifest
+    Corrupt,
+    Missing,
+}
+
+impl fmt::Display for FileState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FileState::Ok => write!(f, "OK"),
+            FileState::Corrupt => write!(f, "corrupt"),
+            FileState::Missing => write!(f, "missing"),
+        }
+    }
+}
+
+/// Result of checking every local file against the remote manifest
+#[derive(Clone, Debug, Default)]
+pub struct VerifyReport {
+    /// Source path and state of every checked file, in manifest order
+    pub files: Vec<(String, FileState)>,
+}
+
+impl VerifyReport {
+    /// Number of files in `state`
+    pub fn count(&self, state: FileState) -> usize {
+        self.files.iter().filter(|(_, s)| *s == state).count()
+    }
+
+    /// Whether every file matches the manifest
+    pub fn is_ok(&self) -> bool {
+        self.files.iter().all(|(_, state)| *state == FileState::Ok)
+    }
+
+    /// Short description of the result
+    pub fn describe(&self) -> String {
+        format!(
+            "{} files OK, {} corrupt, {} missing",
+            self.count(FileState::Ok),
+            self.count(FileState::Corrupt),
+            self.count(FileState::Missing)
+        )
+    }
+}
+
+/// Receives the progress of [`run_update`]
+pub trait UpdateProgress: Updater + Clone + Send + Sync + 'static {
+    /// Show a short status line, e.g. the size of the update
+    fn set_status(&self, status: String);
+
+    /// Report the step the update has reached
+    fn set_stage(&self, stage: ProgressStage);
+
+    /// Report the source path of the file being downloaded, empty once no
+    /// file is
+    fn set_current_file(&self, _path: &str) {}
+}
+
+/// Progress of cloning a single file, which reports the file as the current
+/// file whenever it makes progress. With several files downloading at once
+/// the current file is the one which made progress last.
+#[derive(Clone)]
+struct FileProgress<P> {
+    progress: P,
+    source_path: String,
+}
+
+#[async_trait]
+impl<P: UpdateProgress> Updater for FileProgress<P> {
+    async fn set_max_progress(&self, total: usize) {
+        self.progress.set_max_progress(total).await;
+    }
+
+    async fn increment_progress(&self, amount: usize) {
+        self.progress.set_current_file(&self.source_path);
+        self.progress.increment_progress(amount).await;
+    }
+}
+
+async fn get_remote_manifest(
+    remote_manifest_url: Url,
+    bypass_cache: bool,
+    cache_path: &Path,
+    clone_options: &CloneOptions,
+) -> anyhow::Result<RemoteManifest> {
+    info!("Downloading remote manifest from {}", remote_manifest_url);
+    clone_options
+        .retry
+        .run(|| {
+            download_remote_manifest(
+                &clone_options.client,
+                remote_manifest_url.clone(),
+                bypass_cache,
+                Some(cache_path),
+            )
+        })
+        .await
+}
+
+/// Check the connectivity to the manifest and news servers, logging the result
+/// of each check, and return descriptions of the checks which failed.
+async fn preflight(config: &UpdateConfig) -> anyhow::Result<Vec<String>> {
+    info!("Running connectivity checks");
+    let mut urls = vec![("Manifest", config.manifest_url.clone())];
+    if let Some(news_url) = &config.news_url {
+        urls.push(("News", news_url.clone()));
+    }
+
+    let mut failures = Vec::new();
+    for check in run_preflight(&config.clone_options.client, &urls).await {
+        if check.failure.is_some() {
+            warn!("Connectivity check {}", check);
+            failures.push(check.to_string());
+        } else {
+            info!("Connectivity check {}", check);
+        }
+    }
+
+    Ok(failures)
+}
+
+async fn update_updater<P: UpdateProgress>(
+    local_updater_path: &Path,
+    updater_output_path: &Path,
+    remote_urls: &[Url],
+    progress: P,
+    clone_options: &CloneOptions,
+) -> anyhow::Result<()> {
+    // When the updater needs to be updated we change the exe name before
+    // restarting the process. This step ensures that we delete the old,
+    // outdated updater exe.
+    let local_updater_path_old = old_updater_path(local_updater_path);
+    if local_updater_path_old.exists() {
+        fs::remove_file(&local_updater_path_old)
+            .await
+            .context(format!(
+                "Failed to delete the old updater file: {}",
+                local_updater_path_old.display()
+            ))?;
+    }
+
+    info!("Updating updater");
+
+    // We cannot delete or modify a currently executing binary so we rename
+    // the currently executing updater to allow us to download the new one
+    // with the same name.
+    if local_updater_path.exists() {
+        fs::rename(&local_updater_path, &local_updater_path_old)
+            .await
+            .context(format!(
+                "Failed to rename the updater from {} to {}",
+                local_updater_path.display(),
+                local_updater_path_old.display(),
+            ))?;
+    }
+
+    clone_remote_with_fallbacks(remote_urls, updater_output_path, progress, clone_options)
+        .await
+        .context(format!("Failed to clone {}", &remote_urls[0]))?;
+
+    info!(
+        "Cloned {} to {}",
+        &remote_urls[0],
+        updater_output_path.display()
+    );
+
+    Ok(())
+}
+
+/// Read the local manifest from `folder`.
+///
+/// Returns `None` when a manifest exists but can't be parsed. The corrupt
+/// manifest is backed up next to the original so it can be inspected later,
+/// unless `backup` is false.
+async fn get_local_manifest(
+    folder: &PathBuf,
+    backup: bool,
+) -> anyhow::Result<Option<LocalManifest>> {
+    info!("Getting local manifest");
+
+    // Read the manifest file if we can. Otherwise we default to an empty local
+    // manifest which we save as a new manifest later.
+    if !folder
+        .try_exists()
+        .context("Failed to get the local manifest")?
+    {
+        return Ok(Some(LocalManifest::default()));
+    }
+
+    info!("Using existing manifest file: {}", folder.display());
+
+    let file = File::open(&folder).await?;
+    let manifest = serde_json::from_reader(file.into_std().await)
+        .map_err(anyhow::Error::from)
+        .and_then(migrate_local_manifest);
+    match manifest {
+        Ok(manifest) => Ok(Some(manifest)),
+        Err(e) if e.is::<UnsupportedManifestVersion>() => Err(e),
+        Err(e) if !backup => {
+            warn!(error =? e, "Failed to parse local manifest");
+            Ok(None)
+        }
+        Err(e) => {
+            let backup_path = folder.with_extension(LOCAL_MANIFEST_BACKUP_EXT);
+            warn!(
+                error =? e,
+                backup =? backup_path.display(),
+                "Failed to parse local manifest, backing it up"
+            );
+            fs::rename(&folder, &backup_path).await.context(format!(
+                "Failed to back up the corrupt local manifest to {}",
+                backup_path.display()
+            ))?;
+            Ok(None)
+        }
+    }
+}
+
+/// Release recorded in the local manifest at `path`, zero if there is none.
+/// This is read even when the rest of the local manifest is ignored.
+async fn installed_release(path: &Path) -> u64 {
+    fs::read(path)
+        .await
+        .ok()
+        .and_then(|contents| serde_json::from_slice::<serde_json::Value>(&contents).ok())
+        .and_then(|manifest| manifest.get("release")?.as_u64())
+        .unwrap_or(0)
+}
+
+/// Create a local manifest entry for `remote_entry` if the local file already
+/// matches the remote source hash.
+async fn matching_local_entry(
+    output: &Path,
+    remote_entry: &RemoteManifestFileEntry,
+) -> Option<LocalManifestFileEntry> {
+    let local_path = remote_entry.local_path(output);
+
+    // Avoid hashing files which can't possibly match
+    let metadata = fs::metadata(&local_path).await.ok()?;
+    if metadata.len() != remote_entry.source_size as u64 {
+        return None;
+    }
+
+    match hash_file(&local_path).await {
+        Ok(hash) if hash == remote_entry.source_hash => Some(LocalManifestFileEntry {
+            path: remote_entry.source_path.clone(),
+            hash,
+            size: remote_entry.source_size,
+            mode: remote_entry.mode,
+            modified_at: file_modified_at(&local_path),
+        }),
+        Ok(_) => None,
+        Err(e) => {
+            warn!(path =? local_path.display(), error =? e, "Failed to hash file");
+            None
+        }
+    }
+}
+
+/// Rebuild a local manifest by hashing the existing local files and keeping
+/// the ones that already match the remote manifest.
+///
+/// This is used to recover from a corrupt local manifest, or when the local
+/// manifest is being ignored, so only the files which actually differ are
+/// updated rather than rechecking everything.
+async fn rebuild_local_manifest<P: Updater>(
+    output: &Path,
+    remote_manifest: &RemoteManifest,
+    progress: &P,
+) -> anyhow::Result<LocalManifest> {
+    info!("Rebuilding local manifest from local files");
+
+    let mut local_manifest = LocalManifest {
+        version: LOCAL_MANIFEST_VERSION,
+        ..Default::default()
+    };
+
+    let total_size = remote_manifest.updater.source_size
+        + remote_manifest
+            .files
+            .iter()
+            .map(|entry| entry.source_size)
+            .sum::<usize>();
+    progress.set_max_progress(total_size).await;
+
+    if let Some(local_entry) = matching_local_entry(output, &remote_manifest.updater).await {
+        local_manifest.updater = local_entry;
+    }
+    progress
+        .increment_progress(remote_manifest.updater.source_size)
+        .await;
+
+    for remote_entry in &remote_manifest.files {
+        if let Some(local_entry) = matching_local_entry(output, remote_entry).await {
+            local_manifest.files.push(local_entry);
+        }
+        progress.increment_progress(remote_entry.source_size).await;
+    }
+
+    info!(
+        "Recovered {} of {} files from local files",
+        local_manifest.files.len(),
+        remote_manifest.files.len()
+    );
+
+    Ok(local_manifest)
+}
+
+/// Key of the update pass a checkpoint belongs to, which is the hash of the
+/// entries of the remote manifest being updated to
+fn checkpoint_key(remote_manifest: &RemoteManifest) -> String {
+    remote_manifest
+        .compute_files_hash()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// Read the entries of the files completed by a previous run that was
+/// interrupted before it could save the local manifest.
+///
+/// The first line of the checkpoint is the key of the pass it was written
+/// by. A checkpoint of another pass, e.g. of an older remote manifest, is
+/// ignored.
+async fn read_checkpoint(
+    checkpoint_path: &Path,
+    key: &str,
+) -> HashMap<PathBuf, LocalManifestFileEntry> {
+    let contents = match fs::read_to_string(checkpoint_path).await {
+        Ok(contents) => contents,
+        Err(_) => return HashMap::new(),
+    };
+    let mut lines = contents.lines();
+    if lines.next() != Some(key) {
+        return HashMap::new();
+    }
+
+    // The last line may be incomplete if the process was killed while writing
+    // it so we skip any entries we can't parse.
+    lines
+        .filter_map(|line| serde_json::from_str::<LocalManifestFileEntry>(line).ok())
+        .map(|entry| (PathBuf::from(&entry.path), entry))
+        .collect()
+}
+
+/// Start the checkpoint of a new pass, dropping the entries of any previous
+/// pass
+async fn start_checkpoint(checkpoint_path: &Path, key: &str) -> anyhow::Result<()> {
+    fs::write(checkpoint_path, format!("{}\n", key))
+        .await
+        .context(format!(
+            "Failed to start the checkpoint at {}",
+            checkpoint_path.display()
+        ))
+}
+
+async fn append_checkpoint(
+    checkpoint_path: &Path,
+    entry: &LocalManifestFileEntry,
+) -> anyhow::Result<()> {
+    let mut line = serde_json::to_vec(entry)?;
+    line.push(b'\n');
+
+    let mut checkpoint_file = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(checkpoint_path)
+        .await?;
+    checkpoint_file.write_all(&line).await?;
+    checkpoint_file.flush().await?;
+
+    Ok(())
+}
+
+/// Paths of the files in `new_manifest` which are new or whose hash differs
+/// from `previous_hashes`
+fn changed_files(
+    previous_hashes: &HashMap<String, Vec<u8>>,
+    new_manifest: &LocalManifest,
+) -> Vec<String> {
+    let mut changed: Vec<String> = new_manifest
+        .files
+        .iter()
+        .filter(|entry| previous_hashes.get(&entry.path) != Some(&entry.hash))
+        .map(|entry| entry.path.clone())
+        .collect();
+    changed.sort();
+    changed
+}
+
+/// Size and modification time of a local file, used to tell whether it has
+/// changed since it was last verified
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+struct FileStamp {
+    size: u64,
+    modified_secs: u64,
+    modified_nanos: u32,
+}
+
+impl FileStamp {
+    fn read(path: &Path) -> Option<Self> {
+        let metadata = std::fs::metadata(path).ok()?;
+        let modified = metadata
+            .modified()
+            .ok()?
+            .duration_since(std::time::UNIX_EPOCH)
+            .ok()?;
+        Some(Self {
+            size: metadata.len(),
+            modified_secs: modified.as_secs(),
+            modified_nanos: modified.subsec_nanos(),
+        })
+    }
+}
+
+/// A local file which was verified to have `hash` while it had `stamp`
+#[derive(Serialize, Deserialize, Clone, Debug)]
+struct VerifyCacheEntry {
+    stamp: FileStamp,
+    hash: Vec<u8>,
+}
+
+async fn read_verify_cache(verify_cache_path: &Path) -> HashMap<String, VerifyCacheEntry> {
+    match fs::read(verify_cache_path).await {
+        Ok(contents) => serde_json::from_slice(&contents).unwrap_or_default(),
+        Err(_) => HashMap::new(),
+    }
+}
+
+async fn save_verify_cache(
+    verify_cache_path: &Path,
+    verify_cache: &HashMap<String, VerifyCacheEntry>,
+) -> anyhow::Result<()> {
+    fs::write(verify_cache_path, serde_json::to_vec(verify_cache)?)
+        .await
+        .context(format!(
+            "Failed to save the verify cache to {}",
+            verify_cache_path.display()
+        ))
+}
+
+struct VerificationResults {
+    files_to_update: Vec<(Vec<Url>, RemoteManifestFileEntry)>,
+    /// Up to date files whose permissions changed in the remote manifest
+    mode_changes: Vec<RemoteManifestFileEntry>,
+    total_size: usize,
+    already_downloaded_size: usize,
+}
+
+fn verify_local_files(
+    output: &Path,
+    remote_url: &Url,
+    remote_manifest: RemoteManifest,
+    local_filedata: &HashMap<PathBuf, LocalManifestFileEntry>,
+    checkpoint: &HashMap<PathBuf, LocalManifestFileEntry>,
+    verify_cache: &HashMap<String, VerifyCacheEntry>,
+    force_verify: bool,
+) -> anyhow::Result<VerificationResults> {
+    info!("Checking local files");
+
+    let mut files_to_update = Vec::new();
+    let mut mode_changes = Vec::new();
+    let mut total_size = 0;
+    let mut already_downloaded_size = 0;
+    for remote_entry in remote_manifest.files {
+        let output_path = remote_entry.local_path(output);
+        let needs_update = || {
+            let metadata = match std::fs::metadata(&output_path) {
+                Ok(metadata) => metadata,
+                Err(_) => return true,
+            };
+
+            // A file of the wrong size is definitely outdated or truncated so
+            // it doesn't matter what the local manifest says about it
+            if metadata.len() != remote_entry.source_size as u64 {
+                debug!(
+                    "File {} is {} bytes but should be {} bytes",
+                    output_path.display(),
+                    metadata.len(),
+                    remote_entry.source_size
+                );
+                return true;
+            }
+
+            if let Some(local_entry) = local_filedata.get(&PathBuf::from(&remote_entry.source_path))
+            {
+                // A file changed behind our back has to be checked again even
+                // though the manifest says it's up to date
+                if local_entry.is_modified(&output_path) {
+                    debug!(
+                        "File {} was modified since it was last updated",
+                        output_path.display()
+                    );
+                    return true;
+                }
+
+                if local_entry.hash == remote_entry.source_hash {
+                    return false;
+                }
+            }
+
+            true
+        };
+
+        // Files which were already verified by an interrupted run don't need
+        // to be verified again
+        let checkpointed = checkpoint
+            .get(&PathBuf::from(&remote_entry.source_path))
+            .is_some_and(|entry| entry.hash == remote_entry.source_hash);
+
+        // Neither do files which haven't been modified since they were last
+        // verified
+        let verified = force_verify
+            && verify_cache
+                .get(&remote_entry.source_path)
+                .is_some_and(|entry| {
+                    entry.hash == remote_entry.source_hash
+                        && FileStamp::read(&output_path).as_ref() == Some(&entry.stamp)
+                });
+
+        total_size += remote_entry.source_size;
+
+        if (!force_verify || checkpointed || verified) && !needs_update() {
+            debug!(
+                "Skipping file {} as it is already present",
+                output_path.display()
+            );
+            already_downloaded_size += remote_entry.source_size;
+            if local_filedata
+                .get(&PathBuf::from(&remote_entry.source_path))
+                .is_some_and(|entry| entry.mode != remote_entry.mode)
+            {
+                mode_changes.push(remote_entry);
+            }
+            continue;
+        }
+
+        let clone_urls = remote_entry.archive_urls(remote_url)?;
+        files_to_update.push((clone_urls, remote_entry));
+    }
+
+    Ok(VerificationResults {
+        files_to_update,
+        mode_changes,
+        total_size,
+        already_downloaded_size,
+    })
+}
+
+/// Hash every local file in `remote_manifest` which would be installed and
+/// compare it with the manifest, without writing anything
+async fn verify_only<P: UpdateProgress>(
+    config: &UpdateConfig,
+    remote_manifest: &RemoteManifest,
+    progress: &P,
+) -> anyhow::Result<VerifyReport> {
+    if remote_manifest.hash_algorithm != HASH_ALGORITHM {
+        bail!(
+            "The remote manifest uses the hash algorithm {} which this updater doesn't support",
+            remote_manifest.hash_algorithm
+        );
+    }
+
+    let updater = (!config.skip_updater).then_some(&remote_manifest.updater);
+    let entries: Vec<&RemoteManifestFileEntry> = updater
+        .into_iter()
+        .chain(remote_manifest.files.iter().filter(|remote_entry| {
+            config
+                .groups
+                .as_ref()
+                .is_none_or(|groups| remote_entry.in_groups(groups))
+        }))
+        .collect();
+
+    progress.set_stage(ProgressStage::Verifying);
+    progress
+        .set_max_progress(entries.iter().map(|entry| entry.source_size).sum())
+        .await;
+
+    let mut report = VerifyReport::default();
+    for remote_entry in entries {
+        let local_path = remote_entry.local_path(&config.output);
+        let state = if !local_path.is_file() {
+            FileState::Missing
+        } else {
+            match hash_file(&local_path).await {
+                Ok(hash) if hash == remote_entry.source_hash => FileState::Ok,
+                Ok(_) => FileState::Corrupt,
+                Err(e) => {
+                    warn!(error =? e, path =? local_path.display(), "Failed to hash file");
+                    FileState::Corrupt
+                }
+            }
+        };
+        debug!("File {} is {}", remote_entry.source_path, state);
+        report.files.push((remote_entry.source_path.clone(), state));
+        progress.increment_progress(remote_entry.source_size).await;
+    }
+
+    info!("Verified local files: {}", report.describe());
+    Ok(report)
+}
+
+/// Print the action a dry run would take for every file in `remote_manifest`
+/// followed by the totals
+fn print_dry_run(
+    output: &Path,
+    remote_manifest: &RemoteManifest,
+    updater_needs_update: bool,
+    files_to_update: &[(Vec<Url>, RemoteManifestFileEntry)],
+) {
+    let to_update: HashSet<&str> = files_to_update
+        .iter()
+        .map(|(_, remote_entry)| remote_entry.source_path.as_str())
+        .collect();
+
+    let mut downloads = 0;
+    let mut updates = 0;
+    let mut skipped = 0;
+    let mut total_size = 0;
+    let entries = std::iter::once((&remote_manifest.updater, updater_needs_update)).chain(
+        remote_manifest.files.iter().map(|remote_entry| {
+            let changed = to_update.contains(remote_entry.source_path.as_str());
+            (remote_entry, changed)
+        }),
+    );
+    for (remote_entry, changed) in entries {
+        let action = if !changed {
+            skipped += 1;
+            "skip"
+        } else if remote_entry.local_path(output).exists() {
+            updates += 1;
+            "update"
+        } else {
+            downloads += 1;
+            "download"
+        };
+        if changed {
+            total_size += remote_entry.source_size;
+        }
+        println!(
+            "{:<8} {} ({})",
+            action,
+            remote_entry.source_path,
+            format_size(remote_entry.source_size as u64)
+        );
+    }
+
+    println!(
+        "{} to download, {} to update, {} up to date, {} in total",
+        downloads,
+        updates,
+        skipped,
+        format_size(total_size as u64)
+    );
+}
+
+pub fn format_size(size: u64) -> String {
+    size.file_size(file_size_opts::CONVENTIONAL)
+        .unwrap_or_else(|_| format!("{} B", size))
+}
+
+/// Whether `path` is a text file which is always downloaded whole
+fn is_text_file(path: &Path) -> bool {
+    path.extension()
+        .and_then(|s| s.to_str())
+        .is_some_and(|ext| TEXT_FILE_EXTENSIONS.contains(&ext))
+}
+
+/// Disk space needed to update `files_to_update`
+fn required_space(output: &Path, files_to_update: &[(Vec<Url>, RemoteManifestFileEntry)]) -> u64 {
+    files_to_update
+        .iter()
+        .map(|(_, remote_entry)| {
+            let output_path = remote_entry.local_path(output);
+
+            // Text files are downloaded whole next to the original, other
+            // files are updated in place so we only need room for them to grow
+            if is_text_file(&output_path) {
+                return remote_entry.source_size as u64;
+            }
+            let local_size = std::fs::metadata(&output_path)
+                .map(|metadata| metadata.len())
+                .unwrap_or(0);
+            (remote_entry.source_size as u64).saturating_sub(local_size)
+        })
+        .sum()
+}
+
+/// Make sure there are `required` bytes of free disk space for the update
+/// while keeping at least `min_free_space` bytes free.
+fn check_disk_space(output: &Path, required: u64, min_free_space: u64) -> anyhow::Result<()> {
+    let available = fs2::available_space(output).context(format!(
+        "Failed to get the available disk space for {}",
+        output.display()
+    ))?;
+
+    if required > available {
+        bail!(
+            "Not enough disk space for the update: need {}, have {}",
+            format_size(required),
+            format_size(available)
+        );
+    }
+
+    if available - required < min_free_space {
+        bail!(
+            "The update needs {} which would leave only {} of free disk space, less than the {} to keep free",
+            format_size(required),
+            format_size(available - required),
+            format_size(min_free_space)
+        );
+    }
+
+    Ok(())
+}
+
+/// Clone a remote file to `output_path`
+///
+/// Empty files don't have any chunks to clone so they are created directly
+/// rather than fetching their archive.
+async fn clone_file<P: UpdateProgress>(
+    clone_urls: &[Url],
+    output_path: &Path,
+    remote_entry: &RemoteManifestFileEntry,
+    progress: P,
+    clone_options: &CloneOptions,
+) -> anyhow::Result<()> {
+    if remote_entry.source_size == 0 {
+        if let Some(output_parent) = output_path.parent() {
+            fs::create_dir_all(output_parent).await?;
+        }
+        File::create(output_path).await?;
+        return Ok(());
+    }
+
+    let progress = FileProgress {
+        progress,
+        source_path: remote_entry.source_path.clone(),
+    };
+
+    if is_text_file(output_path) {
+        // Bitar doesn't handle text files well so they are always downloaded
+        // whole into an empty temporary file. The temporary file only replaces
+        // the original once it is complete so an interrupted download never
+        // leaves a partial file in place.
+        let mut temp_name = output_path.file_name().unwrap_or_default().to_os_string();
+        temp_name.push(".");
+        temp_name.push(TEMP_FILE_EXT);
+        let temp_path = output_path.with_file_name(temp_name);
+
+        if temp_path.exists() {
+            fs::remove_file(&temp_path).await?;
+        }
+
+        clone_remote_with_fallbacks(clone_urls, &temp_path, progress, clone_options).await?;
+        fs::rename(&temp_path, output_path).await.context(format!(
+            "Failed to move {} to {}",
+            temp_path.display(),
+            output_path.display()
+        ))?;
+        return Ok(());
+    }
+
+    clone_remote_with_fallbacks(clone_urls, output_path, progress, clone_options).await
+}
+
+/// Clone a remote file and check the result against the hash in the manifest,
+/// downloading the whole file again if it doesn't match
+///
+/// The whole-file check is skipped when `verify` is false, i.e. when the
+/// manifest was hashed with an algorithm this updater doesn't compute.
+async fn clone_and_verify_file<P: UpdateProgress>(
+    clone_urls: &[Url],
+    output_path: &Path,
+    remote_entry: &RemoteManifestFileEntry,
+    progress: P,
+    clone_options: &CloneOptions,
+    verify: bool,
+) -> anyhow::Result<()> {
+    clone_file(
+        clone_urls,
+        output_path,
+        remote_entry,
+        progress.clone(),
+        clone_options,
+    )
+    .await?;
+    if !verify || verify_output_file(output_path, &remote_entry.source_hash).await? {
+        return Ok(());
+    }
+
+    warn!(
+        path =? output_path.display(),
+        "Cloned file doesn't match the manifest, downloading it again"
+    );
+    fs::remove_file(output_path).await?;
+    clone_file(
+        clone_urls,
+        output_path,
+        remote_entry,
+        progress,
+        clone_options,
+    )
+    .await?;
+    if !verify_output_file(output_path, &remote_entry.source_hash).await? {
+        bail!(
+            "{} doesn't match the manifest after downloading it again",
+            output_path.display()
+        );
+    }
+
+    Ok(())
+}
+
+/// Apply the permission bits `mode` to the up to date file at `output_path`
+/// when they differ from the ones recorded in `local_entry`
+fn reapply_mode(
+    output_path: &Path,
+    ownership: &FileOwnership,
+    mode: Option<u32>,
+    local_entry: &mut LocalManifestFileEntry,
+) {
+    if local_entry.mode == mode {
+        return;
+    }
+    match ownership.apply(output_path, mode) {
+        Ok(()) => local_entry.mode = mode,
+        Err(e) => {
+            warn!(path =? output_path.display(), error =? e, "Failed to set file permissions")
+        }
+    }
+}
+
+fn get_remote_files<P: UpdateProgress>(
+    output: &Path,
+    files_to_update: Vec<(Vec<Url>, RemoteManifestFileEntry)>,
+    progress: P,
+    clone_options: CloneOptions,
+    ownership: FileOwnership,
+    verify: bool,
+    shutdown_rx: tokio::sync::watch::Receiver<bool>,
+    tx: tokio::sync::mpsc::Sender<LocalManifestFileEntry>,
+) -> anyhow::Result<Vec<tokio::task::JoinHandle<()>>> {
+    let mut clone_tasks = Vec::new();
+
+    for entry in files_to_update {
+        let (clone_urls, remote_entry) = entry;
+        let progress = progress.clone();
+        let clone_options = clone_options.clone();
+        let output_path = remote_entry.local_path(output);
+        let mut cloned_shutdown = shutdown_rx.clone();
+        let cloned_tx = tx.clone();
+
+        clone_tasks.push(tokio::spawn(async move {
+            let clone_url = &clone_urls[0];
+            info!("Downloading {}", clone_url);
+            tokio::select! {
+                res = clone_and_verify_file(
+                    &clone_urls,
+                    &output_path,
+                    &remote_entry,
+                    progress,
+                    &clone_options,
+                    verify) => if res.is_ok() {
+                        info!("Cloned {} to {}", clone_url, output_path.display());
+                        if let Err(e) = ownership.apply(&output_path, remote_entry.mode) {
+                            warn!(path =? output_path.display(), error =? e, "Failed to set file permissions");
+                        }
+                        cloned_tx.send(LocalManifestFileEntry {
+                            path: remote_entry.source_path.clone(),
+                            hash: remote_entry.source_hash.clone(),
+                            size: remote_entry.source_size,
+                            mode: remote_entry.mode,
+                            modified_at: file_modified_at(&output_path),
+                        }).await.expect("Failed to send clone message");
+                    } else {
+                        error!("Failed to clone {}", clone_url);
+                    },
+                _ = cloned_shutdown.changed() => {
+                    info!("Stopped cloning {}", clone_url);
+                }
+            }
+        }));
+    }
+
+    Ok(clone_tasks)
+}
+
+/// Update the client in `config.output` to the remote manifest, reporting the
+/// progress to `progress`.
+///
+/// The update stops with [`UpdateCancelled`] once `shutdown_rx` changes,
+/// keeping the files completed so far so the next run picks up from there.
+pub async fn run_update<P: UpdateProgress>(
+    config: &UpdateConfig,
+    progress: P,
+    mut shutdown_rx: tokio::sync::watch::Receiver<bool>,
+) -> anyhow::Result<UpdateOutcome> {
+    let remote_url = &config.url;
+    let clone_options = &config.clone_options;
+
+    if config.preflight {
+        tokio::select! {
+            res = preflight(config) => res?,
+            _ = shutdown_rx.changed() => return Err(UpdateCancelled.into())
+        };
+    }
+
+    let manifest_cache_path = config
+        .output
+        .join("updater")
+        .join(config.manifest_url.host_str().unwrap_or("default"))
+        .join(REMOTE_MANIFEST_CACHE_NAME);
+    let remote_manifest = tokio::select! {
+        res = get_remote_manifest(
+            config.manifest_url.clone(),
+            config.repair,
+            &manifest_cache_path,
+            clone_options,
+        ) => res,
+        _ = shutdown_rx.changed() => return Err(UpdateCancelled.into())
+    };
+
+    // Diagnose why the manifest couldn't be downloaded so it's clear whether
+    // DNS, the connection or the server is at fault
+    let mut remote_manifest = match remote_manifest {
+        Ok(remote_manifest) => remote_manifest,
+        Err(e) => {
+            let failures = tokio::select! {
+                res = preflight(config) => res?,
+                _ = shutdown_rx.changed() => return Err(UpdateCancelled.into())
+            };
+            if failures.is_empty() {
+                return Err(e);
+            }
+            bail!("{}\n\n{}", e, failures.join("\n"));
+        }
+    };
+    remote_manifest.verify_files_hash()?;
+
+    if let Some(arch) = &remote_manifest.arch {
+        if arch != std::env::consts::ARCH {
+            bail!(
+                "This launcher build ({}) doesn't match the game build for your system ({}), please download the launcher again from {}",
+                std::env::consts::ARCH,
+                arch,
+                config.support_url
+            );
+        }
+    }
+
+    if config.verify_only {
+        let report = tokio::select! {
+            res = verify_only(config, &remote_manifest, &progress) => res?,
+            _ = shutdown_rx.changed() => return Err(UpdateCancelled.into())
+        };
+        progress.set_stage(ProgressStage::Done);
+        progress.set_status(report.describe());
+        return Ok(UpdateOutcome::Verified(report));
+    }
+
+    // The updater can use different "profiles" to use the same updater for different clients
+    let local_manifest_path = config
+        .output
+        .join("updater")
+        .join(remote_url.host_str().unwrap_or("default"))
+        .join("local_manifest.json");
+
+    // A stale server cache may serve an older manifest, which would take the
+    // client back to an older release
+    let installed_release = installed_release(&local_manifest_path).await;
+    if remote_manifest.release < installed_release && !config.allow_downgrade {
+        bail!(
+            "The update server offered release {} but release {} is already installed, which \
+             usually means the server is still serving an old update. Please try again later.",
+            remote_manifest.release,
+            installed_release
+        );
+    }
+
+    // A previous run may have been stopped halfway through updating the
+    // updater, leaving only the previous updater behind
+    if !config.skip_updater && !config.dry_run {
+        recover_interrupted_updater(&remote_manifest.updater.local_path(&config.output))?;
+    }
+
+    if remote_manifest.total_source_size > 0 {
+        progress.set_status(config.strings.format(
+            "update_size",
+            &[&format_size(remote_manifest.total_source_size as u64)],
+        ));
+    }
+
+    // Hashes made with another algorithm never match ours, so nothing recorded
+    // locally can be trusted and every file has to be checked again
+    let hash_algorithm_changed = remote_manifest.hash_algorithm != HASH_ALGORITHM;
+    if hash_algorithm_changed {
+        warn!(
+            remote = %remote_manifest.hash_algorithm,
+            supported = HASH_ALGORITHM,
+            "The remote manifest uses a different hash algorithm, rechecking all files"
+        );
+    }
+
+    let local_manifest = if config.force_recheck || config.repair || hash_algorithm_changed {
+        info!("Ignoring the cached local manifest");
+        None
+    } else {
+        tokio::select! {
+            res = get_local_manifest(&local_manifest_path, !config.dry_run) => res?,
+            _ = shutdown_rx.changed() => return Err(UpdateCancelled.into())
+        }
+    };
+
+    let mut local_manifest = match local_manifest {
+        Some(local_manifest) => local_manifest,
+        None => tokio::select! {
+            res = async {
+                progress.set_stage(ProgressStage::Rebuilding);
+                rebuild_local_manifest(&config.output, &remote_manifest, &progress).await
+            } => res?,
+            _ = shutdown_rx.changed() => return Err(UpdateCancelled.into())
+        },
+    };
+
+    // First, we check if the updater itself needs an update. If it does then we
+    // will only update the updater then start the process again to update the
+    // rest of the files.
+    let updater_output_path = remote_manifest.updater.local_path(&config.output);
+    let updater_needs_update = remote_manifest.updater.source_hash != local_manifest.updater.hash
+        || local_manifest.updater.is_modified(&updater_output_path);
+
+    let updater_needs_update =
+        !config.skip_updater && (config.force_recheck_updater || updater_needs_update);
+    if updater_needs_update && !config.dry_run {
+        let local_updater_path = remote_manifest.updater.local_path(&config.output);
+
+        // The running updater is kept as a backup while the new one is
+        // downloaded next to it
+        fs::create_dir_all(&config.output).await?;
+        check_disk_space(
+            &config.output,
+            remote_manifest.updater.source_size as u64,
+            config.min_free_space,
+        )?;
+
+        progress.set_stage(ProgressStage::Updater);
+        progress
+            .set_max_progress(remote_manifest.updater.source_size)
+            .await;
+
+        let remote = remote_manifest.updater.archive_urls(remote_url)?;
+
+        tokio::select! {
+            res = update_updater(&local_updater_path, &updater_output_path, &remote, progress, clone_options) => res?,
+            _ = shutdown_rx.changed() => return Err(UpdateCancelled.into())
+        }
+
+        // Never swap in an updater which doesn't match the manifest, unless
+        // the manifest uses a hash algorithm we can't compute
+        if !hash_algorithm_changed
+            && !verify_output_file(&updater_output_path, &remote_manifest.updater.source_hash)
+                .await?
+        {
+            rollback_updater(&updater_output_path)?;
+            bail!(
+                "The downloaded updater {} doesn't match the manifest, the previous updater was restored",
+                updater_output_path.display()
+            );
+        }
+
+        if let Err(e) = config
+            .ownership
+            .apply(&updater_output_path, remote_manifest.updater.mode)
+        {
+            warn!(error =? e, "Failed to set the updater permissions");
+        }
+
+        // We update the local manifest with only the data for the updater, the
+        // rest of the data should be updated the next time we run the updater.
+        let mut new_local_manifest = LocalManifest {
+            version: LOCAL_MANIFEST_VERSION,
+            updater: LocalManifestFileEntry {
+                path: remote_manifest.updater.source_path.clone(),
+                hash: remote_manifest.updater.source_hash.clone(),
+                size: remote_manifest.updater.source_size,
+                mode: remote_manifest.updater.mode,
+                modified_at: file_modified_at(&updater_output_path),
+            },
+            ..local_manifest
+        };
+
+        save_local_manifest(&local_manifest_path, &new_local_manifest).await?;
+
+        info!("Restarting updater");
+        let child = start_new_updater(&updater_output_path, |path| {
+            Command::new(path)
+                .args(
+                    config
+                        .restart_args
+                        .iter()
+                        // Prevent infinite loop of update rechecks by removing the forced updater check
+                        .filter(|arg| !arg.to_string_lossy().contains("force-recheck-updater")),
+                )
+                .spawn()
+        });
+        let child = match child {
+            Ok(child) => child,
+            Err(e) => {
+                // The restored updater no longer matches the manifest entry
+                // so it's updated again on the next run
+                new_local_manifest.updater = LocalManifestFileEntry::default();
+                save_local_manifest(&local_manifest_path, &new_local_manifest).await?;
+                return Err(e);
+            }
+        };
+
+        return Ok(UpdateOutcome::UpdaterUpdated(child));
+    }
+
+    if !config.dry_run && local_manifest.updater.hash == remote_manifest.updater.source_hash {
+        reapply_mode(
+            &updater_output_path,
+            &config.ownership,
+            remote_manifest.updater.mode,
+            &mut local_manifest.updater,
+        );
+    }
+
+    // Create a lookup table for our local cache data so we can compare to remote manifest
+    let mut current_local_filedata: HashMap<PathBuf, LocalManifestFileEntry> = HashMap::new();
+    for entry in &local_manifest.files {
+        current_local_filedata.insert(PathBuf::from(&entry.path), entry.clone());
+    }
+
+    // Remember the state before updating so we can report what changed
+    let previous_hashes: HashMap<String, Vec<u8>> = local_manifest
+        .files
+        .iter()
+        .map(|entry| (entry.path.clone(), entry.hash.clone()))
+        .collect();
+
+    // Files completed by an interrupted run are more recent than the local
+    // manifest so they take precedence.
+    let checkpoint_path = local_manifest_path.with_file_name(CHECKPOINT_NAME);
+    let checkpoint_key = checkpoint_key(&remote_manifest);
+    let checkpoint = if config.repair || hash_algorithm_changed {
+        HashMap::new()
+    } else {
+        read_checkpoint(&checkpoint_path, &checkpoint_key).await
+    };
+    if !checkpoint.is_empty() {
+        info!(
+            "Resuming from checkpoint with {} completed files",
+            checkpoint.len()
+        );
+    }
+    for (path, entry) in &checkpoint {
+        current_local_filedata.insert(path.clone(), entry.clone());
+    }
+
+    // Repairs don't trust anything which was verified before
+    let verify_cache_path = local_manifest_path.with_file_name(VERIFY_CACHE_NAME);
+    let mut verify_cache = if config.repair || hash_algorithm_changed {
+        HashMap::new()
+    } else {
+        read_verify_cache(&verify_cache_path).await
+    };
+
+    let launch_command = remote_manifest.launch.clone();
+    let prune_manifest = config.prune.then(|| remote_manifest.clone());
+    let dry_run_manifest = config.dry_run.then(|| remote_manifest.clone());
+    let symlinks = std::mem::take(&mut remote_manifest.symlinks);
+
+    let available_groups = remote_manifest.groups();
+    if !available_groups.is_empty() {
+        info!("Available content groups: {:?}", available_groups);
+    }
+    if let Some(groups) = &config.groups {
+        info!("Installing content groups: {:?}", groups);
+        remote_manifest
+            .files
+            .retain(|remote_entry| remote_entry.in_groups(groups));
+    }
+    let remote_file_count = remote_manifest.files.len();
+
+    progress.set_stage(ProgressStage::Verifying);
+    let VerificationResults {
+        files_to_update,
+        mode_changes,
+        total_size,
+        already_downloaded_size,
+    } = verify_local_files(
+        &config.output,
+        remote_url,
+        remote_manifest,
+        &current_local_filedata,
+        &checkpoint,
+        &verify_cache,
+        config.verify,
+    )?;
+
+    if let Some(dry_run_manifest) = &dry_run_manifest {
+        print_dry_run(
+            &config.output,
+            dry_run_manifest,
+            updater_needs_update,
+            &files_to_update,
+        );
+        return Ok(UpdateOutcome::DryRun);
+    }
+
+    fs::create_dir_all(&config.output).await?;
+    for remote_entry in &mode_changes {
+        if let Some(local_entry) =
+            current_local_filedata.get_mut(&PathBuf::from(&remote_entry.source_path))
+        {
+            reapply_mode(
+                &remote_entry.local_path(&config.output),
+                &config.ownership,
+                remote_entry.mode,
+                local_entry,
+            );
+        }
+    }
+
+    check_disk_space(
+        &config.output,
+        required_space(&config.output, &files_to_update),
+        config.min_free_space,
+    )?;
+
+    progress.set_stage(ProgressStage::Downloading);
+    progress.set_max_progress(total_size).await;
+    progress.increment_progress(already_downloaded_size).await;
+
+    let (tx, mut rx) = tokio::sync::mpsc::channel::<LocalManifestFileEntry>(64);
+
+    if let Some(checkpoint_dir) = checkpoint_path.parent() {
+        fs::create_dir_all(checkpoint_dir).await?;
+    }
+    if checkpoint.is_empty() {
+        start_checkpoint(&checkpoint_path, &checkpoint_key).await?;
+    }
+
+    let files_to_update_count = files_to_update.len();
+    let files_to_update_paths: Vec<String> = files_to_update
+        .iter()
+        .map(|(_, remote_entry)| remote_entry.source_path.clone())
+        .collect();
+    let work_checkpoint_path = checkpoint_path.clone();
+    let release = remote_manifest.release;
+    let work = tokio::spawn(async move {
+        let mut hash_new_local_manifest = HashSet::new();
+        let mut new_local_manifest = LocalManifest {
+            version: LOCAL_MANIFEST_VERSION,
+            updater: local_manifest.updater,
+            release,
+            ..Default::default()
+        };
+
+        while let Some(manifest) = rx.recv().await {
+            if let Err(e) = append_checkpoint(&work_checkpoint_path, &manifest).await {
+                warn!(error =? e, "Failed to write checkpoint");
+            }
+            hash_new_local_manifest.insert(PathBuf::from(&manifest.path));
+            new_local_manifest.files.push(manifest);
+        }
+
+        (hash_new_local_manifest, new_local_manifest)
+    });
+
+    let daily_cap = clone_options.daily_cap.clone();
+    let clone_tasks = get_remote_files(
+        &config.output,
+        files_to_update,
+        progress.clone(),
+        clone_options.clone(),
+        config.ownership,
+        !hash_algorithm_changed,
+        shutdown_rx.clone(),
+        tx,
+    )?;
+
+    futures::future::join_all(clone_tasks).await;
+    let (hash_new_local_manifest, mut new_local_manifest) = work.await?;
+    let completed_all = hash_new_local_manifest.len() == files_to_update_count;
+
+    for (path, local_entry) in current_local_filedata {
+        if !hash_new_local_manifest.contains(&path) {
+            new_local_manifest.files.push(local_entry);
+        }
+    }
+
+    save_local_manifest(&local_manifest_path, &new_local_manifest).await?;
+
+    // The files completed before cancelling are recorded above and partially
+    // downloaded files are resumed, so the next run picks up from here
+    if *shutdown_rx.borrow() {
+        return Err(UpdateCancelled.into());
+    }
+
+    if let Some(daily_cap) = &daily_cap {
+        daily_cap.save().await?;
+        if !completed_all && daily_cap.is_reached() {
+            bail!(
+                "The daily download limit of {} has been reached, the update will resume tomorrow",
+                format_size(daily_cap.cap())
+            );
+        }
+    }
+
+    // Every file which was cloned has just been verified
+    let local_paths: HashSet<&str> = new_local_manifest
+        .files
+        .iter()
+        .map(|entry| entry.path.as_str())
+        .collect();
+    verify_cache.retain(|path, _| local_paths.contains(path.as_str()));
+    for entry in &new_local_manifest.files {
+        if !hash_new_local_manifest.contains(&PathBuf::from(&entry.path)) {
+            continue;
+        }
+        let local_path = config.output.join(PathBuf::from_slash(&entry.path));
+        if let Some(stamp) = FileStamp::read(&local_path) {
+            verify_cache.insert(
+                entry.path.clone(),
+                VerifyCacheEntry {
+                    stamp,
+                    hash: entry.hash.clone(),
+                },
+            );
+        }
+    }
+    if let Err(e) = save_verify_cache(&verify_cache_path, &verify_cache).await {
+        warn!(error =? e, "Failed to save the verify cache");
+    }
+
+    let changed = changed_files(&previous_hashes, &new_local_manifest);
+    info!("Updated {} files", changed.len());
+    for path in &changed {
+        info!("Updated {}", path);
+    }
+
+    // Keep the checkpoint around until every file has been completed so an
+    // interrupted verify can resume where it left off.
+    if completed_all && checkpoint_path.exists() {
+        fs::remove_file(&checkpoint_path).await?;
+    }
+
+    progress.set_stage(ProgressStage::Cleanup);
+    let symlinks_created = create_symlinks(&config.output, &symlinks)?;
+    if symlinks_created > 0 {
+        info!("Created {} symlinks", symlinks_created);
+    }
+
+    if let Some(prune_manifest) = &prune_manifest {
+        if completed_all {
+            let pruned =
+                prune_local_files(&config.output, prune_manifest, &config.prune_protected_dirs)?;
+            info!("Deleted {} files not in the manifest", pruned.len());
+        } else {
+            warn!("Not pruning files as some files failed to update");
+        }
+    }
+
+    let summary = RunSummary {
+        verify: config.verify,
+        repair: config.repair,
+        files_checked: remote_file_count,
+        files_to_update: files_to_update_count,
+        files_updated: hash_new_local_manifest.len(),
+        bytes_updated: new_local_manifest
+            .files
+            .iter()
+            .filter(|entry| hash_new_local_manifest.contains(&PathBuf::from(&entry.path)))
+            .map(|entry| entry.size)
+            .sum(),
+        files_changed: changed.len(),
+        failed_files: files_to_update_paths
+            .into_iter()
+            .filter(|path| !hash_new_local_manifest.contains(&PathBuf::from(path)))
+            .collect(),
+    };
+    let summary_json = serde_json::to_string(&summary)?;
+    info!(summary = %summary_json, "{}", summary.describe());
+    let summary_path = local_manifest_path.with_file_name(RUN_SUMMARY_NAME);
+    if let Err(e) = fs::write(&summary_path, &summary_json).await {
+        warn!(error =? e, "Failed to save the run summary");
+    }
+
+    progress.set_stage(ProgressStage::Done);
+    progress.set_status(summary.describe_in(&config.strings));
+
+    Ok(UpdateOutcome::ApplicationUpdated(launch_command, summary))
+}
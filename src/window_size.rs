@@ -0,0 +1,55 @@
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::write_atomic;
+
+/// Size of the updater window, remembered across launches
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct WindowSize {
+    pub width: i32,
+    pub height: i32,
+}
+
+impl WindowSize {
+    /// Size the layout was designed for, which is also the smallest size it
+    /// fits into
+    pub const MIN: WindowSize = WindowSize {
+        width: 780,
+        height: 630,
+    };
+
+    /// Largest size accepted, anything larger is most likely a typo
+    pub const MAX: WindowSize = WindowSize {
+        width: 7680,
+        height: 4320,
+    };
+
+    /// Read the size saved at `path`, `None` if there is no usable size
+    pub fn load(path: &Path) -> Option<Self> {
+        let contents = std::fs::read(path).ok()?;
+        serde_json::from_slice(&contents).ok()
+    }
+
+    /// Save the size to `path` so it's used by the next launch
+    pub fn save(&self, path: &Path) -> anyhow::Result<()> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        write_atomic(path, |writer| Ok(serde_json::to_writer(writer, self)?))
+    }
+
+    /// The size limited to between [`WindowSize::MIN`] and [`WindowSize::MAX`]
+    pub fn clamped(self) -> Self {
+        Self {
+            width: self.width.clamp(Self::MIN.width, Self::MAX.width),
+            height: self.height.clamp(Self::MIN.height, Self::MAX.height),
+        }
+    }
+}
+
+impl Default for WindowSize {
+    fn default() -> Self {
+        Self::MIN
+    }
+}
@@ -0,0 +1,549 @@
+mod common;
+
+use std::path::Path;
+
+use tokio::fs;
+
+use common::{default_clone_options, source_data, test_dir, NullUpdater, TestServer};
+use rose_update::{
+    clone_remote, create_archives, ArchiveCompression, ArchiveOptions, ChunkBudget, CloneOptions,
+    RemoteManifest, RemoteManifestSymlink,
+};
+#[cfg(unix)]
+use rose_update::{create_symlinks, SymlinkMode};
+
+/// Write a small client with a few nested files to `input`
+async fn write_client(input: &Path) -> anyhow::Result<()> {
+    fs::create_dir_all(input.join("3DDATA/STB")).await?;
+    fs::create_dir_all(input.join("SOUND")).await?;
+    fs::write(input.join("rose-updater.exe"), source_data(64 * 1024, 30)).await?;
+    fs::write(input.join("trose.exe"), source_data(512 * 1024, 31)).await?;
+    fs::write(
+        input.join("3DDATA/STB/LIST_ZONE.STB"),
+        source_data(32 * 1024, 32),
+    )
+    .await?;
+    fs::write(
+        input.join("3DDATA/STB/LIST_NPC.STB"),
+        source_data(16 * 1024, 33),
+    )
+    .await?;
+    fs::write(input.join("SOUND/CLICK.WAV"), source_data(8 * 1024, 34)).await?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn parallel_archives_match_sequential() -> anyhow::Result<()> {
+    let dir = test_dir("parallel-archives");
+    let _ = fs::remove_dir_all(&dir).await;
+    let input = dir.join("input");
+    write_client(&input).await?;
+
+    let sequential = create_archives(
+        &input,
+        &dir.join("sequential"),
+        &ArchiveOptions {
+            jobs: 1,
+            ..Default::default()
+        },
+    )
+    .await?;
+    let parallel = create_archives(
+        &input,
+        &dir.join("parallel"),
+        &ArchiveOptions {
+            jobs: 4,
+            ..Default::default()
+        },
+    )
+    .await?;
+
+    assert_eq!(
+        serde_json::to_string(&sequential)?,
+        serde_json::to_string(&parallel)?
+    );
+    assert_eq!(sequential.updater.source_path, "rose-updater.exe");
+    let source_paths: Vec<&str> = parallel
+        .files
+        .iter()
+        .map(|entry| entry.source_path.as_str())
+        .collect();
+    assert_eq!(
+        source_paths,
+        vec![
+            "3DDATA/STB/LIST_NPC.STB",
+            "3DDATA/STB/LIST_ZONE.STB",
+            "SOUND/CLICK.WAV",
+            "trose.exe",
+        ]
+    );
+
+    fs::remove_dir_all(&dir).await?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn unchanged_files_keep_their_archive() -> anyhow::Result<()> {
+    let dir = test_dir("incremental-archives");
+    let _ = fs::remove_dir_all(&dir).await;
+    let input = dir.join("input");
+    let output = dir.join("output");
+    fs::create_dir_all(&input).await?;
+    fs::write(input.join("rose-updater.exe"), source_data(16 * 1024, 40)).await?;
+    fs::write(input.join("trose.exe"), source_data(64 * 1024, 41)).await?;
+    fs::write(input.join("LIST_ZONE.STB"), source_data(16 * 1024, 42)).await?;
+
+    let options = ArchiveOptions {
+        jobs: 1,
+        ..Default::default()
+    };
+    let previous = create_archives(&input, &output, &options).await?;
+
+    // Mark the existing archives so we can tell which ones were rebuilt
+    let archives = ["rose-updater.exe.cba", "trose.exe.cba", "LIST_ZONE.STB.cba"];
+    for archive in archives {
+        fs::write(output.join("data").join(archive), "stale").await?;
+    }
+    fs::write(input.join("trose.exe"), source_data(64 * 1024, 43)).await?;
+
+    let manifest = create_archives(
+        &input,
+        &output,
+        &ArchiveOptions {
+            existing_manifest: Some(previous.clone()),
+            ..options
+        },
+    )
+    .await?;
+
+    assert_eq!(
+        fs::read(output.join("data/rose-updater.exe.cba")).await?,
+        b"stale"
+    );
+    assert_eq!(
+        fs::read(output.join("data/LIST_ZONE.STB.cba")).await?,
+        b"stale"
+    );
+    assert_ne!(fs::read(output.join("data/trose.exe.cba")).await?, b"stale");
+
+    assert_eq!(manifest.updater.source_hash, previous.updater.source_hash);
+    assert_eq!(manifest.files[0].source_path, "LIST_ZONE.STB");
+    assert_eq!(manifest.files[0].source_hash, previous.files[0].source_hash);
+    assert_eq!(manifest.files[1].source_path, "trose.exe");
+    assert_ne!(manifest.files[1].source_hash, previous.files[1].source_hash);
+
+    fs::remove_dir_all(&dir).await?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn changed_compression_rebuilds_every_archive() -> anyhow::Result<()> {
+    let dir = test_dir("recompressed-archives");
+    let _ = fs::remove_dir_all(&dir).await;
+    let input = dir.join("input");
+    let output = dir.join("output");
+    fs::create_dir_all(&input).await?;
+    fs::write(input.join("rose-updater.exe"), source_data(16 * 1024, 44)).await?;
+    fs::write(input.join("trose.exe"), source_data(64 * 1024, 45)).await?;
+
+    let previous = create_archives(&input, &output, &ArchiveOptions::default()).await?;
+    assert_eq!(previous.compression_level, Some(4));
+
+    let archives = ["rose-updater.exe.cba", "trose.exe.cba"];
+    for (compression, level) in [
+        (ArchiveCompression::Zstd, Some(9)),
+        (ArchiveCompression::Brotli, None),
+    ] {
+        // Mark the existing archives so we can tell which ones were rebuilt
+        for archive in archives {
+            fs::write(output.join("data").join(archive), "stale").await?;
+        }
+
+        let manifest = create_archives(
+            &input,
+            &output,
+            &ArchiveOptions {
+                compression,
+                compression_level: level,
+                existing_manifest: Some(previous.clone()),
+                ..Default::default()
+            },
+        )
+        .await?;
+        assert_eq!(manifest.compression.as_deref(), Some(compression.name()));
+        assert_eq!(
+            manifest.compression_level,
+            Some(level.unwrap_or_else(|| compression.default_level()))
+        );
+        for archive in archives {
+            assert_ne!(fs::read(output.join("data").join(archive)).await?, b"stale");
+        }
+    }
+
+    fs::remove_dir_all(&dir).await?;
+    Ok(())
+}
+
+/// Archive a small client with `compression` and clone it back
+async fn round_trip(
+    name: &str,
+    compression: ArchiveCompression,
+    level: Option<u32>,
+) -> anyhow::Result<()> {
+    let dir = test_dir(name);
+    let _ = fs::remove_dir_all(&dir).await;
+    let input = dir.join("input");
+    let output = dir.join("output");
+    fs::create_dir_all(&input).await?;
+    fs::write(input.join("rose-updater.exe"), source_data(16 * 1024, 50)).await?;
+    let source = source_data(256 * 1024, 51);
+    fs::write(input.join("trose.exe"), &source).await?;
+
+    let manifest = create_archives(
+        &input,
+        &output,
+        &ArchiveOptions {
+            compression,
+            compression_level: level,
+            ..Default::default()
+        },
+    )
+    .await?;
+    assert_eq!(manifest.compression.as_deref(), Some(compression.name()));
+
+    let server = TestServer::serve_dir(&output).await?;
+    let clone_options = CloneOptions {
+        chunk_budget: ChunkBudget::new(source.len()),
+        ..default_clone_options()
+    };
+    let cloned_path = dir.join("trose.exe");
+    clone_remote(
+        &server.url(&manifest.files[0].path),
+        &cloned_path,
+        NullUpdater,
+        &clone_options,
+    )
+    .await?;
+    assert!(fs::read(&cloned_path).await? == source);
+
+    fs::remove_dir_all(&dir).await?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn zstd_archives_round_trip() -> anyhow::Result<()> {
+    round_trip("compression-zstd", ArchiveCompression::Zstd, Some(4)).await
+}
+
+#[tokio::test]
+async fn brotli_archives_round_trip() -> anyhow::Result<()> {
+    round_trip("compression-brotli", ArchiveCompression::Brotli, Some(6)).await
+}
+
+#[tokio::test]
+async fn lzma_archives_round_trip() -> anyhow::Result<()> {
+    round_trip("compression-lzma", ArchiveCompression::Lzma, Some(6)).await
+}
+
+#[tokio::test]
+async fn uncompressed_archives_round_trip() -> anyhow::Result<()> {
+    round_trip("compression-none", ArchiveCompression::None, None).await
+}
+
+#[tokio::test]
+async fn cli_default_level_suits_the_algorithm() -> anyhow::Result<()> {
+    let dir = test_dir("compression-cli-default");
+    let _ = fs::remove_dir_all(&dir).await;
+    let input = dir.join("input");
+    fs::create_dir_all(&input).await?;
+    fs::write(input.join("rose-updater.exe"), source_data(1024, 53)).await?;
+
+    // Without --compression-level every algorithm uses a level it supports
+    for compression in ["zstd", "brotli", "lzma", "none"] {
+        let output = dir.join(compression);
+        let status = tokio::process::Command::new(env!("CARGO_BIN_EXE_rose-updater-archive"))
+            .arg(&input)
+            .arg(&output)
+            .args(["--compression", compression])
+            .status()
+            .await?;
+        assert!(status.success(), "{} failed with {}", compression, status);
+
+        let manifest: RemoteManifest =
+            serde_json::from_slice(&fs::read(output.join("manifest.json")).await?)?;
+        assert_eq!(manifest.compression.as_deref(), Some(compression));
+    }
+
+    fs::remove_dir_all(&dir).await?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn compression_level_is_checked_per_algorithm() -> anyhow::Result<()> {
+    let dir = test_dir("compression-level");
+    let _ = fs::remove_dir_all(&dir).await;
+    let input = dir.join("input");
+    fs::create_dir_all(&input).await?;
+    fs::write(input.join("rose-updater.exe"), source_data(1024, 52)).await?;
+
+    let result = create_archives(
+        &input,
+        &dir.join("output"),
+        &ArchiveOptions {
+            compression: ArchiveCompression::Brotli,
+            compression_level: Some(12),
+            ..Default::default()
+        },
+    )
+    .await;
+    assert!(result.is_err());
+    assert!(!dir.join("output").exists());
+
+    fs::remove_dir_all(&dir).await?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn excluded_files_are_not_archived() -> anyhow::Result<()> {
+    let dir = test_dir("archive-filters");
+    let _ = fs::remove_dir_all(&dir).await;
+    let input = dir.join("input");
+    let output = dir.join("output");
+    fs::create_dir_all(input.join("3DDATA/STB/OLD")).await?;
+    for path in [
+        "rose-updater.exe",
+        "trose.exe",
+        "Thumbs.db",
+        "3DDATA/Thumbs.db",
+        "3DDATA/STB/LIST_ZONE.STB",
+        "3DDATA/STB/LIST_ZONE.STB.bak",
+        "3DDATA/STB/OLD/LIST_NPC.STB",
+        "3DDATA/STB/OLD/KEEP.bak",
+    ] {
+        fs::write(input.join(path), path).await?;
+    }
+
+    let manifest = create_archives(
+        &input,
+        &output,
+        &ArchiveOptions {
+            exclude: vec![
+                "**/Thumbs.db".to_string(),
+                "**/*.bak".to_string(),
+                "3DDATA/STB/OLD/**".to_string(),
+            ],
+            include: vec!["3DDATA/**/KEEP.bak".to_string()],
+            ..Default::default()
+        },
+    )
+    .await?;
+
+    let source_paths: Vec<&str> = manifest
+        .files
+        .iter()
+        .map(|entry| entry.source_path.as_str())
+        .collect();
+    assert_eq!(
+        source_paths,
+        vec![
+            "3DDATA/STB/LIST_ZONE.STB",
+            "3DDATA/STB/OLD/KEEP.bak",
+            "trose.exe"
+        ]
+    );
+    assert!(!output.join("data/Thumbs.db.cba").exists());
+    assert!(!output.join("data/3DDATA/STB/OLD/LIST_NPC.STB.cba").exists());
+
+    fs::remove_dir_all(&dir).await?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn star_does_not_match_nested_directories() -> anyhow::Result<()> {
+    let dir = test_dir("archive-filter-star");
+    let _ = fs::remove_dir_all(&dir).await;
+    let input = dir.join("input");
+    fs::create_dir_all(input.join("LOGS/OLD")).await?;
+    for path in [
+        "rose-updater.exe",
+        "LOGS/today.log",
+        "LOGS/OLD/yesterday.log",
+    ] {
+        fs::write(input.join(path), path).await?;
+    }
+
+    let manifest = create_archives(
+        &input,
+        &dir.join("output"),
+        &ArchiveOptions {
+            exclude: vec!["LOGS/*.log".to_string()],
+            ..Default::default()
+        },
+    )
+    .await?;
+
+    let source_paths: Vec<&str> = manifest
+        .files
+        .iter()
+        .map(|entry| entry.source_path.as_str())
+        .collect();
+    assert_eq!(source_paths, vec!["LOGS/OLD/yesterday.log"]);
+
+    fs::remove_dir_all(&dir).await?;
+    Ok(())
+}
+
+/// Write a client with a symlink to a file and one to a directory
+#[cfg(unix)]
+async fn write_client_with_symlinks(input: &Path) -> anyhow::Result<()> {
+    fs::create_dir_all(input.join("3DDATA/STB")).await?;
+    fs::write(input.join("rose-updater.exe"), "updater").await?;
+    fs::write(input.join("trose.exe"), "client").await?;
+    fs::write(input.join("3DDATA/STB/LIST_ZONE.STB"), "zones").await?;
+    std::os::unix::fs::symlink("trose.exe", input.join("trose"))?;
+    std::os::unix::fs::symlink("3DDATA/STB", input.join("STB"))?;
+    Ok(())
+}
+
+#[cfg(unix)]
+async fn archive_symlinks(name: &str, symlinks: SymlinkMode) -> anyhow::Result<RemoteManifest> {
+    let dir = test_dir(name);
+    let _ = fs::remove_dir_all(&dir).await;
+    let input = dir.join("input");
+    write_client_with_symlinks(&input).await?;
+
+    let manifest = create_archives(
+        &input,
+        &dir.join("output"),
+        &ArchiveOptions {
+            symlinks,
+            ..Default::default()
+        },
+    )
+    .await?;
+
+    fs::remove_dir_all(&dir).await?;
+    Ok(manifest)
+}
+
+#[cfg(unix)]
+fn source_paths(manifest: &RemoteManifest) -> Vec<&str> {
+    manifest
+        .files
+        .iter()
+        .map(|entry| entry.source_path.as_str())
+        .collect()
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn followed_symlinks_are_archived_as_files() -> anyhow::Result<()> {
+    let manifest = archive_symlinks("symlinks-follow", SymlinkMode::Follow).await?;
+    assert_eq!(
+        source_paths(&manifest),
+        vec![
+            "3DDATA/STB/LIST_ZONE.STB",
+            "STB/LIST_ZONE.STB",
+            "trose",
+            "trose.exe"
+        ]
+    );
+    assert!(manifest.symlinks.is_empty());
+    Ok(())
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn skipped_symlinks_are_left_out() -> anyhow::Result<()> {
+    let manifest = archive_symlinks("symlinks-skip", SymlinkMode::Skip).await?;
+    assert_eq!(
+        source_paths(&manifest),
+        vec!["3DDATA/STB/LIST_ZONE.STB", "trose.exe"]
+    );
+    assert!(manifest.symlinks.is_empty());
+    Ok(())
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn stored_symlinks_are_recreated() -> anyhow::Result<()> {
+    let manifest = archive_symlinks("symlinks-store", SymlinkMode::Store).await?;
+    assert_eq!(
+        source_paths(&manifest),
+        vec!["3DDATA/STB/LIST_ZONE.STB", "trose.exe"]
+    );
+    assert_eq!(
+        manifest.symlinks,
+        vec![
+            RemoteManifestSymlink {
+                path: "STB".to_string(),
+                target: "3DDATA/STB".to_string(),
+            },
+            RemoteManifestSymlink {
+                path: "trose".to_string(),
+                target: "trose.exe".to_string(),
+            },
+        ]
+    );
+    manifest.verify_files_hash()?;
+
+    let output = test_dir("symlinks-store-client");
+    let _ = fs::remove_dir_all(&output).await;
+    fs::create_dir_all(&output).await?;
+    // A stale file in place of a link is replaced
+    fs::write(output.join("trose"), "stale").await?;
+
+    assert_eq!(create_symlinks(&output, &manifest.symlinks)?, 2);
+    assert_eq!(
+        fs::read_link(output.join("trose")).await?,
+        Path::new("trose.exe")
+    );
+    assert_eq!(
+        fs::read_link(output.join("STB")).await?,
+        Path::new("3DDATA/STB")
+    );
+    // Links which are already in place are left alone
+    assert_eq!(create_symlinks(&output, &manifest.symlinks)?, 0);
+
+    fs::remove_dir_all(&output).await?;
+    Ok(())
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn symlinks_outside_the_input_are_rejected() -> anyhow::Result<()> {
+    let dir = test_dir("symlinks-outside");
+    let _ = fs::remove_dir_all(&dir).await;
+    let input = dir.join("input");
+    fs::create_dir_all(&input).await?;
+    fs::write(input.join("rose-updater.exe"), "updater").await?;
+    std::os::unix::fs::symlink("../secret.txt", input.join("secret.txt"))?;
+
+    let result = create_archives(
+        &input,
+        &dir.join("output"),
+        &ArchiveOptions {
+            symlinks: SymlinkMode::Store,
+            ..Default::default()
+        },
+    )
+    .await;
+    assert!(result.is_err());
+
+    fs::remove_dir_all(&dir).await?;
+    Ok(())
+}
+
+#[test]
+fn symlinks_must_stay_in_the_client() {
+    let symlink = |path: &str, target: &str| RemoteManifestSymlink {
+        path: path.to_string(),
+        target: target.to_string(),
+    };
+    assert!(symlink("3DDATA/STB/LIST", "../../SOUND/LIST").is_contained());
+    assert!(symlink("trose", "./trose.exe").is_contained());
+    assert!(!symlink("3DDATA/LIST", "../../LIST").is_contained());
+    assert!(!symlink("trose", "/usr/bin/trose").is_contained());
+    assert!(!symlink("../trose", "trose.exe").is_contained());
+    assert!(!symlink("/trose", "trose.exe").is_contained());
+}
@@ -0,0 +1,45 @@
+mod common;
+
+use std::fs;
+use std::io::Write;
+
+use anyhow::bail;
+
+use common::test_dir;
+use rose_update::write_atomic;
+
+#[test]
+fn write_replaces_contents() -> anyhow::Result<()> {
+    let dir = test_dir("atomic-write");
+    fs::create_dir_all(&dir)?;
+    let path = dir.join("local_manifest.json");
+    fs::write(&path, "old")?;
+
+    write_atomic(&path, |writer| Ok(writer.write_all(b"new")?))?;
+
+    assert_eq!(fs::read_to_string(&path)?, "new");
+    assert!(!dir.join("local_manifest.json.tmp").exists());
+
+    fs::remove_dir_all(&dir)?;
+    Ok(())
+}
+
+#[test]
+fn failed_write_keeps_previous_contents() -> anyhow::Result<()> {
+    let dir = test_dir("atomic-write-failure");
+    fs::create_dir_all(&dir)?;
+    let path = dir.join("local_manifest.json");
+    fs::write(&path, "old")?;
+
+    let result = write_atomic(&path, |writer| {
+        writer.write_all(b"partial")?;
+        bail!("Simulated crash while writing")
+    });
+
+    assert!(result.is_err());
+    assert_eq!(fs::read_to_string(&path)?, "old");
+    assert!(!dir.join("local_manifest.json.tmp").exists());
+
+    fs::remove_dir_all(&dir)?;
+    Ok(())
+}
@@ -0,0 +1,100 @@
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use rustls::{Certificate, PrivateKey, RootCertStore, ServerConfig};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+use tokio_rustls::TlsAcceptor;
+
+use rose_update::{spki_pin, CertPins};
+
+/// Serve `ok` over TLS with a self-signed certificate for `localhost`,
+/// returning the address and the certificate
+async fn start_tls_server() -> anyhow::Result<(SocketAddr, Certificate)> {
+    let generated = rcgen::generate_simple_self_signed(vec!["localhost".to_string()])?;
+    let certificate = Certificate(generated.serialize_der()?);
+    let key = PrivateKey(generated.serialize_private_key_der());
+    let config = ServerConfig::builder()
+        .with_safe_defaults()
+        .with_no_client_auth()
+        .with_single_cert(vec![certificate.clone()], key)?;
+    let acceptor = TlsAcceptor::from(Arc::new(config));
+
+    let listener = TcpListener::bind("127.0.0.1:0").await?;
+    let addr = listener.local_addr()?;
+    tokio::spawn(async move {
+        while let Ok((stream, _)) = listener.accept().await {
+            let acceptor = acceptor.clone();
+            tokio::spawn(async move {
+                let mut stream = acceptor.accept(stream).await?;
+                let mut request = [0; 1024];
+                let _ = stream.read(&mut request).await?;
+                stream
+                    .write_all(
+                        b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\nConnection: close\r\n\r\nok",
+                    )
+                    .await?;
+                stream.shutdown().await
+            });
+        }
+    });
+
+    Ok((addr, certificate))
+}
+
+/// Request the server at `addr` with `pins`, trusting only `certificate`
+async fn request_with_pins(
+    addr: SocketAddr,
+    certificate: &Certificate,
+    pins: &CertPins,
+) -> reqwest::Result<String> {
+    let mut roots = RootCertStore::empty();
+    roots
+        .add(certificate)
+        .expect("Failed to trust the test certificate");
+    let client = reqwest::Client::builder()
+        .use_preconfigured_tls(pins.tls_config(roots))
+        .resolve("localhost", addr)
+        .build()?;
+    client
+        .get(format!("https://localhost:{}/", addr.port()))
+        .send()
+        .await?
+        .text()
+        .await
+}
+
+#[tokio::test]
+async fn matching_pin_is_accepted() -> anyhow::Result<()> {
+    let (addr, certificate) = start_tls_server().await?;
+    let pin = spki_pin(&certificate.0).expect("Failed to pin the test certificate");
+    let pins = CertPins::parse(&format!("localhost={}", pin))?;
+
+    assert_eq!(request_with_pins(addr, &certificate, &pins).await?, "ok");
+    Ok(())
+}
+
+#[tokio::test]
+async fn wrong_pin_rejects_connection() -> anyhow::Result<()> {
+    let (addr, certificate) = start_tls_server().await?;
+    let pins = CertPins::parse(&format!("localhost={}", "A".repeat(43) + "="))?;
+
+    assert!(request_with_pins(addr, &certificate, &pins).await.is_err());
+    Ok(())
+}
+
+#[tokio::test]
+async fn hosts_without_pins_are_not_checked() -> anyhow::Result<()> {
+    let (addr, certificate) = start_tls_server().await?;
+    let pins = CertPins::parse(&format!("example.com={}", "A".repeat(43) + "="))?;
+
+    assert_eq!(request_with_pins(addr, &certificate, &pins).await?, "ok");
+    Ok(())
+}
+
+#[test]
+fn invalid_pins_are_rejected() {
+    assert!(CertPins::parse("localhost").is_err());
+    assert!(CertPins::parse("localhost=not-a-pin").is_err());
+    assert!(CertPins::parse("").unwrap().is_empty());
+}
@@ -0,0 +1,523 @@
+mod common;
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+use tokio::fs;
+
+use common::{
+    create_archive, default_clone_options, source_data, test_dir, Behavior, NullUpdater, TestServer,
+};
+use rose_update::{
+    clone_remote, clone_remote_with_fallbacks, ChunkBudget, CloneOptions, DailyCap, RateLimiter,
+    RetryPolicy, Updater,
+};
+
+const SOURCE_SIZE: usize = 4 * 1024 * 1024;
+
+fn clone_options() -> CloneOptions {
+    CloneOptions {
+        chunk_budget: ChunkBudget::new(SOURCE_SIZE),
+        retry: RetryPolicy {
+            retries: 4,
+            ..default_clone_options().retry
+        },
+        ..default_clone_options()
+    }
+}
+
+/// Clone the archive of `source` into a file pre-seeded with `seed` and
+/// return the cloned bytes.
+async fn clone_with_seed(
+    name: &str,
+    source: &[u8],
+    seed: Option<&[u8]>,
+) -> anyhow::Result<Vec<u8>> {
+    clone_with_seed_and_options(name, source, seed, &clone_options()).await
+}
+
+/// [`clone_with_seed`] with `options` instead of the default options
+async fn clone_with_seed_and_options(
+    name: &str,
+    source: &[u8],
+    seed: Option<&[u8]>,
+    options: &CloneOptions,
+) -> anyhow::Result<Vec<u8>> {
+    let dir = test_dir(name);
+    fs::create_dir_all(&dir).await?;
+
+    let archive = create_archive(source, &dir).await?;
+    let server = TestServer::start().await?;
+    server.add_file("source.cba", archive);
+    let url = server.url("source.cba");
+
+    let output_path = dir.join("output");
+    if let Some(seed) = seed {
+        fs::write(&output_path, seed).await?;
+    }
+
+    clone_remote(&url, &output_path, NullUpdater, options).await?;
+
+    let output = fs::read(&output_path).await?;
+    fs::remove_dir_all(&dir).await?;
+    Ok(output)
+}
+
+#[tokio::test]
+async fn clone_into_missing_file() -> anyhow::Result<()> {
+    let source = source_data(SOURCE_SIZE, 1);
+    let output = clone_with_seed("missing", &source, None).await?;
+    assert!(output == source, "Cloned file does not match the source");
+    Ok(())
+}
+
+#[tokio::test]
+async fn clone_reuses_partially_matching_file() -> anyhow::Result<()> {
+    let source = source_data(SOURCE_SIZE, 2);
+
+    // Keep the first half of the source and replace the rest with other data
+    let mut seed = source[..SOURCE_SIZE / 2].to_vec();
+    seed.extend(source_data(SOURCE_SIZE / 4, 3));
+
+    let output = clone_with_seed("partial", &source, Some(&seed)).await?;
+    assert!(output == source, "Cloned file does not match the source");
+    Ok(())
+}
+
+#[tokio::test]
+async fn clone_truncates_larger_outdated_file() -> anyhow::Result<()> {
+    let source = source_data(SOURCE_SIZE, 4);
+
+    // Shift the source by inserting data in the middle and appending a tail
+    // so the seed is larger than the source
+    let mut seed = source[..SOURCE_SIZE / 4].to_vec();
+    seed.extend(source_data(64 * 1024, 5));
+    seed.extend(&source[SOURCE_SIZE / 4..]);
+    seed.extend(source_data(SOURCE_SIZE / 2, 6));
+
+    let output = clone_with_seed("larger", &source, Some(&seed)).await?;
+    assert!(output == source, "Cloned file does not match the source");
+    Ok(())
+}
+
+#[tokio::test]
+async fn clone_verifies_reordered_file() -> anyhow::Result<()> {
+    let source = source_data(SOURCE_SIZE, 32);
+
+    // Move the second half of the source to the front so the local chunks
+    // have to be reordered
+    let mut seed = source[SOURCE_SIZE / 2..].to_vec();
+    seed.extend(&source[..SOURCE_SIZE / 2]);
+
+    let options = CloneOptions {
+        verify_reorder: true,
+        ..clone_options()
+    };
+    let output =
+        clone_with_seed_and_options("verify-reorder", &source, Some(&seed), &options).await?;
+    assert!(output == source, "Cloned file does not match the source");
+    Ok(())
+}
+
+#[tokio::test]
+async fn clone_empty_file() -> anyhow::Result<()> {
+    let output = clone_with_seed("empty", &[], Some(&source_data(1024, 7))).await?;
+    assert!(output.is_empty(), "Cloned file should be empty");
+    Ok(())
+}
+
+#[tokio::test]
+async fn clone_falls_back_to_secondary_location() -> anyhow::Result<()> {
+    let source = source_data(SOURCE_SIZE, 8);
+    let dir = test_dir("fallback");
+    fs::create_dir_all(&dir).await?;
+
+    let archive = create_archive(&source, &dir).await?;
+    let server = TestServer::start().await?;
+    server.add_file("primary/source.cba", archive.clone());
+    server.add_file("fallback/source.cba", archive);
+    server.set_behavior(
+        "primary/source.cba",
+        Behavior {
+            status: Some(500),
+            ..Default::default()
+        },
+    );
+
+    let urls = [
+        server.url("primary/source.cba"),
+        server.url("fallback/source.cba"),
+    ];
+    let output_path = dir.join("output");
+    clone_remote_with_fallbacks(&urls, &output_path, NullUpdater, &clone_options()).await?;
+
+    let output = fs::read(&output_path).await?;
+    fs::remove_dir_all(&dir).await?;
+    assert!(output == source, "Cloned file does not match the source");
+    assert!(server.request_count("fallback/source.cba") > 0);
+    Ok(())
+}
+
+#[tokio::test]
+async fn clone_without_range_support() -> anyhow::Result<()> {
+    let source = source_data(SOURCE_SIZE, 9);
+    let dir = test_dir("no-range");
+    fs::create_dir_all(&dir).await?;
+
+    let archive = create_archive(&source, &dir).await?;
+    let server = TestServer::start().await?;
+    server.add_file("source.cba", archive);
+    server.set_behavior(
+        "source.cba",
+        Behavior {
+            ignore_range: true,
+            ..Default::default()
+        },
+    );
+
+    // Seed with part of the source so chunks are reused from the local file
+    let output_path = dir.join("output");
+    fs::write(&output_path, &source[..SOURCE_SIZE / 2]).await?;
+
+    let url = server.url("source.cba");
+    clone_remote(&url, &output_path, NullUpdater, &clone_options()).await?;
+
+    let output = fs::read(&output_path).await?;
+    fs::remove_dir_all(&dir).await?;
+    assert!(output == source, "Cloned file does not match the source");
+    Ok(())
+}
+
+#[tokio::test]
+async fn range_support_is_probed_once_per_host() -> anyhow::Result<()> {
+    let source = source_data(SOURCE_SIZE / 4, 30);
+    let dir = test_dir("range-probe");
+    fs::create_dir_all(&dir).await?;
+
+    let archive = create_archive(&source, &dir).await?;
+    let server = TestServer::start().await?;
+    for name in ["first.cba", "second.cba", "truncated.cba"] {
+        server.add_file(name, archive.clone());
+        server.set_behavior(
+            name,
+            Behavior {
+                ignore_range: true,
+                ..Default::default()
+            },
+        );
+    }
+    server.set_behavior(
+        "truncated.cba",
+        Behavior {
+            ignore_range: true,
+            truncate: Some(archive.len() / 2),
+            ..Default::default()
+        },
+    );
+
+    let options = clone_options();
+    let first_url = server.url("first.cba");
+    clone_remote(&first_url, &dir.join("first"), NullUpdater, &options).await?;
+    let second_url = server.url("second.cba");
+    clone_remote(&second_url, &dir.join("second"), NullUpdater, &options).await?;
+
+    // The second archive is downloaded straight away without probing
+    assert_eq!(server.request_count("first.cba"), 2);
+    assert_eq!(server.request_count("second.cba"), 1);
+    assert!(fs::read(dir.join("second")).await? == source);
+    assert!(!dir.join("first.archive").exists());
+
+    // The downloaded archive is removed when the clone fails too
+    let truncated_url = server.url("truncated.cba");
+    let result = clone_remote(
+        &truncated_url,
+        &dir.join("truncated"),
+        NullUpdater,
+        &options,
+    )
+    .await;
+    assert!(result.is_err());
+    assert!(!dir.join("truncated.archive").exists());
+
+    fs::remove_dir_all(&dir).await?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn daily_cap_counts_compressed_bytes() -> anyhow::Result<()> {
+    // Repeated data is stored once in the archive, so far fewer bytes are
+    // received than written
+    let source = source_data(64 * 1024, 31).repeat(16);
+    let dir = test_dir("daily-cap-compressed");
+    fs::create_dir_all(&dir).await?;
+
+    let archive = create_archive(&source, &dir).await?;
+    let server = TestServer::start().await?;
+    server.add_file("source.cba", archive);
+
+    let usage_path = dir.join("daily_usage.json");
+    let daily_cap = DailyCap::load(&usage_path, u64::MAX);
+    let options = CloneOptions {
+        daily_cap: Some(daily_cap.clone()),
+        ..clone_options()
+    };
+    let url = server.url("source.cba");
+    clone_remote(&url, &dir.join("output"), NullUpdater, &options).await?;
+    daily_cap.save().await?;
+
+    let usage: serde_json::Value = serde_json::from_slice(&fs::read(&usage_path).await?)?;
+    let used = usage["bytes"].as_u64().unwrap_or_default() as usize;
+    fs::remove_dir_all(&dir).await?;
+    assert!(used > 0);
+    assert!(
+        used < source.len() / 2,
+        "{} bytes of {} were counted",
+        used,
+        source.len()
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn clone_respects_bandwidth_limit() -> anyhow::Result<()> {
+    const BYTES_PER_SEC: usize = 1024 * 1024;
+    let source = source_data(3 * BYTES_PER_SEC, 10);
+    let dir = test_dir("bandwidth");
+    fs::create_dir_all(&dir).await?;
+
+    let archive = create_archive(&source, &dir).await?;
+    let server = TestServer::start().await?;
+    server.add_file("source.cba", archive);
+
+    let options = CloneOptions {
+        rate_limiter: Some(RateLimiter::new(BYTES_PER_SEC as u64)),
+        ..clone_options()
+    };
+    let output_path = dir.join("output");
+    let url = server.url("source.cba");
+    let start = Instant::now();
+    clone_remote(&url, &output_path, NullUpdater, &options).await?;
+    let elapsed = start.elapsed();
+
+    let output = fs::read(&output_path).await?;
+    fs::remove_dir_all(&dir).await?;
+    assert!(output == source, "Cloned file does not match the source");
+
+    // The first second's worth is allowed through as a burst
+    assert!(
+        elapsed >= Duration::from_millis(1500) && elapsed <= Duration::from_secs(6),
+        "Cloning 3 MB at 1 MB/s took {:?}",
+        elapsed
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn clone_resumes_after_interruption() -> anyhow::Result<()> {
+    let source = source_data(SOURCE_SIZE, 11);
+    let dir = test_dir("resume");
+    fs::create_dir_all(&dir).await?;
+
+    let archive = create_archive(&source, &dir).await?;
+    let archive_size = archive.len();
+    let server = TestServer::start().await?;
+    server.add_file("source.cba", archive);
+    server.set_behavior(
+        "source.cba",
+        Behavior {
+            bytes_per_sec: Some(archive_size / 4),
+            ..Default::default()
+        },
+    );
+
+    // Kill the clone roughly halfway through
+    let output_path = dir.join("output");
+    let url = server.url("source.cba");
+    let interrupted = tokio::time::timeout(
+        Duration::from_secs(2),
+        clone_remote(&url, &output_path, NullUpdater, &clone_options()),
+    )
+    .await;
+    assert!(
+        interrupted.is_err(),
+        "The clone should have been interrupted"
+    );
+    assert!(dir.join("output.progress").exists());
+
+    server.set_behavior("source.cba", Behavior::default());
+    let sent_before = server.bytes_sent("source.cba");
+    clone_remote(&url, &output_path, NullUpdater, &clone_options()).await?;
+    let resumed_size = server.bytes_sent("source.cba") - sent_before;
+
+    let output = fs::read(&output_path).await?;
+    let progress_exists = dir.join("output.progress").exists();
+    fs::remove_dir_all(&dir).await?;
+    assert!(output == source, "Cloned file does not match the source");
+    assert!(
+        !progress_exists,
+        "The progress file should have been removed"
+    );
+    assert!(
+        resumed_size < archive_size * 3 / 4,
+        "Resuming downloaded {} of {} archive bytes",
+        resumed_size,
+        archive_size
+    );
+    Ok(())
+}
+
+/// Updater counting the bytes cloned so far
+struct CountingUpdater(Arc<AtomicUsize>);
+
+#[async_trait]
+impl Updater for CountingUpdater {
+    async fn set_max_progress(&self, _total: usize) {}
+
+    async fn increment_progress(&self, amount: usize) {
+        self.0.fetch_add(amount, Ordering::SeqCst);
+    }
+}
+
+#[tokio::test]
+async fn clone_waits_while_paused() -> anyhow::Result<()> {
+    let source = source_data(SOURCE_SIZE, 9);
+    let dir = test_dir("paused");
+    fs::create_dir_all(&dir).await?;
+
+    let archive = create_archive(&source, &dir).await?;
+    let server = TestServer::start().await?;
+    server.add_file("source.cba", archive);
+
+    let options = clone_options();
+    options.pause.pause();
+
+    let progress = Arc::new(AtomicUsize::new(0));
+    let output_path = dir.join("output");
+    let clone = {
+        let url = server.url("source.cba");
+        let output_path = output_path.clone();
+        let options = options.clone();
+        let updater = CountingUpdater(progress.clone());
+        tokio::spawn(async move { clone_remote(&url, &output_path, updater, &options).await })
+    };
+
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    assert!(!clone.is_finished(), "The clone should wait while paused");
+    assert_eq!(progress.load(Ordering::SeqCst), 0);
+
+    options.pause.resume();
+    clone.await??;
+    assert_eq!(progress.load(Ordering::SeqCst), SOURCE_SIZE);
+    assert!(fs::read(&output_path).await? == source);
+
+    fs::remove_dir_all(&dir).await?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn clone_reuses_same_chunks_regardless_of_verify_jobs() -> anyhow::Result<()> {
+    let source = source_data(SOURCE_SIZE, 12);
+    let mut seed = source[..SOURCE_SIZE / 2].to_vec();
+    seed.extend(source_data(SOURCE_SIZE / 4, 13));
+    seed.extend(&source[SOURCE_SIZE * 3 / 4..]);
+
+    let mut downloaded = Vec::new();
+    for verify_jobs in [1, 8] {
+        let dir = test_dir(&format!("verify-jobs-{}", verify_jobs));
+        fs::create_dir_all(&dir).await?;
+
+        let archive = create_archive(&source, &dir).await?;
+        let server = TestServer::start().await?;
+        server.add_file("source.cba", archive);
+
+        let output_path = dir.join("output");
+        fs::write(&output_path, &seed).await?;
+
+        let options = CloneOptions {
+            verify_jobs,
+            ..clone_options()
+        };
+        let url = server.url("source.cba");
+        clone_remote(&url, &output_path, NullUpdater, &options).await?;
+
+        let output = fs::read(&output_path).await?;
+        fs::remove_dir_all(&dir).await?;
+        assert!(output == source, "Cloned file does not match the source");
+        downloaded.push(server.bytes_sent("source.cba"));
+    }
+
+    assert_eq!(
+        downloaded[0], downloaded[1],
+        "The same local data should be reused with any number of verify jobs"
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn clone_with_buffers_of_one_chunk() -> anyhow::Result<()> {
+    let source = source_data(SOURCE_SIZE, 14);
+    let dir = test_dir("buffer-one");
+    fs::create_dir_all(&dir).await?;
+
+    let archive = create_archive(&source, &dir).await?;
+    let server = TestServer::start().await?;
+    server.add_file("source.cba", archive);
+
+    // Reuse the middle of the source so both local and remote chunks are used
+    let output_path = dir.join("output");
+    fs::write(&output_path, &source[SOURCE_SIZE / 4..SOURCE_SIZE * 3 / 4]).await?;
+
+    let options = CloneOptions {
+        verify_jobs: 1,
+        remote_buffer: 1,
+        ..clone_options()
+    };
+    let url = server.url("source.cba");
+    clone_remote(&url, &output_path, NullUpdater, &options).await?;
+
+    let output = fs::read(&output_path).await?;
+    fs::remove_dir_all(&dir).await?;
+    assert!(output == source, "Cloned file does not match the source");
+    Ok(())
+}
+
+#[tokio::test]
+async fn mapped_and_read_local_files_reuse_the_same_chunks() -> anyhow::Result<()> {
+    let source = source_data(SOURCE_SIZE, 15);
+    let mut seed = source_data(SOURCE_SIZE / 8, 16);
+    seed.extend(&source[SOURCE_SIZE / 8..SOURCE_SIZE * 7 / 8]);
+
+    // Map every local file, then read every local file
+    let mut downloaded = Vec::new();
+    for mmap_threshold in [0, u64::MAX] {
+        let dir = test_dir(&format!("mmap-{}", mmap_threshold));
+        fs::create_dir_all(&dir).await?;
+
+        let archive = create_archive(&source, &dir).await?;
+        let server = TestServer::start().await?;
+        server.add_file("source.cba", archive);
+
+        let output_path = dir.join("output");
+        fs::write(&output_path, &seed).await?;
+
+        let options = CloneOptions {
+            mmap_threshold,
+            ..clone_options()
+        };
+        let url = server.url("source.cba");
+        clone_remote(&url, &output_path, NullUpdater, &options).await?;
+
+        let output = fs::read(&output_path).await?;
+        fs::remove_dir_all(&dir).await?;
+        assert!(output == source, "Cloned file does not match the source");
+        downloaded.push(server.bytes_sent("source.cba"));
+    }
+
+    assert_eq!(
+        downloaded[0], downloaded[1],
+        "Mapping the local file should reuse the same data as reading it"
+    );
+    Ok(())
+}
@@ -0,0 +1,361 @@
+//! Helpers shared by the integration tests
+
+// Not every test uses every helper
+#![allow(dead_code)]
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use async_trait::async_trait;
+use reqwest::Url;
+use tokio::fs;
+use tokio::fs::File;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+
+use rose_update::{
+    ChunkBudget, CloneOptions, DownloadLimit, PauseFlag, RangeSupport, RetryPolicy, Updater,
+    MMAP_THRESHOLD,
+};
+
+#[derive(Clone)]
+pub struct NullUpdater;
+
+#[async_trait]
+impl Updater for NullUpdater {
+    async fn set_max_progress(&self, _total: usize) {}
+    async fn increment_progress(&self, _amount: usize) {}
+}
+
+/// Generate deterministic pseudo-random data so the chunker finds boundaries
+pub fn source_data(size: usize, seed: u64) -> Vec<u8> {
+    let mut state = seed;
+    (0..size)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state as u8
+        })
+        .collect()
+}
+
+/// Clone options with a 4 MiB chunk budget, no retries and no limits, which
+/// tests override with `..default_clone_options()`
+pub fn default_clone_options() -> CloneOptions {
+    CloneOptions {
+        client: reqwest::Client::new(),
+        chunk_budget: ChunkBudget::new(4 * 1024 * 1024),
+        download_limit: DownloadLimit::new(8),
+        verify_reorder: false,
+        io_pacer: None,
+        daily_cap: None,
+        rate_limiter: None,
+        retry: RetryPolicy {
+            retries: 0,
+            delay: Duration::ZERO,
+            jitter: Duration::ZERO,
+        },
+        range_support: RangeSupport::default(),
+        pause: PauseFlag::default(),
+        verify_jobs: 4,
+        remote_buffer: 64,
+        mmap_threshold: MMAP_THRESHOLD,
+    }
+}
+
+pub fn test_dir(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("rose-updater-test-{}-{}", std::process::id(), name))
+}
+
+/// Archive `source` with the same options as the archive tool and return the
+/// archive bytes
+pub async fn create_archive(source: &[u8], dir: &Path) -> anyhow::Result<Vec<u8>> {
+    let input_path = dir.join("source");
+    let archive_path = dir.join("source.cba");
+    fs::write(&input_path, source).await?;
+
+    let mut input_file = File::open(&input_path).await?;
+    let mut output_file = File::create(&archive_path).await?;
+
+    let options = bitar::api::compress::CreateArchiveOptions {
+        chunker_config: bitar::chunker::Config::RollSum(bitar::chunker::FilterConfig {
+            filter_bits: bitar::chunker::FilterBits::from_size(64 * 1024),
+            min_chunk_size: 16 * 1024,
+            max_chunk_size: 16 * 1024 * 1024,
+            window_size: 64,
+        }),
+        compression: Some(bitar::Compression::zstd(4)?),
+        ..Default::default()
+    };
+    bitar::api::compress::create_archive(&mut input_file, &mut output_file, &options).await?;
+    output_file.flush().await?;
+
+    Ok(fs::read(&archive_path).await?)
+}
+
+/// How the test server misbehaves when serving a file
+#[derive(Clone, Debug, Default)]
+pub struct Behavior {
+    /// Respond with this status and an empty body instead of the file
+    pub status: Option<u16>,
+
+    /// Only respond with `status` for this many requests, `None` for all
+    pub status_times: Option<usize>,
+
+    /// Close the connection after sending this many bytes of the body
+    pub truncate: Option<usize>,
+
+    /// Ignore `Range` headers and always send the whole file
+    pub ignore_range: bool,
+
+    /// Limit the rate the body is sent at
+    pub bytes_per_sec: Option<usize>,
+
+    /// Send this `Content-Encoding` header, the file is served as is
+    pub content_encoding: Option<String>,
+
+    /// Send this `ETag` header and respond with `304 Not Modified` to requests
+    /// with a matching `If-None-Match` header
+    pub etag: Option<String>,
+}
+
+#[derive(Default)]
+struct ServerState {
+    files: HashMap<String, Vec<u8>>,
+    behaviors: HashMap<String, Behavior>,
+    requests: HashMap<String, usize>,
+    bytes_sent: HashMap<String, usize>,
+    user_agents: HashMap<String, String>,
+}
+
+/// A local HTTP server with support for single `Range` requests
+#[derive(Clone)]
+pub struct TestServer {
+    base_url: Url,
+    state: Arc<Mutex<ServerState>>,
+}
+
+impl TestServer {
+    pub async fn start() -> anyhow::Result<Self> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        let state = Arc::new(Mutex::new(ServerState::default()));
+
+        let server_state = state.clone();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let state = server_state.clone();
+                tokio::spawn(async move {
+                    let _ = handle_request(stream, &state).await;
+                });
+            }
+        });
+
+        Ok(Self {
+            base_url: Url::parse(&format!("http://{}/", addr))?,
+            state,
+        })
+    }
+
+    /// Start a server serving every file within `dir`
+    pub async fn serve_dir(dir: &Path) -> anyhow::Result<Self> {
+        let server = Self::start().await?;
+        for entry in walkdir::WalkDir::new(dir) {
+            let entry = entry?;
+            if entry.file_type().is_file() {
+                let path = entry.path().strip_prefix(dir)?;
+                let path = path
+                    .components()
+                    .map(|c| c.as_os_str().to_string_lossy())
+                    .collect::<Vec<_>>()
+                    .join("/");
+                server.add_file(&path, fs::read(entry.path()).await?);
+            }
+        }
+        Ok(server)
+    }
+
+    pub fn add_file(&self, path: &str, data: Vec<u8>) {
+        let mut state = self.state.lock().unwrap();
+        state.files.insert(path.to_string(), data);
+    }
+
+    pub fn set_behavior(&self, path: &str, behavior: Behavior) {
+        let mut state = self.state.lock().unwrap();
+        state.behaviors.insert(path.to_string(), behavior);
+    }
+
+    /// Number of requests made for `path`
+    pub fn request_count(&self, path: &str) -> usize {
+        let state = self.state.lock().unwrap();
+        state.requests.get(path).copied().unwrap_or(0)
+    }
+
+    /// Number of body bytes sent for `path`
+    pub fn bytes_sent(&self, path: &str) -> usize {
+        let state = self.state.lock().unwrap();
+        state.bytes_sent.get(path).copied().unwrap_or(0)
+    }
+
+    /// `User-Agent` header of the last request made for `path`
+    pub fn user_agent(&self, path: &str) -> Option<String> {
+        let state = self.state.lock().unwrap();
+        state.user_agents.get(path).cloned()
+    }
+
+    pub fn base_url(&self) -> &Url {
+        &self.base_url
+    }
+
+    pub fn url(&self, path: &str) -> Url {
+        self.base_url
+            .join(path)
+            .expect("Test paths should be valid")
+    }
+}
+
+async fn handle_request(stream: TcpStream, state: &Mutex<ServerState>) -> std::io::Result<()> {
+    let (reader, mut writer) = stream.into_split();
+    let mut reader = BufReader::new(reader);
+
+    let mut request_line = String::new();
+    if reader.read_line(&mut request_line).await? == 0 {
+        return Ok(());
+    }
+    let mut parts = request_line.split_whitespace();
+    let method = parts.next().unwrap_or_default().to_string();
+    let path = parts
+        .next()
+        .unwrap_or_default()
+        .trim_start_matches('/')
+        .to_string();
+
+    let mut range = None;
+    let mut user_agent = None;
+    let mut if_none_match = None;
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line).await? == 0 {
+            return Ok(());
+        }
+
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+
+        if let Some((name, value)) = line.split_once(':') {
+            if name.eq_ignore_ascii_case("range") {
+                range = Some(value.trim().to_string());
+            } else if name.eq_ignore_ascii_case("user-agent") {
+                user_agent = Some(value.trim().to_string());
+            } else if name.eq_ignore_ascii_case("if-none-match") {
+                if_none_match = Some(value.trim().to_string());
+            }
+        }
+    }
+
+    let (data, behavior, request_index) = {
+        let mut state = state.lock().unwrap();
+        let requests = state.requests.entry(path.clone()).or_default();
+        let request_index = *requests;
+        *requests += 1;
+        if let Some(user_agent) = user_agent {
+            state.user_agents.insert(path.clone(), user_agent);
+        }
+        (
+            state.files.get(&path).cloned(),
+            state.behaviors.get(&path).cloned().unwrap_or_default(),
+            request_index,
+        )
+    };
+
+    let forced_status = behavior
+        .status
+        .filter(|_| !matches!(behavior.status_times, Some(n) if request_index >= n));
+    let data = match (forced_status, data) {
+        (Some(status), _) => return write_status(&mut writer, status).await,
+        (None, Some(data)) => data,
+        (None, None) => return write_status(&mut writer, 404).await,
+    };
+    if behavior.etag.is_some() && behavior.etag == if_none_match {
+        return write_status(&mut writer, 304).await;
+    }
+
+    let range = range
+        .filter(|_| !behavior.ignore_range)
+        .and_then(|range| parse_range(&range, data.len()));
+    let (status, body) = match range {
+        Some((start, end)) => ("206 Partial Content", &data[start..=end]),
+        None => ("200 OK", &data[..]),
+    };
+
+    let mut header = format!(
+        "HTTP/1.1 {}\r\nContent-Length: {}\r\nConnection: close\r\n",
+        status,
+        body.len()
+    );
+    if let Some((start, end)) = range {
+        header += &format!("Content-Range: bytes {}-{}/{}\r\n", start, end, data.len());
+    }
+    if let Some(encoding) = &behavior.content_encoding {
+        header += &format!("Content-Encoding: {}\r\n", encoding);
+    }
+    if let Some(etag) = &behavior.etag {
+        header += &format!("ETag: {}\r\n", etag);
+    }
+    header += "\r\n";
+    writer.write_all(header.as_bytes()).await?;
+
+    if method == "HEAD" {
+        return writer.shutdown().await;
+    }
+
+    let body = match behavior.truncate {
+        Some(truncate) => &body[..truncate.min(body.len())],
+        None => body,
+    };
+    let piece_size = match behavior.bytes_per_sec {
+        Some(bytes_per_sec) => (bytes_per_sec / 10).max(1),
+        None => body.len().max(1),
+    };
+    for piece in body.chunks(piece_size) {
+        writer.write_all(piece).await?;
+        *state
+            .lock()
+            .unwrap()
+            .bytes_sent
+            .entry(path.clone())
+            .or_default() += piece.len();
+        if behavior.bytes_per_sec.is_some() {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+    }
+    writer.shutdown().await
+}
+
+async fn write_status(
+    writer: &mut tokio::net::tcp::OwnedWriteHalf,
+    status: u16,
+) -> std::io::Result<()> {
+    let header = format!(
+        "HTTP/1.1 {} Test\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+        status
+    );
+    writer.write_all(header.as_bytes()).await?;
+    writer.shutdown().await
+}
+
+fn parse_range(value: &str, len: usize) -> Option<(usize, usize)> {
+    let (start, end) = value.strip_prefix("bytes=")?.split_once('-')?;
+    let start = start.parse().ok()?;
+    let end = if end.is_empty() {
+        len - 1
+    } else {
+        end.parse::<usize>().ok()?.min(len - 1)
+    };
+    Some((start, end))
+}
@@ -0,0 +1,99 @@
+mod common;
+
+use std::ffi::OsString;
+
+use clap::{Arg, ArgMatches, Command};
+
+use common::test_dir;
+use rose_update::ConfigFile;
+
+/// A command with the kinds of options the updater has
+fn command() -> Command<'static> {
+    Command::new("rose-updater")
+        .arg(
+            Arg::new("url")
+                .long("url")
+                .takes_value(true)
+                .default_value("https://updates.roseonlinegame.com"),
+        )
+        .arg(Arg::new("exe").long("exe").takes_value(true))
+        .arg(Arg::new("retries").long("retries").takes_value(true))
+        .arg(
+            Arg::new("groups")
+                .long("groups")
+                .takes_value(true)
+                .multiple_values(true)
+                .value_delimiter(','),
+        )
+        .arg(Arg::new("headless").long("headless"))
+        .arg(Arg::new("skip_updater").long("skip-updater"))
+}
+
+/// Parse `cli` with the defaults of `file`
+fn matches(file: &str, cli: &[&str]) -> anyhow::Result<ArgMatches> {
+    let args = std::iter::once("rose-updater")
+        .chain(cli.iter().copied())
+        .map(OsString::from)
+        .collect();
+    let (command, args) = ConfigFile::parse(file)?.apply(command(), args)?;
+    Ok(command.try_get_matches_from(args)?)
+}
+
+#[test]
+fn file_values_replace_defaults() -> anyhow::Result<()> {
+    let matches = matches(
+        "url = \"https://example.com\"\nexe = \"game.exe\"\nretries = 2\n\
+         groups = [\"hd\", \"voices\"]\nheadless = true\nskip-updater = false\n",
+        &[],
+    )?;
+    assert_eq!(matches.value_of("url"), Some("https://example.com"));
+    assert_eq!(matches.value_of("exe"), Some("game.exe"));
+    assert_eq!(matches.value_of("retries"), Some("2"));
+    assert_eq!(
+        matches.values_of("groups").map(|groups| groups.collect()),
+        Some(vec!["hd", "voices"])
+    );
+    assert!(matches.is_present("headless"));
+    assert!(!matches.is_present("skip_updater"));
+    Ok(())
+}
+
+#[test]
+fn command_line_overrides_file() -> anyhow::Result<()> {
+    let matches = matches(
+        "url = \"https://example.com\"\nheadless = true\n",
+        &["--url", "https://other.example.com", "--headless"],
+    )?;
+    assert_eq!(matches.value_of("url"), Some("https://other.example.com"));
+    assert!(matches.is_present("headless"));
+    Ok(())
+}
+
+#[test]
+fn invalid_options_are_rejected() {
+    assert!(matches("unknown = 1", &[]).is_err());
+    assert!(matches("headless = \"yes\"", &[]).is_err());
+    assert!(matches("url = { host = \"example.com\" }", &[]).is_err());
+}
+
+#[test]
+fn later_files_replace_earlier_ones() -> anyhow::Result<()> {
+    let dir = test_dir("config-file");
+    std::fs::create_dir_all(&dir)?;
+    let first = dir.join("first.toml");
+    let second = dir.join("second.toml");
+    std::fs::write(
+        &first,
+        "url = \"https://first.example.com\"\nexe = \"game.exe\"\n",
+    )?;
+    std::fs::write(&second, "url = \"https://second.example.com\"\n")?;
+
+    let config = ConfigFile::load(&[first, dir.join("missing.toml"), second])?;
+    let (command, args) = config.apply(command(), vec![OsString::from("rose-updater")])?;
+    let matches = command.try_get_matches_from(args)?;
+    assert_eq!(matches.value_of("url"), Some("https://second.example.com"));
+    assert_eq!(matches.value_of("exe"), Some("game.exe"));
+
+    std::fs::remove_dir_all(&dir)?;
+    Ok(())
+}
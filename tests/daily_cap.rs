@@ -0,0 +1,39 @@
+mod common;
+
+use common::test_dir;
+use rose_update::DailyCap;
+
+#[tokio::test]
+async fn cap_is_reached_and_kept_across_runs() -> anyhow::Result<()> {
+    let dir = test_dir("daily-cap");
+    let _ = std::fs::remove_dir_all(&dir);
+    let path = dir.join("daily_usage.json");
+
+    let cap = DailyCap::load(&path, 100);
+    cap.add(60).await;
+    assert!(cap.check().is_ok());
+    cap.add(50).await;
+    assert!(cap.is_reached());
+    cap.save().await?;
+
+    assert!(DailyCap::load(&path, 100).is_reached());
+    assert!(!DailyCap::load(&path, 200).is_reached());
+
+    std::fs::remove_dir_all(&dir)?;
+    Ok(())
+}
+
+#[test]
+fn usage_of_another_day_is_reset() -> anyhow::Result<()> {
+    let dir = test_dir("daily-cap-reset");
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir)?;
+    let path = dir.join("daily_usage.json");
+    std::fs::write(&path, r#"{"date":"2000-01-01","bytes":1000}"#)?;
+
+    let cap = DailyCap::load(&path, 100);
+    assert!(cap.check().is_ok());
+
+    std::fs::remove_dir_all(&dir)?;
+    Ok(())
+}
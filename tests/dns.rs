@@ -0,0 +1,69 @@
+use std::net::{Ipv4Addr, SocketAddr};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use reqwest::dns::{Addrs, Name, Resolve, Resolving};
+
+use rose_update::{CachingResolver, FallbackResolver};
+
+/// Resolver standing in for a DNS over HTTPS service which can't be reached
+struct FailingResolver;
+
+impl Resolve for FailingResolver {
+    fn resolve(&self, _name: Name) -> Resolving {
+        Box::pin(async { Err("connection refused".into()) })
+    }
+}
+
+/// Resolver which resolves every name to localhost
+struct LocalhostResolver;
+
+impl Resolve for LocalhostResolver {
+    fn resolve(&self, _name: Name) -> Resolving {
+        Box::pin(async {
+            let addrs: Addrs =
+                Box::new(std::iter::once(SocketAddr::from((Ipv4Addr::LOCALHOST, 0))));
+            Ok(addrs)
+        })
+    }
+}
+
+/// Resolver which counts its lookups
+#[derive(Default)]
+struct CountingResolver {
+    lookups: Arc<AtomicUsize>,
+}
+
+impl Resolve for CountingResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        self.lookups.fetch_add(1, Ordering::SeqCst);
+        LocalhostResolver.resolve(name)
+    }
+}
+
+#[tokio::test]
+async fn failed_lookup_falls_back() {
+    let resolver = FallbackResolver::new(FailingResolver, LocalhostResolver);
+    let name: Name = "updates.roseonlinegame.com".parse().unwrap();
+    let addrs: Vec<SocketAddr> = resolver.resolve(name).await.unwrap().collect();
+    assert_eq!(addrs, vec![SocketAddr::from((Ipv4Addr::LOCALHOST, 0))]);
+}
+
+#[tokio::test]
+async fn repeated_lookups_are_cached() {
+    let inner = CountingResolver::default();
+    let lookups = inner.lookups.clone();
+    let resolver = CachingResolver::new(inner, Duration::from_secs(60));
+
+    for _ in 0..2 {
+        let name: Name = "updates2.roseonlinegame.com".parse().unwrap();
+        let addrs: Vec<SocketAddr> = resolver.resolve(name).await.unwrap().collect();
+        assert_eq!(addrs, vec![SocketAddr::from((Ipv4Addr::LOCALHOST, 0))]);
+    }
+    assert_eq!(lookups.load(Ordering::SeqCst), 1);
+
+    let name: Name = "updates.roseonlinegame.com".parse().unwrap();
+    resolver.resolve(name).await.unwrap();
+    assert_eq!(lookups.load(Ordering::SeqCst), 2);
+}
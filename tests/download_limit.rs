@@ -0,0 +1,30 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use rose_update::DownloadLimit;
+
+#[tokio::test]
+async fn concurrent_downloads_never_exceed_the_limit() {
+    let limit = DownloadLimit::new(3);
+    let running = Arc::new(AtomicUsize::new(0));
+    let peak = Arc::new(AtomicUsize::new(0));
+
+    let tasks: Vec<_> = (0..20)
+        .map(|_| {
+            let limit = limit.clone();
+            let running = running.clone();
+            let peak = peak.clone();
+            tokio::spawn(async move {
+                let _permit = limit.acquire().await;
+                let now_running = running.fetch_add(1, Ordering::SeqCst) + 1;
+                peak.fetch_max(now_running, Ordering::SeqCst);
+                tokio::time::sleep(Duration::from_millis(10)).await;
+                running.fetch_sub(1, Ordering::SeqCst);
+            })
+        })
+        .collect();
+    futures::future::join_all(tasks).await;
+
+    assert_eq!(peak.load(Ordering::SeqCst), 3);
+}
@@ -0,0 +1,28 @@
+mod common;
+
+use blake2::{Blake2b512, Digest};
+use tokio::fs;
+
+use common::{source_data, test_dir};
+use rose_update::verify_output_file;
+
+#[tokio::test]
+async fn verify_output_file_compares_whole_file_hash() -> anyhow::Result<()> {
+    let dir = test_dir("verify-output");
+    fs::create_dir_all(&dir).await?;
+    let path = dir.join("output");
+    let source = source_data(256 * 1024, 12);
+    fs::write(&path, &source).await?;
+
+    let expected_hash = Blake2b512::digest(&source).to_vec();
+    assert!(verify_output_file(&path, &expected_hash).await?);
+
+    // A single wrong byte is caught
+    let mut corrupt = source;
+    corrupt[1000] ^= 0xff;
+    fs::write(&path, &corrupt).await?;
+    assert!(!verify_output_file(&path, &expected_hash).await?);
+
+    fs::remove_dir_all(&dir).await?;
+    Ok(())
+}
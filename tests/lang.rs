@@ -0,0 +1,43 @@
+use rose_update::{RunSummary, Strings};
+
+#[test]
+fn strings_for_locale() {
+    assert_eq!(Strings::for_locale("de").lang(), "de");
+    assert_eq!(Strings::for_locale("de-DE").lang(), "de");
+    assert_eq!(Strings::for_locale("DE_at.UTF-8").lang(), "de");
+    assert_eq!(Strings::for_locale("en-US").lang(), "en");
+
+    // Languages the updater doesn't ship with use English
+    assert_eq!(Strings::for_locale("xx-YY").lang(), "en");
+    assert_eq!(Strings::for_locale("").lang(), "en");
+}
+
+#[test]
+fn strings_are_translated() {
+    let english = Strings::default();
+    let german = Strings::for_locale("de");
+    assert_eq!(english.get("cancel"), "Cancel");
+    assert_eq!(german.get("cancel"), "Abbrechen");
+    assert_eq!(german.format("starting_in", &[&5]), "Start in 5s");
+
+    // Unknown keys are shown as they are rather than as an empty label
+    assert_eq!(german.get("no_such_string"), "no_such_string");
+}
+
+#[test]
+fn run_summary_is_translated() {
+    let summary = RunSummary {
+        files_checked: 3,
+        files_updated: 1,
+        failed_files: vec!["a.txt".to_string()],
+        ..Default::default()
+    };
+    assert_eq!(
+        summary.describe(),
+        "Checked 3 files, updated 1 (0 B), 1 failed"
+    );
+    assert_eq!(
+        summary.describe_in(&Strings::for_locale("de")),
+        "3 Dateien geprüft, 1 aktualisiert (0 B), 1 fehlgeschlagen"
+    );
+}
@@ -0,0 +1,59 @@
+mod common;
+
+use common::test_dir;
+use rose_update::{log_files, prune_log_files, read_log_tail, LOG_FILE_PREFIX};
+
+#[test]
+fn old_log_files_are_pruned() -> anyhow::Result<()> {
+    let dir = test_dir("log-files");
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir)?;
+
+    for day in ["2024-05-03", "2024-04-30", "2024-05-01", "2024-05-02"] {
+        std::fs::write(dir.join(format!("{}.{}", LOG_FILE_PREFIX, day)), day)?;
+    }
+    std::fs::write(dir.join("settings.json"), "{}")?;
+
+    assert_eq!(prune_log_files(&dir, 2)?, 2);
+    let names: Vec<_> = log_files(&dir)?
+        .iter()
+        .map(|path| path.file_name().unwrap().to_string_lossy().into_owned())
+        .collect();
+    assert_eq!(
+        names,
+        [
+            format!("{}.2024-05-02", LOG_FILE_PREFIX),
+            format!("{}.2024-05-03", LOG_FILE_PREFIX),
+        ]
+    );
+
+    // Other files in the directory are left alone
+    assert!(dir.join("settings.json").exists());
+    assert_eq!(prune_log_files(&dir, 2)?, 0);
+
+    std::fs::remove_dir_all(&dir)?;
+    Ok(())
+}
+
+#[test]
+fn log_tail_is_read_from_the_newest_file() -> anyhow::Result<()> {
+    let dir = test_dir("log-tail");
+    let _ = std::fs::remove_dir_all(&dir);
+
+    // Nothing was logged yet
+    assert!(read_log_tail(&dir, 2)?.is_none());
+    std::fs::create_dir_all(&dir)?;
+    assert!(read_log_tail(&dir, 2)?.is_none());
+
+    let old_path = dir.join(format!("{}.2024-05-01", LOG_FILE_PREFIX));
+    let new_path = dir.join(format!("{}.2024-05-02", LOG_FILE_PREFIX));
+    std::fs::write(&old_path, "old\n")?;
+    std::fs::write(&new_path, "one\ntwo\nthree\n")?;
+
+    let (path, tail) = read_log_tail(&dir, 2)?.unwrap();
+    assert_eq!(path, new_path);
+    assert_eq!(tail, "two\nthree");
+
+    std::fs::remove_dir_all(&dir)?;
+    Ok(())
+}
@@ -0,0 +1,319 @@
+mod common;
+
+use std::io::Write;
+use std::time::{Duration, SystemTime};
+
+use serde_json::json;
+
+use common::{test_dir, Behavior, TestServer};
+use rose_update::{
+    download_remote_manifest, file_modified_at, migrate_local_manifest, DnsResolver, LaunchCommand,
+    LocalManifestFileEntry, RemoteManifest, RemoteManifestFileEntry, RemoteManifestSymlink,
+    UnsupportedManifestVersion, LOCAL_MANIFEST_VERSION,
+};
+
+fn remote_manifest() -> RemoteManifest {
+    RemoteManifest {
+        version: 1,
+        files: vec![RemoteManifestFileEntry {
+            path: "trose.exe.cba".to_string(),
+            source_path: "trose.exe".to_string(),
+            source_hash: vec![1, 2, 3],
+            source_size: 1024,
+            ..Default::default()
+        }],
+        ..Default::default()
+    }
+}
+
+#[tokio::test]
+async fn download_gzip_manifest() -> anyhow::Result<()> {
+    let json = serde_json::to_vec(&remote_manifest())?;
+    let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+    encoder.write_all(&json)?;
+
+    let server = TestServer::start().await?;
+    server.add_file("manifest.json", encoder.finish()?);
+    server.set_behavior(
+        "manifest.json",
+        Behavior {
+            content_encoding: Some("gzip".to_string()),
+            ..Default::default()
+        },
+    );
+
+    let manifest = download_remote_manifest(
+        &reqwest::Client::new(),
+        server.url("manifest.json"),
+        false,
+        None,
+    )
+    .await?;
+    assert_eq!(manifest.files.len(), 1);
+    assert_eq!(manifest.files[0].source_path, "trose.exe");
+    assert_eq!(manifest.files[0].source_hash, vec![1, 2, 3]);
+    Ok(())
+}
+
+#[tokio::test]
+async fn download_plain_manifest() -> anyhow::Result<()> {
+    let server = TestServer::start().await?;
+    server.add_file("manifest.json", serde_json::to_vec(&remote_manifest())?);
+
+    let manifest = download_remote_manifest(
+        &reqwest::Client::new(),
+        server.url("manifest.json"),
+        false,
+        None,
+    )
+    .await?;
+    assert_eq!(manifest.files.len(), 1);
+    assert_eq!(manifest.files[0].source_size, 1024);
+    Ok(())
+}
+
+#[tokio::test]
+async fn manifest_request_has_user_agent() -> anyhow::Result<()> {
+    let server = TestServer::start().await?;
+    server.add_file("manifest.json", serde_json::to_vec(&remote_manifest())?);
+
+    let client = DnsResolver::System
+        .client_builder(Duration::from_secs(60))
+        .build()?;
+    download_remote_manifest(&client, server.url("manifest.json"), false, None).await?;
+    assert_eq!(
+        server.user_agent("manifest.json"),
+        Some(format!("rose-updater/{}", env!("CARGO_PKG_VERSION")))
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn unchanged_manifest_is_read_from_cache() -> anyhow::Result<()> {
+    let dir = test_dir("manifest-etag");
+    let cache_path = dir.join("remote_manifest.json");
+    let server = TestServer::start().await?;
+    server.add_file("manifest.json", serde_json::to_vec(&remote_manifest())?);
+    server.set_behavior(
+        "manifest.json",
+        Behavior {
+            etag: Some("\"v1\"".to_string()),
+            ..Default::default()
+        },
+    );
+
+    let client = reqwest::Client::new();
+    let url = server.url("manifest.json");
+    download_remote_manifest(&client, url.clone(), false, Some(&cache_path)).await?;
+    assert!(cache_path.exists());
+    let sent = server.bytes_sent("manifest.json");
+
+    // The server only answers 304 Not Modified for the same ETag, so a file
+    // which changed without a new ETag shows whether the cache was used
+    let mut changed = remote_manifest();
+    changed.files[0].source_size = 2048;
+    server.add_file("manifest.json", serde_json::to_vec(&changed)?);
+
+    let manifest = download_remote_manifest(&client, url.clone(), false, Some(&cache_path)).await?;
+    assert_eq!(server.bytes_sent("manifest.json"), sent);
+    assert_eq!(manifest.files[0].source_size, 1024);
+
+    // Repairing bypasses the cache
+    let manifest = download_remote_manifest(&client, url, true, Some(&cache_path)).await?;
+    assert_eq!(manifest.files[0].source_size, 2048);
+
+    std::fs::remove_dir_all(&dir)?;
+    Ok(())
+}
+
+#[test]
+fn migrate_v0_manifest_keeps_files() {
+    let value = json!({
+        "updater": { "path": "rose-updater.exe", "hash": [1, 2, 3] },
+        "files": [
+            { "path": "trose.exe", "hash": [4, 5, 6] },
+            { "path": "3DDATA/STB/LIST_ZONE.STB", "hash": [7, 8, 9] },
+        ],
+    });
+
+    let manifest = migrate_local_manifest(value).unwrap();
+    assert_eq!(manifest.version, LOCAL_MANIFEST_VERSION);
+    assert_eq!(manifest.updater.path, "rose-updater.exe");
+    assert_eq!(manifest.updater.hash, vec![1, 2, 3]);
+
+    let files: Vec<_> = manifest
+        .files
+        .iter()
+        .map(|entry| (entry.path.as_str(), entry.hash.clone(), entry.size))
+        .collect();
+    assert_eq!(
+        files,
+        vec![
+            ("trose.exe", vec![4, 5, 6], 0),
+            ("3DDATA/STB/LIST_ZONE.STB", vec![7, 8, 9], 0),
+        ]
+    );
+}
+
+#[test]
+fn migrate_v0_manifest_without_updater() {
+    let value = json!({
+        "files": [{ "path": "trose.exe", "hash": [4, 5, 6] }],
+    });
+
+    let manifest = migrate_local_manifest(value).unwrap();
+    assert!(manifest.updater.path.is_empty());
+    assert_eq!(manifest.files.len(), 1);
+}
+
+#[test]
+fn migrate_current_manifest_is_unchanged() {
+    let value = json!({
+        "version": LOCAL_MANIFEST_VERSION,
+        "updater": { "path": "rose-updater.exe", "hash": [1], "size": 10 },
+        "files": [{ "path": "trose.exe", "hash": [2], "size": 20, "mode": 0o755 }],
+    });
+
+    let manifest = migrate_local_manifest(value).unwrap();
+    assert_eq!(manifest.updater.size, 10);
+    assert_eq!(manifest.files[0].size, 20);
+    assert_eq!(manifest.files[0].mode, Some(0o755));
+}
+
+#[test]
+fn migrate_newer_manifest_fails() {
+    let value = json!({
+        "version": LOCAL_MANIFEST_VERSION + 1,
+        "updater": { "path": "rose-updater.exe", "hash": [1], "size": 10 },
+        "files": [],
+    });
+
+    let e = migrate_local_manifest(value).unwrap_err();
+    assert!(e.is::<UnsupportedManifestVersion>());
+}
+
+#[test]
+fn touched_file_is_modified() -> anyhow::Result<()> {
+    let dir = test_dir("touched-file");
+    std::fs::create_dir_all(&dir)?;
+    let path = dir.join("trose.exe");
+    std::fs::write(&path, "trose")?;
+
+    let entry = LocalManifestFileEntry {
+        path: "trose.exe".to_string(),
+        modified_at: file_modified_at(&path),
+        ..Default::default()
+    };
+    assert!(entry.modified_at.is_some());
+    assert!(!entry.is_modified(&path));
+
+    let file = std::fs::File::options().write(true).open(&path)?;
+    file.set_modified(SystemTime::now() + Duration::from_secs(60))?;
+    drop(file);
+    assert!(entry.is_modified(&path));
+
+    // Entries recorded before modification times were stored are trusted
+    let entry = LocalManifestFileEntry {
+        modified_at: None,
+        ..entry
+    };
+    assert!(!entry.is_modified(&path));
+
+    std::fs::remove_dir_all(&dir)?;
+    Ok(())
+}
+
+#[test]
+fn only_selected_groups_are_installed() {
+    let entry = |source_path: &str, group: Option<&str>| RemoteManifestFileEntry {
+        source_path: source_path.to_string(),
+        group: group.map(str::to_string),
+        ..Default::default()
+    };
+    let manifest = RemoteManifest {
+        files: vec![
+            entry("trose.exe", None),
+            entry("3DDATA/HD/AVATAR.DDS", Some("hd-textures")),
+            entry("SOUND/DE/VOICE.OGG", Some("voices-de")),
+        ],
+        ..Default::default()
+    };
+
+    assert_eq!(
+        manifest.groups().into_iter().collect::<Vec<_>>(),
+        vec!["hd-textures", "voices-de"]
+    );
+
+    let groups = vec!["voices-de".to_string()];
+    let selected: Vec<&str> = manifest
+        .files
+        .iter()
+        .filter(|entry| entry.in_groups(&groups))
+        .map(|entry| entry.source_path.as_str())
+        .collect();
+    assert_eq!(selected, vec!["trose.exe", "SOUND/DE/VOICE.OGG"]);
+}
+
+#[test]
+fn files_hash_covers_every_field() {
+    let manifest = remote_manifest();
+    let hash = manifest.compute_files_hash();
+
+    let changes: Vec<fn(&mut RemoteManifestFileEntry)> = vec![
+        |entry| entry.mode = Some(0o755),
+        |entry| entry.group = Some("hd-textures".to_string()),
+        |entry| entry.group = Some(String::new()),
+        |entry| entry.fallback_paths = vec!["mirror/".to_string()],
+    ];
+    for change in changes {
+        let mut changed = manifest.clone();
+        change(&mut changed.files[0]);
+        assert_ne!(changed.compute_files_hash(), hash);
+    }
+
+    let manifest_changes: Vec<fn(&mut RemoteManifest)> = vec![
+        |manifest| manifest.version = 2,
+        |manifest| manifest.release = 1,
+        |manifest| manifest.hash_algorithm = "sha256".to_string(),
+        |manifest| manifest.total_source_size = 1024,
+        |manifest| manifest.compression = Some("zstd".to_string()),
+        |manifest| manifest.compression_level = Some(4),
+        |manifest| manifest.arch = Some("x86_64".to_string()),
+        |manifest| {
+            manifest.launch = Some(LaunchCommand {
+                exe: "trose.exe".to_string(),
+                args: vec!["--init".to_string()],
+            })
+        },
+        |manifest| manifest.launch = Some(LaunchCommand::default()),
+        |manifest| {
+            manifest.symlinks = vec![RemoteManifestSymlink {
+                path: "LIB".to_string(),
+                target: "lib".to_string(),
+            }]
+        },
+    ];
+    for change in manifest_changes {
+        let mut changed = manifest.clone();
+        change(&mut changed);
+        assert_ne!(changed.compute_files_hash(), hash);
+    }
+
+    // The hash itself isn't covered so it can be stored in the manifest
+    let mut hashed = manifest.clone();
+    hashed.files_hash = Some(hash.clone());
+    assert_eq!(hashed.compute_files_hash(), hash);
+
+    // Moving an item between the lists of two entries changes the hash
+    let mut first = remote_manifest();
+    first.files.push(RemoteManifestFileEntry {
+        source_path: "zz".to_string(),
+        ..Default::default()
+    });
+    let mut second = first.clone();
+    first.files[0].fallback_paths = vec!["a".to_string(), "b".to_string()];
+    first.files[1].fallback_paths = vec![];
+    second.files[0].fallback_paths = vec!["a".to_string()];
+    second.files[1].fallback_paths = vec!["b".to_string()];
+    assert_ne!(first.compute_files_hash(), second.compute_files_hash());
+}
@@ -0,0 +1,91 @@
+mod common;
+
+use std::time::Duration;
+
+use common::{test_dir, Behavior, TestServer};
+use rose_update::{add_toolbar, load_news, News, RetryPolicy};
+
+const NO_RETRIES: RetryPolicy = RetryPolicy {
+    retries: 0,
+    delay: Duration::ZERO,
+    jitter: Duration::ZERO,
+};
+
+const PAGE: &str = "<html><head><title>News</title></head><body><a href=\"patch.html\">Patch notes</a></body></html>";
+
+#[tokio::test]
+async fn news_is_cached_for_offline_launches() -> anyhow::Result<()> {
+    let dir = test_dir("news-cache");
+    let _ = std::fs::remove_dir_all(&dir);
+    let cache_path = dir.join("config").join("news.html");
+
+    let server = TestServer::start().await?;
+    server.add_file("launcher.html", PAGE.as_bytes().to_vec());
+    let url = server.url("launcher.html");
+    let client = reqwest::Client::new();
+
+    let news = load_news(&client, &url, Some(&cache_path), &NO_RETRIES).await;
+    assert_eq!(news, News::Live(PAGE.to_string()));
+    assert_eq!(std::fs::read_to_string(&cache_path)?, PAGE);
+
+    // The cached copy is shown while the news can't be downloaded
+    server.set_behavior(
+        "launcher.html",
+        Behavior {
+            status: Some(503),
+            ..Default::default()
+        },
+    );
+    let news = load_news(&client, &url, Some(&cache_path), &NO_RETRIES).await;
+    assert_eq!(news, News::Cached(PAGE.to_string()));
+
+    let html = news.to_html(&url);
+    assert!(html.contains(&format!("<head><base href=\"{}\">", url)));
+    assert!(html.contains("Showing cached news"));
+    assert!(html.contains("retry_news()"));
+    assert!(html.ends_with("<a href=\"patch.html\">Patch notes</a></body></html>"));
+
+    std::fs::remove_file(&cache_path)?;
+    let news = load_news(&client, &url, Some(&cache_path), &NO_RETRIES).await;
+    assert_eq!(news, News::Unavailable);
+    assert!(news.to_html(&url).contains("retry_news()"));
+
+    std::fs::remove_dir_all(&dir)?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn news_is_retried_after_a_failure() -> anyhow::Result<()> {
+    let server = TestServer::start().await?;
+    server.add_file("launcher.html", PAGE.as_bytes().to_vec());
+    server.set_behavior(
+        "launcher.html",
+        Behavior {
+            status: Some(503),
+            status_times: Some(1),
+            ..Default::default()
+        },
+    );
+    let url = server.url("launcher.html");
+    let retry = RetryPolicy {
+        retries: 1,
+        ..NO_RETRIES
+    };
+
+    let news = load_news(&reqwest::Client::new(), &url, None, &retry).await;
+    assert_eq!(news, News::Live(PAGE.to_string()));
+    assert_eq!(server.request_count("launcher.html"), 2);
+    Ok(())
+}
+
+#[test]
+fn toolbar_and_page_hash() {
+    let html = add_toolbar(PAGE, "<button>What's new</button>");
+    assert!(html.contains("<body><div"));
+    assert!(html.contains("<button>What's new</button></div><a href"));
+
+    let page = News::Live(PAGE.to_string());
+    assert_eq!(page.page_hash(), News::Cached(PAGE.to_string()).page_hash());
+    assert_ne!(page.page_hash(), News::Live(html).page_hash());
+    assert_eq!(News::Unavailable.page_hash(), None);
+}
@@ -0,0 +1,77 @@
+mod common;
+
+use tokio::fs;
+
+use common::{default_clone_options, source_data, test_dir, NullUpdater, TestServer};
+use rose_update::{
+    apply_patch, base_hash, clone_remote, create_patches, hash_file, ArchiveOptions, PatchOp,
+};
+
+#[tokio::test]
+async fn patch_and_old_file_rebuild_new_file() -> anyhow::Result<()> {
+    let dir = test_dir("patch");
+    let _ = fs::remove_dir_all(&dir).await;
+    let from = dir.join("from");
+    let to = dir.join("to");
+    let output = dir.join("output");
+    fs::create_dir_all(from.join("3DDATA")).await?;
+    fs::create_dir_all(to.join("3DDATA")).await?;
+
+    // Replace a stretch in the middle of the client with new data
+    let old = source_data(2 * 1024 * 1024, 60);
+    let mut new = old[..512 * 1024].to_vec();
+    new.extend(source_data(64 * 1024, 61));
+    new.extend(&old[768 * 1024..]);
+    fs::write(from.join("trose.exe"), &old).await?;
+    fs::write(to.join("trose.exe"), &new).await?;
+
+    let stb = source_data(64 * 1024, 62);
+    fs::write(from.join("3DDATA/LIST_ZONE.STB"), &stb).await?;
+    fs::write(to.join("3DDATA/LIST_ZONE.STB"), &stb).await?;
+    fs::write(to.join("3DDATA/LIST_NPC.STB"), source_data(1024, 63)).await?;
+
+    let manifest = create_patches(&from, &to, &output, &ArchiveOptions::default()).await?;
+
+    // Unchanged and new files have no patch
+    assert_eq!(manifest.files.len(), 1);
+    let entry = &manifest.files[0];
+    assert_eq!(entry.source_path, "trose.exe");
+    assert_eq!(entry.path, "data/trose.exe.cba");
+    assert_eq!(entry.base_hash, hash_file(&from.join("trose.exe")).await?);
+    assert!(entry.patch_size < new.len() as u64 / 4);
+    assert!(matches!(entry.ops[0], PatchOp::Copy { offset: 0, .. }));
+    assert!(entry
+        .ops
+        .iter()
+        .any(|op| matches!(op, PatchOp::Insert { .. })));
+
+    let stb_hash = hash_file(&from.join("3DDATA/LIST_ZONE.STB")).await?;
+    assert_eq!(
+        manifest.base_hash,
+        base_hash([
+            ("trose.exe", entry.base_hash.as_slice()),
+            ("3DDATA/LIST_ZONE.STB", stb_hash.as_slice()),
+        ])
+    );
+
+    let server = TestServer::serve_dir(&output).await?;
+    let inserts_path = dir.join("trose.exe.inserts");
+    clone_remote(
+        &server.url(&entry.path),
+        &inserts_path,
+        NullUpdater,
+        &default_clone_options(),
+    )
+    .await?;
+
+    let patched_path = dir.join("trose.exe");
+    apply_patch(&from.join("trose.exe"), &inserts_path, entry, &patched_path).await?;
+    assert!(fs::read(&patched_path).await? == new);
+
+    // The patch only applies to the file it was made from
+    let result = apply_patch(&patched_path, &inserts_path, entry, &dir.join("other.exe")).await;
+    assert!(result.is_err());
+
+    fs::remove_dir_all(&dir).await?;
+    Ok(())
+}
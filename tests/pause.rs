@@ -0,0 +1,40 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use rose_update::PauseFlag;
+
+#[tokio::test]
+async fn paused_flag_halts_and_resumes_consumers() {
+    let pause = PauseFlag::default();
+    let consumed = Arc::new(AtomicUsize::new(0));
+
+    // Stand in for a clone pulling chunks from its archive
+    let consumer = {
+        let pause = pause.clone();
+        let consumed = consumed.clone();
+        tokio::spawn(async move {
+            for _ in 0..1000 {
+                pause.wait().await;
+                consumed.fetch_add(1, Ordering::SeqCst);
+                tokio::time::sleep(Duration::from_millis(1)).await;
+            }
+        })
+    };
+
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    pause.pause();
+    tokio::time::sleep(Duration::from_millis(20)).await;
+
+    let paused_at = consumed.load(Ordering::SeqCst);
+    assert!(paused_at > 0);
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert_eq!(consumed.load(Ordering::SeqCst), paused_at);
+
+    pause.resume();
+    tokio::time::timeout(Duration::from_secs(10), consumer)
+        .await
+        .expect("The consumer should finish once resumed")
+        .unwrap();
+    assert_eq!(consumed.load(Ordering::SeqCst), 1000);
+}
@@ -0,0 +1,82 @@
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+
+use rose_update::{ProgressEvent, ProgressEventWriter, ProgressStage, Updater};
+
+/// Writer collecting everything written to it so the events can be read back
+#[derive(Clone, Default)]
+struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+impl Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[tokio::test]
+async fn events_are_json_lines() -> anyhow::Result<()> {
+    let buffer = SharedBuffer::default();
+    let writer = ProgressEventWriter::new(buffer.clone());
+
+    writer.set_text("Update is 1 KB".to_string());
+    writer.set_stage(ProgressStage::Downloading);
+    writer.set_max_progress(1024).await;
+    writer.increment_progress(256).await;
+    writer.set_stage(ProgressStage::Done);
+    writer.flush();
+
+    let output = String::from_utf8(buffer.0.lock().unwrap().clone())?;
+    let events = output
+        .lines()
+        .map(serde_json::from_str)
+        .collect::<Result<Vec<ProgressEvent>, _>>()?;
+
+    let event = |stage, current, total| ProgressEvent {
+        stage,
+        current,
+        total,
+        text: "Update is 1 KB".to_string(),
+    };
+    assert_eq!(
+        events,
+        vec![
+            event(ProgressStage::Manifest, 0, 0),
+            event(ProgressStage::Downloading, 0, 0),
+            event(ProgressStage::Downloading, 0, 1024),
+            event(ProgressStage::Downloading, 256, 1024),
+            event(ProgressStage::Done, 256, 1024),
+        ]
+    );
+
+    assert!(output.starts_with(r#"{"stage":"manifest","current":0,"total":0,"text":"#));
+    Ok(())
+}
+
+#[tokio::test]
+async fn progress_events_are_throttled() -> anyhow::Result<()> {
+    let buffer = SharedBuffer::default();
+    let writer = ProgressEventWriter::new(buffer.clone());
+
+    writer.set_stage(ProgressStage::Downloading);
+    writer.set_max_progress(1000).await;
+    for _ in 0..1000 {
+        writer.increment_progress(1).await;
+    }
+    writer.flush();
+
+    let output = String::from_utf8(buffer.0.lock().unwrap().clone())?;
+    let events = output
+        .lines()
+        .map(serde_json::from_str)
+        .collect::<Result<Vec<ProgressEvent>, _>>()?;
+
+    // The final progress is always written
+    assert!(events.len() < 100, "{} events were written", events.len());
+    assert_eq!(events.last().map(|event| event.current), Some(1000));
+    Ok(())
+}
@@ -0,0 +1,110 @@
+use std::sync::{Arc, Mutex};
+
+use rose_update::{ProgressObserver, ProgressStage, ProgressState, UpdateProgress, Updater};
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Callback {
+    Stage(ProgressStage),
+    Progress(u64, u64),
+    Status(String),
+    File(String),
+}
+
+/// Observer recording every callback in the order it was made
+#[derive(Clone, Default)]
+struct RecordingObserver(Arc<Mutex<Vec<Callback>>>);
+
+impl ProgressObserver for RecordingObserver {
+    fn on_stage(&self, stage: ProgressStage) {
+        self.0.lock().unwrap().push(Callback::Stage(stage));
+    }
+
+    fn on_progress(&self, current: u64, total: u64) {
+        self.0
+            .lock()
+            .unwrap()
+            .push(Callback::Progress(current, total));
+    }
+
+    fn on_status(&self, status: &str) {
+        self.0
+            .lock()
+            .unwrap()
+            .push(Callback::Status(status.to_string()));
+    }
+
+    fn on_file(&self, path: &str) {
+        self.0
+            .lock()
+            .unwrap()
+            .push(Callback::File(path.to_string()));
+    }
+}
+
+#[tokio::test]
+async fn observer_receives_callbacks_in_order() {
+    let observer = RecordingObserver::default();
+    let state = ProgressState::with_observer(observer.clone());
+
+    state.set_status("Update is 1 KB".to_string());
+    state.set_stage(ProgressStage::Downloading);
+    state.set_max_progress(1024).await;
+    state.increment_progress(256).await;
+    state.increment_progress(768).await;
+    state.set_stage(ProgressStage::Done);
+
+    assert_eq!(
+        *observer.0.lock().unwrap(),
+        vec![
+            Callback::Status("Update is 1 KB".to_string()),
+            Callback::Stage(ProgressStage::Downloading),
+            Callback::Progress(0, 1024),
+            Callback::Progress(256, 1024),
+            Callback::Progress(1024, 1024),
+            Callback::Stage(ProgressStage::Done),
+        ]
+    );
+    assert_eq!(state.stage(), ProgressStage::Done);
+    assert_eq!(state.current(), 1024);
+    assert_eq!(state.total(), 1024);
+}
+
+#[tokio::test]
+async fn state_without_observer_tracks_progress() {
+    let state = ProgressState::new();
+    state.set_max_progress(100).await;
+    state.increment_progress(40).await;
+    assert_eq!(state.current(), 40);
+
+    // Starting a new stage starts the progress over
+    state.set_max_progress(50).await;
+    assert_eq!(state.current(), 0);
+    assert_eq!(state.total(), 50);
+}
+
+#[tokio::test]
+async fn current_file_is_reported_once_per_change() {
+    let observer = RecordingObserver::default();
+    let state = ProgressState::with_observer(observer.clone());
+
+    state.set_stage(ProgressStage::Downloading);
+    state.set_current_file("3DDATA/STB/ITEM.STB");
+    state.set_current_file("3DDATA/STB/ITEM.STB");
+    assert_eq!(state.current_file(), "3DDATA/STB/ITEM.STB");
+    state.set_current_file("3DDATA/STB/LIST_NPC.STB");
+
+    // The file is cleared once the stage is over
+    state.set_stage(ProgressStage::Done);
+    assert_eq!(state.current_file(), "");
+
+    assert_eq!(
+        *observer.0.lock().unwrap(),
+        vec![
+            Callback::Stage(ProgressStage::Downloading),
+            Callback::File("3DDATA/STB/ITEM.STB".to_string()),
+            Callback::File("3DDATA/STB/LIST_NPC.STB".to_string()),
+            Callback::File(String::new()),
+            Callback::Stage(ProgressStage::Done),
+        ]
+    );
+}
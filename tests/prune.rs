@@ -0,0 +1,103 @@
+mod common;
+
+use std::fs;
+use std::path::PathBuf;
+
+use common::test_dir;
+use rose_update::{prune_local_files, RemoteManifest, RemoteManifestFileEntry};
+
+fn entry(source_path: &str) -> RemoteManifestFileEntry {
+    RemoteManifestFileEntry {
+        source_path: source_path.to_string(),
+        ..Default::default()
+    }
+}
+
+#[test]
+fn prune_removes_stray_files() -> anyhow::Result<()> {
+    let dir = test_dir("prune");
+    let _ = fs::remove_dir_all(&dir);
+    let output = dir.join("output");
+    fs::create_dir_all(output.join("3DDATA/STB"))?;
+    fs::create_dir_all(output.join("updater/localhost"))?;
+
+    for path in [
+        "rose-updater.exe",
+        "trose.exe",
+        "3DDATA/STB/LIST_ZONE.STB",
+        "3DDATA/STB/STRAY.STB",
+        "stray.txt",
+        "updater/localhost/local_manifest.json",
+    ] {
+        fs::write(output.join(path), path)?;
+    }
+    fs::write(dir.join("outside.txt"), "outside")?;
+
+    let manifest = RemoteManifest {
+        updater: entry("rose-updater.exe"),
+        files: vec![entry("trose.exe"), entry("3DDATA/STB/LIST_ZONE.STB")],
+        ..Default::default()
+    };
+
+    let mut pruned = prune_local_files(&output, &manifest, &[])?;
+    pruned.sort();
+    assert_eq!(
+        pruned,
+        vec![
+            output.join("3DDATA/STB/STRAY.STB"),
+            output.join("stray.txt")
+        ]
+    );
+
+    assert!(output.join("rose-updater.exe").exists());
+    assert!(output.join("trose.exe").exists());
+    assert!(output.join("3DDATA/STB/LIST_ZONE.STB").exists());
+    assert!(output
+        .join("updater/localhost/local_manifest.json")
+        .exists());
+    assert!(dir.join("outside.txt").exists());
+
+    fs::remove_dir_all(&dir)?;
+    Ok(())
+}
+
+#[test]
+fn prune_keeps_protected_and_in_progress_files() -> anyhow::Result<()> {
+    let dir = test_dir("prune_protected");
+    let _ = fs::remove_dir_all(&dir);
+    let output = dir.join("output");
+    fs::create_dir_all(output.join("beta/updater"))?;
+    fs::create_dir_all(output.join("3DDATA"))?;
+
+    for path in [
+        "rose-updater.exe",
+        "rose-updater.old",
+        "trose.exe.progress",
+        "trose.exe.archive",
+        "3DDATA/LIST.STB.tmp",
+        "stray.txt.progress",
+        "beta/trose.exe",
+        "beta/updater/local_manifest.json",
+    ] {
+        fs::write(output.join(path), path)?;
+    }
+
+    let manifest = RemoteManifest {
+        updater: entry("rose-updater.exe"),
+        files: vec![entry("trose.exe"), entry("3DDATA/LIST.STB")],
+        ..Default::default()
+    };
+
+    let pruned = prune_local_files(&output, &manifest, &[PathBuf::from("beta")])?;
+    assert_eq!(pruned, vec![output.join("stray.txt.progress")]);
+
+    assert!(output.join("rose-updater.old").exists());
+    assert!(output.join("trose.exe.progress").exists());
+    assert!(output.join("trose.exe.archive").exists());
+    assert!(output.join("3DDATA/LIST.STB.tmp").exists());
+    assert!(output.join("beta/trose.exe").exists());
+    assert!(output.join("beta/updater/local_manifest.json").exists());
+
+    fs::remove_dir_all(&dir)?;
+    Ok(())
+}
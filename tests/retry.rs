@@ -0,0 +1,64 @@
+mod common;
+
+use std::time::Duration;
+
+use common::{Behavior, TestServer};
+use rose_update::{download_remote_manifest, RemoteManifest, RetryPolicy};
+
+fn retry_policy(retries: u32) -> RetryPolicy {
+    RetryPolicy {
+        retries,
+        delay: Duration::from_millis(10),
+        jitter: Duration::ZERO,
+    }
+}
+
+async fn flaky_server() -> anyhow::Result<TestServer> {
+    let server = TestServer::start().await?;
+    server.add_file(
+        "manifest.json",
+        serde_json::to_vec(&RemoteManifest::default())?,
+    );
+    server.set_behavior(
+        "manifest.json",
+        Behavior {
+            status: Some(503),
+            status_times: Some(2),
+            ..Default::default()
+        },
+    );
+    Ok(server)
+}
+
+#[tokio::test]
+async fn request_succeeds_after_retries() -> anyhow::Result<()> {
+    let server = flaky_server().await?;
+    let url = server.url("manifest.json");
+
+    retry_policy(3)
+        .run(|| download_remote_manifest(&reqwest::Client::new(), url.clone(), false, None))
+        .await?;
+    assert_eq!(server.request_count("manifest.json"), 3);
+    Ok(())
+}
+
+#[tokio::test]
+async fn request_fails_when_retries_are_used_up() -> anyhow::Result<()> {
+    let server = flaky_server().await?;
+    let url = server.url("manifest.json");
+
+    let result = retry_policy(1)
+        .run(|| download_remote_manifest(&reqwest::Client::new(), url.clone(), false, None))
+        .await;
+    assert!(result.is_err());
+    assert_eq!(server.request_count("manifest.json"), 2);
+    Ok(())
+}
+
+#[test]
+fn retry_delay_backs_off_exponentially() {
+    let policy = retry_policy(10);
+    assert_eq!(policy.retry_delay(0), Duration::from_millis(10));
+    assert_eq!(policy.retry_delay(3), Duration::from_millis(80));
+    assert_eq!(policy.retry_delay(20), Duration::from_secs(30));
+}
@@ -0,0 +1,68 @@
+mod common;
+
+use std::fs;
+
+use common::test_dir;
+use rose_update::{old_updater_path, recover_interrupted_updater, start_new_updater};
+
+#[test]
+fn interrupted_update_restores_previous_updater() -> anyhow::Result<()> {
+    let dir = test_dir("recover-updater");
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir)?;
+
+    // The updater was moved out of the way but the new one was never written
+    let updater_path = dir.join("rose-updater.exe");
+    fs::write(old_updater_path(&updater_path), "previous updater")?;
+
+    assert!(recover_interrupted_updater(&updater_path)?);
+    assert_eq!(fs::read_to_string(&updater_path)?, "previous updater");
+    assert!(!old_updater_path(&updater_path).exists());
+
+    // Nothing left to recover
+    assert!(!recover_interrupted_updater(&updater_path)?);
+
+    fs::remove_dir_all(&dir)?;
+    Ok(())
+}
+
+#[test]
+fn completed_update_keeps_new_updater() -> anyhow::Result<()> {
+    let dir = test_dir("keep-updater");
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir)?;
+
+    let updater_path = dir.join("rose-updater.exe");
+    fs::write(&updater_path, "new updater")?;
+    fs::write(old_updater_path(&updater_path), "previous updater")?;
+
+    assert!(!recover_interrupted_updater(&updater_path)?);
+    assert_eq!(fs::read_to_string(&updater_path)?, "new updater");
+
+    fs::remove_dir_all(&dir)?;
+    Ok(())
+}
+
+#[test]
+fn failed_start_rolls_back_updater() -> anyhow::Result<()> {
+    let dir = test_dir("rollback-updater");
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir)?;
+
+    let updater_path = dir.join("rose-updater.exe");
+    fs::write(&updater_path, "corrupt updater")?;
+    fs::write(old_updater_path(&updater_path), "previous updater")?;
+
+    let result = start_new_updater(&updater_path, |_| {
+        Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            "bad executable",
+        ))
+    });
+    assert!(result.is_err());
+    assert_eq!(fs::read_to_string(&updater_path)?, "previous updater");
+    assert!(!old_updater_path(&updater_path).exists());
+
+    fs::remove_dir_all(&dir)?;
+    Ok(())
+}
@@ -0,0 +1,42 @@
+mod common;
+
+use common::test_dir;
+use rose_update::style::{ProgressBarColors, Theme};
+use rose_update::Settings;
+
+#[test]
+fn settings_round_trip() -> anyhow::Result<()> {
+    let dir = test_dir("settings");
+    let _ = std::fs::remove_dir_all(&dir);
+    let path = dir.join("config").join("settings.json");
+
+    // Nothing saved yet
+    assert_eq!(Settings::load(&path), Settings::default());
+
+    let settings = Settings {
+        beta: true,
+        theme: Theme::Light,
+        progress_bar: ProgressBarColors {
+            percentage: Some("#ff8000".to_string()),
+            ..Default::default()
+        },
+        seen_changelog: Some(vec![1, 2, 3]),
+    };
+    settings.save(&path)?;
+    assert_eq!(Settings::load(&path), settings);
+
+    // Settings saved by older updaters without the fields use the defaults
+    std::fs::write(&path, "{\"beta\":true}")?;
+    assert_eq!(
+        Settings::load(&path),
+        Settings {
+            beta: true,
+            theme: Theme::Dark,
+            progress_bar: ProgressBarColors::default(),
+            seen_changelog: None,
+        }
+    );
+
+    std::fs::remove_dir_all(&dir)?;
+    Ok(())
+}
@@ -0,0 +1,48 @@
+use std::time::{Duration, Instant};
+
+use rose_update::SpeedEstimate;
+
+const MB: usize = 1024 * 1024;
+
+fn assert_close(actual: f64, expected: f64) {
+    assert!(
+        (actual - expected).abs() <= expected * 0.1,
+        "Expected about {} but got {}",
+        expected,
+        actual
+    );
+}
+
+#[test]
+fn speed_follows_recent_progress() {
+    let start = Instant::now();
+    let mut speed = SpeedEstimate::new();
+
+    // 10 MB/s for 3 seconds
+    for i in 0..=30 {
+        speed.record_at(start + Duration::from_millis(i * 100), MB);
+    }
+    let now = start + Duration::from_secs(3);
+    assert_close(speed.speed_at(now), 10.0 * MB as f64);
+
+    // Then throttled to 1 MB/s for 6 seconds
+    for i in 31..=90 {
+        speed.record_at(start + Duration::from_millis(i * 100), MB / 10);
+    }
+    let now = start + Duration::from_secs(9);
+    assert_close(speed.speed_at(now), MB as f64);
+
+    let eta = speed.eta_at(now, 10 * MB).unwrap();
+    assert_close(eta.as_secs_f64(), 10.0);
+}
+
+#[test]
+fn no_eta_without_progress() {
+    let start = Instant::now();
+    let mut speed = SpeedEstimate::new();
+    assert_eq!(speed.eta_at(start, MB), None);
+
+    // A stalled download has no speed once the window has passed
+    speed.record_at(start, MB);
+    assert_eq!(speed.eta_at(start + Duration::from_secs(10), MB), None);
+}
@@ -0,0 +1,26 @@
+use fltk::enums::Color;
+use rose_update::style::{ProgressBarColors, Theme, PROGRESS_PERCENTAGE_COLOR};
+
+#[test]
+fn progress_bar_colors_override_the_theme() {
+    let colors = ProgressBarColors::default();
+    assert_eq!(colors.percentage(), PROGRESS_PERCENTAGE_COLOR);
+    assert_eq!(colors.text(Theme::Light), Theme::Light.text_color());
+    assert_eq!(
+        colors.background(Theme::Dark),
+        Theme::Dark.background_color()
+    );
+
+    let colors = ProgressBarColors {
+        percentage: Some("#ff8000".to_string()),
+        text: Some("#FF8000".to_string()),
+        // Invalid colors use the theme's
+        background: Some("orange".to_string()),
+    };
+    assert_eq!(colors.percentage(), Color::from_rgb(255, 128, 0));
+    assert_eq!(colors.text(Theme::Dark), Color::from_rgb(255, 128, 0));
+    assert_eq!(
+        colors.background(Theme::Dark),
+        Theme::Dark.background_color()
+    );
+}
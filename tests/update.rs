@@ -0,0 +1,452 @@
+mod common;
+
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use async_trait::async_trait;
+use tokio::fs;
+
+use common::{create_archive, default_clone_options, source_data, test_dir, TestServer};
+use rose_update::{
+    hash_file, run_update, FileState, ProgressStage, RemoteManifest, RemoteManifestFileEntry,
+    UpdateConfig, UpdateOutcome, UpdateProgress, Updater, HASH_ALGORITHM,
+};
+
+/// Records the stages an update went through
+#[derive(Clone, Default)]
+struct StageRecorder {
+    stages: Arc<Mutex<Vec<ProgressStage>>>,
+}
+
+#[async_trait]
+impl Updater for StageRecorder {
+    async fn set_max_progress(&self, _total: usize) {}
+    async fn increment_progress(&self, _amount: usize) {}
+}
+
+impl UpdateProgress for StageRecorder {
+    fn set_status(&self, _status: String) {}
+
+    fn set_stage(&self, stage: ProgressStage) {
+        self.stages.lock().unwrap().push(stage);
+    }
+}
+
+/// Archive `source` as `source_path` on `server` and return its manifest entry
+async fn add_archive(
+    server: &TestServer,
+    dir: &Path,
+    source_path: &str,
+    source: &[u8],
+) -> anyhow::Result<RemoteManifestFileEntry> {
+    let archive_path = format!("{}.cba", source_path);
+    server.add_file(&archive_path, create_archive(source, dir).await?);
+    Ok(RemoteManifestFileEntry {
+        path: archive_path,
+        source_path: source_path.to_string(),
+        source_hash: hash_file(&dir.join("source")).await?,
+        source_size: source.len(),
+        ..Default::default()
+    })
+}
+
+#[tokio::test]
+async fn update_downloads_files_without_ui() -> anyhow::Result<()> {
+    let dir = test_dir("run-update");
+    let _ = fs::remove_dir_all(&dir).await;
+    let archive_dir = dir.join("archive");
+    let output = dir.join("output");
+    fs::create_dir_all(&archive_dir).await?;
+
+    let exe = source_data(1024 * 1024, 20);
+    let stb = source_data(64 * 1024, 21);
+
+    let server = TestServer::start().await?;
+    let manifest = RemoteManifest {
+        version: 1,
+        files: vec![
+            add_archive(&server, &archive_dir, "trose.exe", &exe).await?,
+            add_archive(&server, &archive_dir, "3DDATA/STB/LIST_ZONE.STB", &stb).await?,
+        ],
+        hash_algorithm: HASH_ALGORITHM.to_string(),
+        ..Default::default()
+    };
+    server.add_file("manifest.json", serde_json::to_vec(&manifest)?);
+
+    let config = UpdateConfig {
+        skip_updater: true,
+        ..UpdateConfig::new(
+            server.base_url().clone(),
+            server.url("manifest.json"),
+            output.clone(),
+            default_clone_options(),
+        )
+    };
+    let progress = StageRecorder::default();
+    let (_shutdown_tx, shutdown_rx) = tokio::sync::watch::channel(false);
+
+    let outcome = run_update(&config, progress.clone(), shutdown_rx.clone()).await?;
+    let summary = match outcome {
+        UpdateOutcome::ApplicationUpdated(_, summary) => summary,
+        outcome => panic!("Unexpected outcome {:?}", outcome),
+    };
+    assert_eq!(summary.files_updated, 2);
+    assert!(summary.failed_files.is_empty());
+    assert!(fs::read(output.join("trose.exe")).await? == exe);
+    assert!(fs::read(output.join("3DDATA/STB/LIST_ZONE.STB")).await? == stb);
+    assert_eq!(
+        progress.stages.lock().unwrap().last(),
+        Some(&ProgressStage::Done)
+    );
+
+    // Everything is up to date so nothing is downloaded again
+    let requests = server.request_count("trose.exe.cba");
+    let outcome = run_update(&config, progress, shutdown_rx).await?;
+    let summary = match outcome {
+        UpdateOutcome::ApplicationUpdated(_, summary) => summary,
+        outcome => panic!("Unexpected outcome {:?}", outcome),
+    };
+    assert_eq!(summary.files_to_update, 0);
+    assert_eq!(server.request_count("trose.exe.cba"), requests);
+
+    fs::remove_dir_all(&dir).await?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn verify_only_reports_without_changing_files() -> anyhow::Result<()> {
+    let dir = test_dir("verify-only");
+    let _ = fs::remove_dir_all(&dir).await;
+    let archive_dir = dir.join("archive");
+    let output = dir.join("output");
+    fs::create_dir_all(&archive_dir).await?;
+    fs::create_dir_all(&output).await?;
+
+    let exe = source_data(64 * 1024, 22);
+    let stb = source_data(16 * 1024, 23);
+    let wav = source_data(8 * 1024, 24);
+
+    let server = TestServer::start().await?;
+    let manifest = RemoteManifest {
+        version: 1,
+        files: vec![
+            add_archive(&server, &archive_dir, "trose.exe", &exe).await?,
+            add_archive(&server, &archive_dir, "LIST_ZONE.STB", &stb).await?,
+            add_archive(&server, &archive_dir, "CLICK.WAV", &wav).await?,
+        ],
+        hash_algorithm: HASH_ALGORITHM.to_string(),
+        ..Default::default()
+    };
+    server.add_file("manifest.json", serde_json::to_vec(&manifest)?);
+
+    fs::write(output.join("trose.exe"), &exe).await?;
+    fs::write(output.join("LIST_ZONE.STB"), b"corrupt").await?;
+
+    let config = UpdateConfig {
+        skip_updater: true,
+        verify_only: true,
+        ..UpdateConfig::new(
+            server.base_url().clone(),
+            server.url("manifest.json"),
+            output.clone(),
+            default_clone_options(),
+        )
+    };
+    let (_shutdown_tx, shutdown_rx) = tokio::sync::watch::channel(false);
+
+    let report = match run_update(&config, StageRecorder::default(), shutdown_rx).await? {
+        UpdateOutcome::Verified(report) => report,
+        outcome => panic!("Unexpected outcome {:?}", outcome),
+    };
+    assert_eq!(
+        report.files,
+        vec![
+            ("trose.exe".to_string(), FileState::Ok),
+            ("LIST_ZONE.STB".to_string(), FileState::Corrupt),
+            ("CLICK.WAV".to_string(), FileState::Missing),
+        ]
+    );
+    assert!(!report.is_ok());
+
+    // Nothing was downloaded or written
+    assert_eq!(server.request_count("LIST_ZONE.STB.cba"), 0);
+    assert_eq!(fs::read(output.join("LIST_ZONE.STB")).await?, b"corrupt");
+    assert!(!output.join("CLICK.WAV").exists());
+    assert!(!output.join("updater").exists());
+
+    fs::remove_dir_all(&dir).await?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn older_release_is_refused_unless_allowed() -> anyhow::Result<()> {
+    let dir = test_dir("downgrade");
+    let _ = fs::remove_dir_all(&dir).await;
+    let archive_dir = dir.join("archive");
+    let output = dir.join("output");
+    fs::create_dir_all(&archive_dir).await?;
+
+    let old_exe = source_data(64 * 1024, 25);
+    let new_exe = source_data(64 * 1024, 26);
+
+    let server = TestServer::start().await?;
+    let mut config = UpdateConfig {
+        skip_updater: true,
+        ..UpdateConfig::new(
+            server.base_url().clone(),
+            server.url("manifest.json"),
+            output.clone(),
+            default_clone_options(),
+        )
+    };
+    let (_shutdown_tx, shutdown_rx) = tokio::sync::watch::channel(false);
+
+    let serve_release = |release: u64, entry: RemoteManifestFileEntry| {
+        let manifest = RemoteManifest {
+            version: 1,
+            files: vec![entry],
+            hash_algorithm: HASH_ALGORITHM.to_string(),
+            release,
+            ..Default::default()
+        };
+        server.add_file("manifest.json", serde_json::to_vec(&manifest).unwrap());
+    };
+
+    serve_release(
+        2,
+        add_archive(&server, &archive_dir, "trose.exe", &new_exe).await?,
+    );
+    run_update(&config, StageRecorder::default(), shutdown_rx.clone()).await?;
+    assert!(fs::read(output.join("trose.exe")).await? == new_exe);
+
+    // A stale server serving the previous release doesn't change anything
+    serve_release(
+        1,
+        add_archive(&server, &archive_dir, "trose.exe", &old_exe).await?,
+    );
+    let result = run_update(&config, StageRecorder::default(), shutdown_rx.clone()).await;
+    let error = result.expect_err("The update to an older release should be refused");
+    assert!(error.to_string().contains("release 1"), "{}", error);
+    assert!(fs::read(output.join("trose.exe")).await? == new_exe);
+
+    config.allow_downgrade = true;
+    run_update(&config, StageRecorder::default(), shutdown_rx).await?;
+    assert!(fs::read(output.join("trose.exe")).await? == old_exe);
+
+    fs::remove_dir_all(&dir).await?;
+    Ok(())
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn changed_mode_is_applied_without_downloading() -> anyhow::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let dir = test_dir("mode-change");
+    let _ = fs::remove_dir_all(&dir).await;
+    let archive_dir = dir.join("archive");
+    let output = dir.join("output");
+    fs::create_dir_all(&archive_dir).await?;
+
+    let exe = source_data(64 * 1024, 27);
+    let server = TestServer::start().await?;
+    let mut entry = add_archive(&server, &archive_dir, "trose.exe", &exe).await?;
+    let serve_mode = |entry: &mut RemoteManifestFileEntry, mode: u32| {
+        entry.mode = Some(mode);
+        let manifest = RemoteManifest {
+            version: 1,
+            files: vec![entry.clone()],
+            hash_algorithm: HASH_ALGORITHM.to_string(),
+            ..Default::default()
+        };
+        server.add_file("manifest.json", serde_json::to_vec(&manifest).unwrap());
+    };
+
+    let config = UpdateConfig {
+        skip_updater: true,
+        ..UpdateConfig::new(
+            server.base_url().clone(),
+            server.url("manifest.json"),
+            output.clone(),
+            default_clone_options(),
+        )
+    };
+    let (_shutdown_tx, shutdown_rx) = tokio::sync::watch::channel(false);
+    let file_mode = || -> anyhow::Result<u32> {
+        Ok(std::fs::metadata(output.join("trose.exe"))?
+            .permissions()
+            .mode()
+            & 0o7777)
+    };
+
+    serve_mode(&mut entry, 0o644);
+    run_update(&config, StageRecorder::default(), shutdown_rx.clone()).await?;
+    assert_eq!(file_mode()?, 0o644);
+
+    // Only the permission bits are applied, never setuid
+    let requests = server.request_count("trose.exe.cba");
+    serve_mode(&mut entry, 0o4755);
+    run_update(&config, StageRecorder::default(), shutdown_rx).await?;
+    assert_eq!(file_mode()?, 0o755);
+    assert_eq!(server.request_count("trose.exe.cba"), requests);
+
+    fs::remove_dir_all(&dir).await?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn empty_file_is_created_without_downloading() -> anyhow::Result<()> {
+    let dir = test_dir("empty-file");
+    let _ = fs::remove_dir_all(&dir).await;
+    let output = dir.join("output");
+    fs::create_dir_all(output.join("3DDATA")).await?;
+
+    // A stale file with contents is replaced by the empty one
+    fs::write(output.join("3DDATA/EMPTY.STB"), source_data(1024, 28)).await?;
+    let empty_path = dir.join("empty");
+    fs::write(&empty_path, []).await?;
+
+    // There is no archive on the server so any request for it fails
+    let server = TestServer::start().await?;
+    let manifest = RemoteManifest {
+        version: 1,
+        files: vec![RemoteManifestFileEntry {
+            path: "3DDATA/EMPTY.STB.cba".to_string(),
+            source_path: "3DDATA/EMPTY.STB".to_string(),
+            source_hash: hash_file(&empty_path).await?,
+            source_size: 0,
+            ..Default::default()
+        }],
+        hash_algorithm: HASH_ALGORITHM.to_string(),
+        ..Default::default()
+    };
+    server.add_file("manifest.json", serde_json::to_vec(&manifest)?);
+
+    let config = UpdateConfig {
+        skip_updater: true,
+        ..UpdateConfig::new(
+            server.base_url().clone(),
+            server.url("manifest.json"),
+            output.clone(),
+            default_clone_options(),
+        )
+    };
+    let (_shutdown_tx, shutdown_rx) = tokio::sync::watch::channel(false);
+
+    let outcome = run_update(&config, StageRecorder::default(), shutdown_rx).await?;
+    let summary = match outcome {
+        UpdateOutcome::ApplicationUpdated(_, summary) => summary,
+        outcome => panic!("Unexpected outcome {:?}", outcome),
+    };
+    assert_eq!(summary.files_updated, 1);
+    assert!(summary.failed_files.is_empty());
+    assert!(fs::read(output.join("3DDATA/EMPTY.STB")).await?.is_empty());
+    assert_eq!(server.request_count("3DDATA/EMPTY.STB.cba"), 0);
+
+    fs::remove_dir_all(&dir).await?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn checkpoint_of_another_pass_is_ignored() -> anyhow::Result<()> {
+    let dir = test_dir("stale-checkpoint");
+    let _ = fs::remove_dir_all(&dir).await;
+    let archive_dir = dir.join("archive");
+    let output = dir.join("output");
+    fs::create_dir_all(&archive_dir).await?;
+    fs::create_dir_all(&output).await?;
+
+    let exe = source_data(64 * 1024, 29);
+    let server = TestServer::start().await?;
+    let entry = add_archive(&server, &archive_dir, "trose.exe", &exe).await?;
+    let manifest = RemoteManifest {
+        version: 1,
+        files: vec![entry.clone()],
+        hash_algorithm: HASH_ALGORITHM.to_string(),
+        ..Default::default()
+    };
+    server.add_file("manifest.json", serde_json::to_vec(&manifest)?);
+
+    // A checkpoint left behind by a pass over another manifest claims the
+    // corrupt local file is complete
+    fs::write(output.join("trose.exe"), source_data(64 * 1024, 30)).await?;
+    let checkpoint_dir = output
+        .join("updater")
+        .join(server.base_url().host_str().unwrap_or("default"));
+    fs::create_dir_all(&checkpoint_dir).await?;
+    let stale_entry = serde_json::json!({
+        "path": entry.source_path,
+        "hash": entry.source_hash,
+        "size": entry.source_size,
+    });
+    fs::write(
+        checkpoint_dir.join("checkpoint.jsonl"),
+        format!("0000\n{}\n", stale_entry),
+    )
+    .await?;
+
+    let config = UpdateConfig {
+        skip_updater: true,
+        verify: true,
+        ..UpdateConfig::new(
+            server.base_url().clone(),
+            server.url("manifest.json"),
+            output.clone(),
+            default_clone_options(),
+        )
+    };
+    let (_shutdown_tx, shutdown_rx) = tokio::sync::watch::channel(false);
+    run_update(&config, StageRecorder::default(), shutdown_rx).await?;
+    assert!(fs::read(output.join("trose.exe")).await? == exe);
+    assert!(!checkpoint_dir.join("checkpoint.jsonl").exists());
+
+    fs::remove_dir_all(&dir).await?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn other_hash_algorithm_is_not_verified_after_cloning() -> anyhow::Result<()> {
+    let dir = test_dir("other-hash-algorithm");
+    let _ = fs::remove_dir_all(&dir).await;
+    let archive_dir = dir.join("archive");
+    let output = dir.join("output");
+    fs::create_dir_all(&archive_dir).await?;
+
+    // The manifest was hashed with an algorithm this updater doesn't know so
+    // its hashes never match ours
+    let exe = source_data(64 * 1024, 31);
+    let server = TestServer::start().await?;
+    let manifest = RemoteManifest {
+        version: 1,
+        files: vec![RemoteManifestFileEntry {
+            source_hash: vec![0xab; 32],
+            ..add_archive(&server, &archive_dir, "trose.exe", &exe).await?
+        }],
+        hash_algorithm: "sha256".to_string(),
+        ..Default::default()
+    };
+    server.add_file("manifest.json", serde_json::to_vec(&manifest)?);
+
+    let config = UpdateConfig {
+        skip_updater: true,
+        ..UpdateConfig::new(
+            server.base_url().clone(),
+            server.url("manifest.json"),
+            output.clone(),
+            default_clone_options(),
+        )
+    };
+    let (_shutdown_tx, shutdown_rx) = tokio::sync::watch::channel(false);
+
+    let outcome = run_update(&config, StageRecorder::default(), shutdown_rx).await?;
+    let summary = match outcome {
+        UpdateOutcome::ApplicationUpdated(_, summary) => summary,
+        outcome => panic!("Unexpected outcome {:?}", outcome),
+    };
+    assert_eq!(summary.files_updated, 1);
+    assert!(summary.failed_files.is_empty());
+    assert!(fs::read(output.join("trose.exe")).await? == exe);
+
+    fs::remove_dir_all(&dir).await?;
+    Ok(())
+}
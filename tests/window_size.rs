@@ -0,0 +1,54 @@
+mod common;
+
+use common::test_dir;
+use rose_update::WindowSize;
+
+#[test]
+fn window_size_is_remembered() -> anyhow::Result<()> {
+    let dir = test_dir("window-size");
+    let _ = std::fs::remove_dir_all(&dir);
+    let path = dir.join("updater").join("window.json");
+
+    assert_eq!(WindowSize::load(&path), None);
+
+    let size = WindowSize {
+        width: 1280,
+        height: 960,
+    };
+    size.save(&path)?;
+    assert_eq!(WindowSize::load(&path), Some(size));
+
+    std::fs::write(&path, "not json")?;
+    assert_eq!(WindowSize::load(&path), None);
+
+    std::fs::remove_dir_all(&dir)?;
+    Ok(())
+}
+
+#[test]
+fn window_size_is_clamped() {
+    let tiny = WindowSize {
+        width: 100,
+        height: 2000,
+    };
+    assert_eq!(
+        tiny.clamped(),
+        WindowSize {
+            width: 780,
+            height: 2000
+        }
+    );
+
+    let huge = WindowSize {
+        width: 100_000,
+        height: -1,
+    };
+    assert_eq!(
+        huge.clamped(),
+        WindowSize {
+            width: 7680,
+            height: 630
+        }
+    );
+    assert_eq!(WindowSize::default(), WindowSize::MIN);
+}